@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::merge::merge_maps;
+use crate::{Item, Path};
+
+const ANCHOR: &[u8] = b"@anchor";
+const REF: &[u8] = b"@ref";
+const EXTENDS: &[u8] = b"@extends";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AnchorError {
+  path: Path,
+  reason: String,
+}
+
+impl Error for AnchorError {}
+
+impl AnchorError {
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for AnchorError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "AnchorError: {}: {}", self.path, self.reason)
+  }
+}
+
+/// Expand anchors and references to them, like YAML `&`, `*` and `<<`.
+///
+/// - A map entry `@anchor: name` names the map, `(@anchor name item)` names
+///   any item and is replaced by it
+/// - `(@ref name)` is replaced by the named item
+/// - A map entry `@extends: name` or `@extends: (name ...)` merges the named
+///   maps into the map, its other entries take precedence
+///
+/// Anchors can be referenced before they are defined, their names are
+/// unique in the document.
+///
+/// ```
+/// # use axp::{parse, resolve_anchors};
+/// let item = parse(b"
+///   defaults: (@anchor: base image: rust os: linux)
+///   jobs: (
+///     test: (@extends: base run: test)
+///     mac: (@extends: base os: macos run: test)
+///     also: (@ref base)
+///   )
+/// ").unwrap();
+/// let expected = parse(b"
+///   defaults: (image: rust os: linux)
+///   jobs: (
+///     test: (image: rust os: linux run: test)
+///     mac: (image: rust os: macos run: test)
+///     also: (image: rust os: linux)
+///   )
+/// ").unwrap();
+/// assert_eq!(resolve_anchors(&item), Ok(expected));
+/// ```
+pub fn resolve_anchors(item: &Item) -> Result<Item, AnchorError> {
+  let mut anchors = Anchors {
+    anchors: HashMap::new(),
+    stack: Vec::new(),
+    done: HashMap::new(),
+  };
+  anchors.collect(&Path::root(), item)?;
+  anchors.item(&Path::root(), item)
+}
+
+fn error(path: &Path, reason: impl Into<String>) -> AnchorError {
+  AnchorError { path: path.clone(), reason: reason.into() }
+}
+
+fn text(name: &[u8]) -> String {
+  String::from_utf8_lossy(name).into_owned()
+}
+
+// The name of the anchor of a map
+fn map_anchor<'i>(
+  path: &Path,
+  map: &'i crate::Map,
+) -> Result<Option<&'i [u8]>, AnchorError> {
+  match map.get(&Item::new_atom(ANCHOR)) {
+    Some(Item::Atom(name)) => Ok(Some(name.as_bytes())),
+    Some(_) => Err(error(path, "anchor name must be an atom")),
+    None => Ok(None),
+  }
+}
+
+struct Anchors<'i> {
+  // the definitions by name with their paths
+  anchors: HashMap<&'i [u8], (Path, &'i Item)>,
+  // the anchors being expanded, a reference to them is a cycle
+  stack: Vec<&'i [u8]>,
+  done: HashMap<&'i [u8], Item>,
+}
+
+impl<'i> Anchors<'i> {
+  fn collect(
+    &mut self,
+    path: &Path,
+    item: &'i Item,
+  ) -> Result<(), AnchorError> {
+    let name = match item {
+      Item::Atom(_) => None,
+      Item::List(list) => match list.0.as_slice() {
+        [Item::Atom(a), Item::Atom(name), _] if a.as_bytes() == ANCHOR => {
+          Some(name.as_bytes())
+        }
+        [Item::Atom(a), ..] if a.as_bytes() == ANCHOR => {
+          Err(error(path, "anchor needs a name and an item"))?
+        }
+        _ => None,
+      },
+      Item::Map(map) => map_anchor(path, map)?,
+    };
+
+    if let Some(name) = name {
+      if let Some((other, _)) = self.anchors.get(name) {
+        let reason = format!("anchor {} is already at {other}", text(name));
+        return Err(error(path, reason));
+      }
+      self.anchors.insert(name, (path.clone(), item));
+    }
+
+    match item {
+      Item::Atom(_) => Ok(()),
+      Item::List(list) => list
+        .iter()
+        .enumerate()
+        .try_for_each(|(i, item)| self.collect(&path.join_index(i), item)),
+      Item::Map(map) => map.iter().try_for_each(|(key, value)| {
+        self.collect(&path.join(key.clone()), value)
+      }),
+    }
+  }
+
+  fn item(&mut self, path: &Path, item: &'i Item) -> Result<Item, AnchorError> {
+    match item {
+      Item::Atom(_) => Ok(item.clone()),
+      Item::List(list) => match list.0.as_slice() {
+        [Item::Atom(a), Item::Atom(name)] if a.as_bytes() == REF => {
+          self.anchor(path, name.as_bytes())
+        }
+        [Item::Atom(a), ..] if a.as_bytes() == REF => {
+          Err(error(path, "ref needs one name"))
+        }
+        [Item::Atom(a), Item::Atom(name), _] if a.as_bytes() == ANCHOR => {
+          self.anchor(path, name.as_bytes())
+        }
+        _ => {
+          let items = list.iter().enumerate();
+          let items =
+            items.map(|(i, item)| self.item(&path.join_index(i), item));
+          items.collect::<Result<Vec<_>, _>>().map(Item::new_list)
+        }
+      },
+      Item::Map(map) => match map_anchor(path, map)? {
+        Some(name) => self.anchor(path, name),
+        None => self.map(path, map),
+      },
+    }
+  }
+
+  // The expanded item of an anchor referenced at the path
+  fn anchor(&mut self, path: &Path, name: &[u8]) -> Result<Item, AnchorError> {
+    let Some((&name, (at, item))) = self.anchors.get_key_value(name) else {
+      return Err(error(path, format!("unknown anchor {}", text(name))));
+    };
+    let (at, item) = (at.clone(), *item);
+
+    if let Some(item) = self.done.get(name) {
+      return Ok(item.clone());
+    }
+    if let Some(start) = self.stack.iter().position(|n| *n == name) {
+      let cycle = self.stack[start..].iter().chain([&name]);
+      let cycle = cycle.map(|name| text(name)).collect::<Vec<_>>();
+      return Err(error(path, format!("anchor cycle {}", cycle.join(" -> "))));
+    }
+
+    self.stack.push(name);
+    let expanded = match item {
+      Item::List(list) => self.item(&at.join_index(2), &list.0[2]),
+      Item::Map(map) => self.map(&at, map),
+      Item::Atom(_) => unreachable!("atoms have no anchors"),
+    };
+    self.stack.pop();
+
+    let expanded = expanded?;
+    self.done.insert(name, expanded.clone());
+    Ok(expanded)
+  }
+
+  fn map(
+    &mut self,
+    path: &Path,
+    map: &'i crate::Map,
+  ) -> Result<Item, AnchorError> {
+    let (anchor, extends) = (Item::new_atom(ANCHOR), Item::new_atom(EXTENDS));
+    let (mut layers, mut bases) = (Vec::new(), Vec::new());
+
+    for (key, value) in map.iter().filter(|(key, _)| *key == extends) {
+      let path = path.join(key.clone());
+      let names = match value {
+        Item::List(list) => list.iter().collect(),
+        _ => vec![value],
+      };
+      for name in names {
+        let Item::Atom(name) = name else {
+          return Err(error(&path, "extends names must be atoms"));
+        };
+        layers.push(self.anchor(&path, name.as_bytes())?);
+        bases.push((path.clone(), name.as_bytes()));
+      }
+    }
+
+    let entries =
+      map.iter().filter(|(key, _)| *key != anchor && *key != extends);
+    let entries = entries.map(|(key, value)| {
+      Ok((key.clone(), self.item(&path.join(key.clone()), value)?))
+    });
+    let entries = entries.collect::<Result<Vec<_>, _>>()?;
+    if !entries.is_empty() {
+      layers.push(Item::new_map(entries));
+    }
+    merge_maps(&layers).map_err(|index| {
+      let (path, name) = &bases[index];
+      error(path, format!("{} is not a map", text(name)))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::resolve_anchors;
+  use crate::{canonical, parse};
+
+  fn resolve(input: &str) -> Result<String, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    match resolve_anchors(&item) {
+      Ok(item) => Ok(String::from_utf8(canonical(&item)).unwrap()),
+      Err(err) => Err(err.to_string()),
+    }
+  }
+
+  #[test]
+  fn anchors() {
+    assert_eq!(
+      resolve("a: (@ref l) b: (@anchor l (1 2)) c: (x (@ref l))").unwrap(),
+      "a: (1 2) b: (1 2) c: (x (1 2))"
+    );
+    assert_eq!(
+      resolve(
+        "base: (@anchor: b x: 1 y: (p: 1))
+         more: (@anchor: m @extends: b y: (q: 2))
+         job: (@extends: (m b) z: 3)"
+      )
+      .unwrap(),
+      "base: (x: 1 y: (p: 1)) more: (x: 1 y: (p: 1 q: 2)) \
+       job: (x: 1 y: (p: 1 q: 2) z: 3)"
+    );
+    assert_eq!(resolve("a: 1 b: (1 2)").unwrap(), "a: 1 b: (1 2)");
+  }
+
+  #[test]
+  fn errors() {
+    let err = |input| resolve(input).unwrap_err();
+    assert_eq!(err("a: (@ref x)"), "AnchorError: a: unknown anchor x");
+    assert_eq!(
+      err("a: (@anchor: x) b: (@anchor x 1)"),
+      "AnchorError: b: anchor x is already at a"
+    );
+    assert_eq!(
+      err("a: (@anchor: x b: (@ref y)) c: (@anchor y (@ref x))"),
+      "AnchorError: c/2: anchor cycle x -> y -> x"
+    );
+    assert_eq!(
+      err("a: (@anchor: x b: (@ref x))"),
+      "AnchorError: a/b: anchor cycle x -> x"
+    );
+    assert_eq!(
+      err("a: (@anchor l (1)) b: (@extends: l)"),
+      "AnchorError: b/@extends: l is not a map"
+    );
+    assert_eq!(err("a: (@ref)"), "AnchorError: a: ref needs one name");
+    assert_eq!(
+      err("a: (@anchor x)"),
+      "AnchorError: a: anchor needs a name and an item"
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
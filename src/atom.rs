@@ -1,27 +1,75 @@
 use crate::PrettyUtf8;
 use std::fmt;
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
-pub struct Atom(pub(crate) Vec<u8>);
+/// An atom is a sequence of bytes, ordered bytewise.
+///
+/// An atom can have a tag as a hint for its type. The tag is the guard of a
+/// guarded string, like `date` in `#(date)"2023-10-14#(date)"`. The tag counts
+/// for equality and ordering after the bytes.
+///
+/// ```
+/// # use axp::{canonical, parse, Atom, Item};
+/// let item = parse(br##"day: #(date)"2023-10-14#(date)""##).unwrap();
+/// let Some(Item::Atom(day)) = item.get(&"day".parse().unwrap()) else {
+///   panic!()
+/// };
+/// assert_eq!(day.tag(), Some(&b"date"[..]));
+/// assert_eq!(day, &Atom::tagged(b"date", b"2023-10-14").unwrap());
+/// assert_eq!(canonical(&item), br##"day: #(date)"2023-10-14#(date)""##);
+/// ```
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Atom(pub(crate) Vec<u8>, pub(crate) Option<Vec<u8>>);
 
 impl fmt::Display for Atom {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    f.write_str(&self.0.pretty_short(f.precision().unwrap_or(0)))
+    f.write_str(&self.format(f.precision().unwrap_or(0)))
   }
 }
 
 impl Atom {
   pub fn new(atom: &[u8]) -> Self {
-    Atom(atom.to_vec())
+    Atom(atom.to_vec(), None)
+  }
+
+  /// A tagged atom, `None` if the tag is not valid: one to eight bytes
+  /// without white space, `#`, `(`, `)`, `\` and `"`
+  pub fn tagged(tag: &[u8], atom: &[u8]) -> Option<Self> {
+    is_tag(tag).then(|| Atom(atom.to_vec(), Some(tag.to_vec())))
+  }
+
+  pub fn tag(&self) -> Option<&[u8]> {
+    self.1.as_deref()
   }
 
   pub fn format(&self, width: usize) -> String {
-    self.0.pretty_short(width)
+    match &self.1 {
+      Some(tag) => format!("#({}){}", tag.pretty(), self.0.pretty_short(width)),
+      None => self.0.pretty_short(width),
+    }
   }
 
   pub fn is_empty(&self) -> bool {
     self.0.is_empty()
   }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+pub(crate) fn is_tag(tag: &[u8]) -> bool {
+  let bad = |b: &u8| b"#() \n\r\t\\\"".contains(b);
+  (1..=8).contains(&tag.len()) && !tag.iter().any(bad)
+}
+
+// The text of a float, as in TOML `1.0` stays a float
+pub(crate) fn float_text<F: Copy + fmt::Debug + Into<f64>>(float: F) -> String {
+  match float.into() {
+    f if f.is_nan() => "nan".to_string(),
+    f64::INFINITY => "inf".to_string(),
+    f64::NEG_INFINITY => "-inf".to_string(),
+    _ => format!("{float:?}"),
+  }
 }
 
 // Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,585 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::{Atom, Item};
+
+const MAGIC: &[u8; 4] = b"axpb";
+const VERSION: u8 = 1;
+const TABLE: u8 = 0x01;
+
+const ATOM: u8 = 0x00;
+const TAGGED: u8 = 0x01;
+const LIST: u8 = 0x02;
+const MAP: u8 = 0x03;
+const REF: u8 = 0x04;
+
+/// Options for [`to_binary()`] and [`write_binary()`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BinaryOptions {
+  /// Write atoms which occur more than once into a table and refer to them
+  pub dedup: bool,
+}
+
+/// Limits for [`from_binary()`] and [`read_binary()`], input beyond them is
+/// rejected before it is allocated
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BinaryLimits {
+  /// The nesting of lists and maps, 128 by default
+  pub max_depth: usize,
+  /// The bytes of an atom or a tag, 16 MiB by default
+  pub max_atom: usize,
+  /// The atoms, lists, maps and references, 16 Mi by default
+  pub max_items: usize,
+}
+
+impl Default for BinaryLimits {
+  fn default() -> Self {
+    BinaryLimits { max_depth: 128, max_atom: 1 << 24, max_items: 1 << 24 }
+  }
+}
+
+#[derive(Debug)]
+pub enum BinaryError {
+  Io(io::Error),
+  /// The input is not valid at the byte offset
+  Invalid {
+    offset: usize,
+    reason: String,
+  },
+}
+
+impl Error for BinaryError {}
+
+impl fmt::Display for BinaryError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("BinaryError: ")?;
+    match self {
+      BinaryError::Io(error) => write!(f, "{error}"),
+      BinaryError::Invalid { offset, reason } => {
+        write!(f, "at byte {offset}: {reason}")
+      }
+    }
+  }
+}
+
+impl From<io::Error> for BinaryError {
+  fn from(error: io::Error) -> Self {
+    BinaryError::Io(error)
+  }
+}
+
+/// Encode an item in the binary form.
+///
+/// The binary form is faster to read than the text and it keeps every item,
+/// also empty maps: decoding gives an equal item.
+///
+/// # Format
+///
+/// Numbers are unsigned LEB128: seven bits per byte, least significant
+/// first, the high bit set on all bytes but the last, at most ten bytes.
+///
+/// A document is
+///
+/// - the magic `61 78 70 62` (`axpb`)
+/// - the version `01`
+/// - the flags, bit `01` is set if there is a table, other bits are zero
+/// - with a table: the number of atoms and the atoms, `00` or `01` below
+/// - the item
+///
+/// An item starts with its kind:
+///
+/// - `00` atom: the length and the bytes
+/// - `01` tagged atom: the length and the bytes of the tag, then of the atom
+/// - `02` list: the number of items and the items
+/// - `03` map: the number of entries and for each the key and the value
+/// - `04` reference: the index of an atom in the table
+///
+/// ```
+/// # use axp::{from_binary, parse, to_binary, BinaryOptions};
+/// let item = parse(b"name: axp tags: (name)").unwrap();
+/// let bytes = to_binary(&item, &BinaryOptions::default());
+/// assert_eq!(&bytes[..6], b"axpb\x01\x00");
+/// assert_eq!(bytes[6..9], [0x03, 2, 0x00]);
+/// assert_eq!(from_binary(&bytes, &Default::default()).unwrap(), item);
+///
+/// let dedup = to_binary(&item, &BinaryOptions { dedup: true });
+/// assert_eq!(dedup[5..12], [0x01, 1, 0x00, 4, b'n', b'a', b'm']);
+/// ```
+pub fn to_binary(item: &Item, options: &BinaryOptions) -> Vec<u8> {
+  let mut out = Vec::new();
+  write_binary(&mut out, item, options).expect("writing to a vec");
+  out
+}
+
+/// Write an item in the binary form, see [`to_binary()`]. Writes the item
+/// as it goes, in many small writes, a buffered writer is faster.
+pub fn write_binary<W: Write>(
+  mut writer: W,
+  item: &Item,
+  options: &BinaryOptions,
+) -> io::Result<()> {
+  let table = if options.dedup {
+    table(item)
+  } else {
+    Vec::new()
+  };
+
+  writer.write_all(MAGIC)?;
+  writer.write_all(&[VERSION])?;
+  if options.dedup {
+    writer.write_all(&[TABLE])?;
+    write_number(table.len(), &mut writer)?;
+    for atom in &table {
+      write_atom(atom, &mut writer)?;
+    }
+  } else {
+    writer.write_all(&[0])?;
+  }
+
+  let index = table.iter().enumerate().map(|(i, atom)| (*atom, i)).collect();
+  write_item(item, &index, &mut writer)
+}
+
+// The atoms which occur more than once and are longer than a reference, in
+// the order of their first occurrence
+fn table(item: &Item) -> Vec<&Atom> {
+  fn count<'i>(
+    item: &'i Item,
+    counts: &mut Vec<(&'i Atom, usize)>,
+    index: &mut HashMap<&'i Atom, usize>,
+  ) {
+    match item {
+      Item::Atom(atom) => match index.get(atom) {
+        Some(&i) => counts[i].1 += 1,
+        None => {
+          index.insert(atom, counts.len());
+          counts.push((atom, 1));
+        }
+      },
+      Item::List(list) => {
+        list.iter().for_each(|item| count(item, counts, index))
+      }
+      Item::Map(map) => map.iter().for_each(|(key, value)| {
+        count(key, counts, index);
+        count(value, counts, index);
+      }),
+    }
+  }
+
+  let (mut counts, mut index) = (Vec::new(), HashMap::new());
+  count(item, &mut counts, &mut index);
+  let atoms = counts.into_iter().filter(|(atom, count)| {
+    *count > 1 && atom.0.len() + atom.1.as_ref().map_or(0, Vec::len) > 1
+  });
+  atoms.map(|(atom, _)| atom).collect()
+}
+
+fn write_number(mut number: usize, out: &mut impl Write) -> io::Result<()> {
+  let (mut bytes, mut len) = ([0; 10], 0);
+  while number >= 0x80 {
+    bytes[len] = number as u8 | 0x80;
+    (number, len) = (number >> 7, len + 1);
+  }
+  bytes[len] = number as u8;
+  out.write_all(&bytes[..=len])
+}
+
+fn write_bytes(bytes: &[u8], out: &mut impl Write) -> io::Result<()> {
+  write_number(bytes.len(), out)?;
+  out.write_all(bytes)
+}
+
+fn write_atom(atom: &Atom, out: &mut impl Write) -> io::Result<()> {
+  match &atom.1 {
+    Some(tag) => {
+      out.write_all(&[TAGGED])?;
+      write_bytes(tag, out)?;
+    }
+    None => out.write_all(&[ATOM])?,
+  }
+  write_bytes(&atom.0, out)
+}
+
+fn write_item(
+  item: &Item,
+  index: &HashMap<&Atom, usize>,
+  out: &mut impl Write,
+) -> io::Result<()> {
+  match item {
+    Item::Atom(atom) => match index.get(atom) {
+      Some(&i) => {
+        out.write_all(&[REF])?;
+        write_number(i, out)
+      }
+      None => write_atom(atom, out),
+    },
+    Item::List(list) => {
+      out.write_all(&[LIST])?;
+      write_number(list.len(), out)?;
+      list.iter().try_for_each(|item| write_item(item, index, out))
+    }
+    Item::Map(map) => {
+      out.write_all(&[MAP])?;
+      write_number(map.len(), out)?;
+      for (key, value) in map.iter() {
+        write_item(key, index, out)?;
+        write_item(value, index, out)?;
+      }
+      Ok(())
+    }
+  }
+}
+
+/// Decode an item from the binary form, see [`to_binary()`]. The bytes
+/// must be exactly one document.
+pub fn from_binary(
+  bytes: &[u8],
+  limits: &BinaryLimits,
+) -> Result<Item, BinaryError> {
+  let mut reader = bytes;
+  let mut decoder = Decoder::new(&mut reader, limits);
+  let item = decoder.document()?;
+  if decoder.offset < bytes.len() {
+    Err(decoder.invalid("bytes after the document"))
+  } else {
+    Ok(item)
+  }
+}
+
+/// Read an item in the binary form, see [`to_binary()`]. Reads exactly one
+/// document so that documents can follow each other in a stream, a buffered
+/// reader is faster.
+pub fn read_binary<R: Read>(
+  reader: R,
+  limits: &BinaryLimits,
+) -> Result<Item, BinaryError> {
+  Decoder::new(reader, limits).document()
+}
+
+struct Decoder<'l, R> {
+  reader: R,
+  limits: &'l BinaryLimits,
+  offset: usize,
+  items: usize,
+  table: Vec<Atom>,
+}
+
+impl<'l, R: Read> Decoder<'l, R> {
+  fn new(reader: R, limits: &'l BinaryLimits) -> Self {
+    Decoder { reader, limits, offset: 0, items: 0, table: Vec::new() }
+  }
+
+  fn invalid(&self, reason: impl Into<String>) -> BinaryError {
+    BinaryError::Invalid { offset: self.offset, reason: reason.into() }
+  }
+
+  fn byte(&mut self) -> Result<u8, BinaryError> {
+    let mut byte = [0];
+    match self.reader.read_exact(&mut byte) {
+      Ok(()) => {
+        self.offset += 1;
+        Ok(byte[0])
+      }
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+        Err(self.invalid("unexpected end"))
+      }
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  fn number(&mut self) -> Result<usize, BinaryError> {
+    let mut number = 0u64;
+    for shift in (0..64).step_by(7) {
+      let byte = self.byte()?;
+      let bits = u64::from(byte & 0x7f);
+      if shift == 63 && bits > 1 {
+        return Err(self.invalid("number too large"));
+      }
+      number |= bits << shift;
+      if byte & 0x80 == 0 {
+        return usize::try_from(number)
+          .map_err(|_| self.invalid("number too large"));
+      }
+    }
+    Err(self.invalid("number too large"))
+  }
+
+  fn count(&mut self) -> Result<(), BinaryError> {
+    self.items += 1;
+    if self.items > self.limits.max_items {
+      Err(self.invalid("too many items"))
+    } else {
+      Ok(())
+    }
+  }
+
+  fn bytes(&mut self) -> Result<Vec<u8>, BinaryError> {
+    let len = self.number()?;
+    if len > self.limits.max_atom {
+      return Err(self.invalid(format!("atom of {len} bytes is too long")));
+    }
+    // not allocated up front, the length could be a lie
+    let mut bytes = Vec::new();
+    (&mut self.reader).take(len as u64).read_to_end(&mut bytes)?;
+    self.offset += bytes.len();
+    if bytes.len() < len {
+      Err(self.invalid("unexpected end"))
+    } else {
+      Ok(bytes)
+    }
+  }
+
+  fn document(&mut self) -> Result<Item, BinaryError> {
+    for &expected in MAGIC {
+      if self.byte()? != expected {
+        return Err(self.invalid("not axp binary"));
+      }
+    }
+    match self.byte()? {
+      VERSION => (),
+      version => {
+        return Err(self.invalid(format!("unknown version {version}")))
+      }
+    }
+    match self.byte()? {
+      0 => (),
+      TABLE => {
+        let len = self.number()?;
+        for _ in 0..len {
+          self.count()?;
+          match self.byte()? {
+            kind @ (ATOM | TAGGED) => {
+              let atom = self.atom(kind)?;
+              self.table.push(atom);
+            }
+            _ => return Err(self.invalid("the table can only have atoms")),
+          }
+        }
+      }
+      flags => return Err(self.invalid(format!("unknown flags {flags:#04x}"))),
+    }
+    self.item(0)
+  }
+
+  fn atom(&mut self, kind: u8) -> Result<Atom, BinaryError> {
+    let tag = match kind {
+      TAGGED => Some(self.bytes()?),
+      _ => None,
+    };
+    if tag.as_ref().is_some_and(|tag| !crate::atom::is_tag(tag)) {
+      return Err(self.invalid("bad tag"));
+    }
+    Ok(Atom(self.bytes()?, tag))
+  }
+
+  fn item(&mut self, depth: usize) -> Result<Item, BinaryError> {
+    self.count()?;
+    let kind = self.byte()?;
+    if matches!(kind, LIST | MAP) && depth >= self.limits.max_depth {
+      return Err(self.invalid("nested too deeply"));
+    }
+    match kind {
+      ATOM | TAGGED => self.atom(kind).map(Item::Atom),
+      LIST => {
+        let len = self.number()?;
+        let mut items = Vec::new();
+        for _ in 0..len {
+          items.push(self.item(depth + 1)?);
+        }
+        Ok(Item::new_list(items))
+      }
+      MAP => {
+        let len = self.number()?;
+        let mut entries = Vec::new();
+        for _ in 0..len {
+          entries.push((self.item(depth + 1)?, self.item(depth + 1)?));
+        }
+        Ok(Item::new_map(entries))
+      }
+      REF => {
+        let index = self.number()?;
+        match self.table.get(index) {
+          Some(atom) => Ok(Item::Atom(atom.clone())),
+          None => Err(self.invalid(format!("no atom {index} in the table"))),
+        }
+      }
+      kind => Err(self.invalid(format!("unknown kind {kind:#04x}"))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    from_binary, read_binary, to_binary, write_binary, BinaryLimits,
+    BinaryOptions,
+  };
+  use crate::{canonical, parse, Atom, Item};
+
+  fn documents() -> Vec<Item> {
+    let texts = [
+      &b""[..],
+      b"a",
+      b"a: 1 b: (c d (e: f)) (k l): ()",
+      br##"#(date)"2023-10-14#(date)" "" "\x00\xff" (((())))"##,
+      b"x: (name: a) y: (name: a) z: (name: b)",
+    ];
+    let mut documents = texts.map(|text| parse(text).unwrap()).to_vec();
+    documents.push(Item::new_map([]));
+    documents.push(Item::new_atom(&[b'x'; 300]));
+    documents
+  }
+
+  #[test]
+  fn roundtrip() {
+    for item in documents() {
+      for dedup in [false, true] {
+        let bytes = to_binary(&item, &BinaryOptions { dedup });
+        let decoded = from_binary(&bytes, &BinaryLimits::default()).unwrap();
+        assert_eq!(decoded, item);
+        assert_eq!(canonical(&decoded), canonical(&item));
+      }
+    }
+
+    let item = parse(b"a: (name: value) b: (name: value) c: v").unwrap();
+    let plain = to_binary(&item, &BinaryOptions { dedup: false });
+    let dedup = to_binary(&item, &BinaryOptions { dedup: true });
+    assert!(dedup.len() < plain.len());
+  }
+
+  #[test]
+  fn stream() {
+    let mut stream = Vec::new();
+    for item in documents() {
+      write_binary(&mut stream, &item, &BinaryOptions::default()).unwrap();
+    }
+    let mut reader = &stream[..];
+    for item in documents() {
+      assert_eq!(
+        read_binary(&mut reader, &BinaryLimits::default()).unwrap(),
+        item
+      );
+    }
+    assert!(reader.is_empty());
+
+    // the document is written as it goes, not as a whole
+    struct Small(Vec<u8>);
+    impl std::io::Write for Small {
+      fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        assert!(buf.len() <= 16, "a write of {} bytes", buf.len());
+        self.0.write(buf)
+      }
+      fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+      }
+    }
+    let item = Item::new_list((0..1000).map(|i| Item::new_atom(&[i as u8])));
+    let mut small = Small(Vec::new());
+    write_binary(&mut small, &item, &BinaryOptions::default()).unwrap();
+    assert_eq!(small.0, to_binary(&item, &BinaryOptions::default()));
+  }
+
+  #[test]
+  fn errors() {
+    let err = |bytes: &[u8]| {
+      from_binary(bytes, &BinaryLimits::default()).unwrap_err().to_string()
+    };
+    assert_eq!(err(b"axp"), "BinaryError: at byte 3: unexpected end");
+    assert_eq!(err(b"json\x01\x00"), "BinaryError: at byte 1: not axp binary");
+    assert_eq!(
+      err(b"axpb\x02\x00"),
+      "BinaryError: at byte 5: unknown version 2"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x02"),
+      "BinaryError: at byte 6: unknown flags 0x02"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x00\x07"),
+      "BinaryError: at byte 7: unknown kind 0x07"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x00\x00\x05ab"),
+      "BinaryError: at byte 10: unexpected end"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x00\x00\x00\x00"),
+      "BinaryError: at byte 8: bytes after the document"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x00\x04\x00"),
+      "BinaryError: at byte 8: no atom 0 in the table"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x01\x01\x02\x00"),
+      "BinaryError: at byte 8: the table can only have atoms"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x00\x01\x01#\x00"),
+      "BinaryError: at byte 9: bad tag"
+    );
+    assert_eq!(
+      err(b"axpb\x01\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff\xff\x7f"),
+      "BinaryError: at byte 17: number too large"
+    );
+  }
+
+  #[test]
+  fn limits() {
+    let item = parse(b"a: ((((b)))) c: abcdef").unwrap();
+    let bytes = to_binary(&item, &BinaryOptions::default());
+    let limits = |max_depth, max_atom, max_items| BinaryLimits {
+      max_depth,
+      max_atom,
+      max_items,
+    };
+    assert!(from_binary(&bytes, &limits(5, 6, 9)).is_ok());
+    let err = |limits| from_binary(&bytes, &limits).unwrap_err().to_string();
+    assert!(err(limits(4, 6, 9)).ends_with("nested too deeply"));
+    assert!(err(limits(5, 5, 9)).ends_with("atom of 6 bytes is too long"));
+    assert!(err(limits(5, 6, 8)).ends_with("too many items"));
+
+    // a huge length is rejected, not allocated
+    let err = from_binary(
+      b"axpb\x01\x00\x02\xff\xff\xff\xff\x0f",
+      &BinaryLimits::default(),
+    );
+    assert!(err.unwrap_err().to_string().ends_with("unexpected end"));
+  }
+
+  #[test]
+  fn fuzz() {
+    // a small xorshift for reproducible mutations
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = move || {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      state
+    };
+
+    let limits =
+      BinaryLimits { max_depth: 16, max_atom: 1 << 10, max_items: 1 << 10 };
+    for item in documents() {
+      for dedup in [false, true] {
+        let bytes = to_binary(&item, &BinaryOptions { dedup });
+        for len in 0..bytes.len() {
+          assert!(from_binary(&bytes[..len], &limits).is_err());
+        }
+        for _ in 0..200 {
+          let mut mutated = bytes.clone();
+          let at = random() as usize % mutated.len();
+          mutated[at] = random() as u8;
+          let _ = from_binary(&mutated, &limits);
+        }
+      }
+    }
+    let tagged = Item::Atom(Atom::tagged(b"t", b"").unwrap());
+    let bytes = to_binary(&tagged, &BinaryOptions::default());
+    assert_eq!(from_binary(&bytes, &limits).unwrap(), tagged);
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
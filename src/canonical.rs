@@ -0,0 +1,236 @@
+use crate::{Atom, Item};
+
+/// Options for [`canonical_with()`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CanonicalOptions {
+  /// Sort the entries of all maps, see [`Item::sorted()`]
+  pub sort_keys: bool,
+}
+
+/// Serialize an item to its canonical form.
+///
+/// The canonical form has no comments, a single space between items and
+/// between entries, and a single space after the colon of an entry. Atoms are
+/// written bare if possible, otherwise as quoted strings with the shortest
+/// escapes. Lists and maps are written as the document, without the
+/// parentheses of the top level.
+///
+/// The canonical form parses back to an equal item. There are two
+/// exceptions: an empty map can't be expressed in axp and is written as
+/// `()`, this parses back as an empty list. And a document is a list or a
+/// map, so an atom at the top level is written as itself and parses back as
+/// a list of that atom.
+///
+/// ```
+/// # use axp::{canonical, parse, Item};
+/// let item = parse(b"  name:\n  \"John Doe\"   # a comment\n age: 42").unwrap();
+/// assert_eq!(canonical(&item), br#"name: "John Doe" age: 42"#);
+/// assert_eq!(parse(&canonical(&item)), Ok(item));
+///
+/// let atom = Item::new_atom(b"a");
+/// assert_eq!(canonical(&atom), b"a");
+/// assert_eq!(parse(&canonical(&atom)), Ok(Item::new_list([atom])));
+/// ```
+pub fn canonical(item: &Item) -> Vec<u8> {
+  canonical_with(item, &CanonicalOptions::default())
+}
+
+/// Serialize an item to its canonical form with options.
+///
+/// ```
+/// # use axp::{canonical_with, parse, CanonicalOptions};
+/// let item = parse(b"b: 2 a: (y: 1 x: 0)").unwrap();
+/// let options = CanonicalOptions { sort_keys: true };
+/// assert_eq!(canonical_with(&item, &options), b"a: (x: 0 y: 1) b: 2");
+/// ```
+pub fn canonical_with(item: &Item, options: &CanonicalOptions) -> Vec<u8> {
+  let sorted;
+  let item = if options.sort_keys {
+    sorted = item.sorted();
+    &sorted
+  } else {
+    item
+  };
+
+  let mut out = Vec::new();
+  match item {
+    Item::Atom(atom) => write_atom(atom, &mut out),
+    Item::List(list) => write_items(list.iter(), &mut out),
+    Item::Map(map) => write_entries(map.0.iter(), &mut out),
+  }
+  out
+}
+
+pub(crate) fn write_item(item: &Item, out: &mut Vec<u8>) {
+  match item {
+    Item::Atom(atom) => write_atom(atom, out),
+    Item::List(list) => {
+      out.push(b'(');
+      write_items(list.iter(), out);
+      out.push(b')');
+    }
+    Item::Map(map) => {
+      out.push(b'(');
+      write_entries(map.0.iter(), out);
+      out.push(b')');
+    }
+  }
+}
+
+fn write_items<'i>(items: impl Iterator<Item = &'i Item>, out: &mut Vec<u8>) {
+  for (i, item) in items.enumerate() {
+    if i > 0 {
+      out.push(b' ');
+    }
+    write_item(item, out);
+  }
+}
+
+fn write_entries<'i>(
+  entries: impl Iterator<Item = &'i (Item, Item)>,
+  out: &mut Vec<u8>,
+) {
+  for (i, (key, value)) in entries.enumerate() {
+    if i > 0 {
+      out.push(b' ');
+    }
+    write_item(key, out);
+    out.extend_from_slice(b": ");
+    write_item(value, out);
+  }
+}
+
+/// Whether the atom can be written as a bare: not empty, valid utf8 and no
+/// special, white space or control characters.
+pub(crate) fn is_bare(atom: &[u8]) -> bool {
+  let special = |c: char| ":#()\\\"".contains(c);
+  let bad = |c: char| special(c) || c.is_whitespace() || c.is_control();
+  match std::str::from_utf8(atom) {
+    Ok(s) => !s.is_empty() && !s.chars().any(bad),
+    Err(_) => false,
+  }
+}
+
+pub(crate) fn write_atom(atom: &Atom, out: &mut Vec<u8>) {
+  if atom.1.is_none() && is_bare(&atom.0) {
+    out.extend_from_slice(&atom.0);
+  } else {
+    write_quoted(atom, out);
+  }
+}
+
+// A tagged atom is written as guarded string like `#(date)"…#(date)"`, the
+// guard is written before each escape as well
+pub(crate) fn write_quoted(atom: &Atom, out: &mut Vec<u8>) {
+  let guard = match &atom.1 {
+    Some(tag) => [&b"#("[..], tag, b")"].concat(),
+    None => Vec::new(),
+  };
+  let esc = |esc: &[u8], out: &mut Vec<u8>| {
+    out.extend_from_slice(&guard);
+    out.extend_from_slice(esc);
+  };
+
+  out.extend_from_slice(&guard);
+  out.push(b'"');
+  for chunk in atom.0.utf8_chunks() {
+    for c in chunk.valid().chars() {
+      match c {
+        '"' => esc(b"\\\"", out),
+        '\n' => esc(b"\\n", out),
+        '\r' => esc(b"\\r", out),
+        '\t' => esc(b"\\t", out),
+        '\0' => esc(b"\\0", out),
+        '\\' => esc(b"\\x5c", out),
+        c if c.is_ascii_control() => esc(&hex(c as u8), out),
+        c if c.is_control() => {
+          esc(format!("\\u{{{:02x}}}", c as u32).as_bytes(), out)
+        }
+        c => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+      }
+    }
+    for &byte in chunk.invalid() {
+      esc(&hex(byte), out);
+    }
+  }
+  out.extend_from_slice(&guard);
+  out.push(b'"');
+}
+
+fn hex(byte: u8) -> Vec<u8> {
+  format!("\\x{byte:02x}").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{canonical, canonical_with, CanonicalOptions};
+  use crate::{parse, Atom, Item};
+
+  fn roundtrip(item: &Item) {
+    let bytes = canonical(item);
+    assert_eq!(parse(&bytes).as_ref(), Ok(item), "{bytes:?}");
+  }
+
+  #[test]
+  fn quoting() {
+    let atom = |s: &[u8]| canonical(&Item::new_atom(s));
+    assert_eq!(atom(b"bare"), b"bare");
+    assert_eq!(atom("Schön".as_bytes()), "Schön".as_bytes());
+    assert_eq!(atom(b""), br#""""#);
+    assert_eq!(atom(b"a b"), br#""a b""#);
+    assert_eq!(atom(b"a:b"), br#""a:b""#);
+    assert_eq!(atom(b"#"), br##""#""##);
+    assert_eq!(atom(b"\"\\"), br#""\"\x5c""#);
+    assert_eq!(atom(b"\n\x01\x7f\xff"), br#""\n\x01\x7f\xff""#);
+    assert_eq!(atom("\u{85}".as_bytes()), br#""\u{85}""#);
+  }
+
+  #[test]
+  fn roundtrips() {
+    let atoms = [
+      &b""[..],
+      b"a",
+      b"a b",
+      b"#",
+      b"#\"",
+      b"a#",
+      b"#(t.1)",
+      b"#(t.1)\n#(t.1)\"",
+      b"\\",
+      b"\"",
+      b"\x00\x1b\xff\xfe",
+      "\u{85}\u{2028} ä 💩".as_bytes(),
+      b"0123456789abcdefghijklmnopqrstuvwxyz",
+    ];
+    for atom in atoms {
+      roundtrip(&Item::new_list([Item::new_atom(atom)]));
+      roundtrip(&Item::new_map([(Item::new_atom(atom), Item::new_atom(atom))]));
+      for tag in [&b"t.1"[..], b"12345678"] {
+        let tagged = Item::Atom(Atom::tagged(tag, atom).unwrap());
+        roundtrip(&Item::new_list([tagged]));
+      }
+    }
+
+    roundtrip(&parse(b"a (b (c ())) (k: (l: v))").unwrap());
+    roundtrip(&parse(b"(a b): (c d) x: ()").unwrap());
+  }
+
+  #[test]
+  fn formatting_independent() {
+    let a = parse(b"# config\nname: x\nlist: (\n  1\n  2 # two\n)\n").unwrap();
+    let b = parse(b"name:x list:(1 2)").unwrap();
+    assert_eq!(canonical(&a), canonical(&b));
+    assert_eq!(canonical(&a), b"name: x list: (1 2)");
+  }
+
+  #[test]
+  fn sort_keys() {
+    let options = CanonicalOptions { sort_keys: true };
+    let a = parse(b"b: 1 a: (d: 1 c: 2)").unwrap();
+    let b = parse(b"a: (c: 2 d: 1) b: 1").unwrap();
+    assert_ne!(canonical(&a), canonical(&b));
+    assert_eq!(canonical_with(&a, &options), canonical_with(&b, &options));
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,434 @@
+use ciborium::Value;
+
+use crate::atom::{float_text, is_tag};
+use crate::codec::{number, to_value, Codec, FLOAT, INT};
+use crate::{Atom, CodecError, CodecOptions, Item, Path};
+
+const BIGPOS: u64 = 2;
+const BIGNEG: u64 = 3;
+const SELF_DESCRIBED: u64 = 55799;
+
+// CBOR tags which have an axp tag, other tags are their number
+const TAGS: [(u64, &[u8]); 4] =
+  [(0, b"datetime"), (1, b"epoch"), (32, b"uri"), (37, b"uuid")];
+
+/// Options for [`from_cbor()`] and [`to_cbor()`]
+pub type CborOptions = CodecOptions;
+
+/// An error of [`from_cbor()`] and [`to_cbor()`]
+pub type CborError = CodecError;
+
+fn error(path: &Path, reason: impl Into<String>) -> CborError {
+  Value::error(path, reason)
+}
+
+/// Decode a CBOR data item to an item.
+///
+/// Maps become maps and arrays become lists. Text and byte strings become
+/// atoms of their bytes, numbers of any size and booleans of their text,
+/// null and undefined are `null`.
+///
+/// A tagged scalar becomes a tagged atom: `datetime` for tag 0, `epoch` for
+/// 1, `uri` for 32, `uuid` for 37 and the number for other tags. Bignums
+/// are numbers and the self-described tag is dropped. Other tags on arrays
+/// and maps, which axp can't tag, are errors.
+///
+/// ```
+/// # use axp::{from_cbor, parse, CborOptions};
+/// // {"port": 80, "since": 0("2013-03-21T20:04:00Z")}
+/// let bytes = b"\xa2\x64port\x18\x50\x65since\xc0\x742013-03-21T20:04:00Z";
+/// let item = from_cbor(bytes, &CborOptions::default()).unwrap();
+/// let expected = br##"port: 80 since: #(datetime)"2013-03-21T20:04:00Z#(datetime)""##;
+/// assert_eq!(item, parse(expected).unwrap());
+/// ```
+pub fn from_cbor(
+  bytes: &[u8],
+  options: &CborOptions,
+) -> Result<Item, CborError> {
+  let mut reader = bytes;
+  let value = ciborium::from_reader::<Value, _>(&mut reader)
+    .map_err(|err| error(&Path::root(), err.to_string()))?;
+  if reader.is_empty() {
+    from_value(&Path::root(), &value, options)
+  } else {
+    Err(error(&Path::root(), "bytes after the data item"))
+  }
+}
+
+fn from_value(
+  path: &Path,
+  value: &Value,
+  options: &CborOptions,
+) -> Result<Item, CborError> {
+  Ok(match value {
+    Value::Integer(integer) => {
+      number(i128::from(*integer).to_string(), INT, options)
+    }
+    Value::Float(float) => number(float_text(*float), FLOAT, options),
+    Value::Text(text) => Item::new_atom(text.as_bytes()),
+    Value::Bytes(bytes) => Item::new_atom(bytes),
+    Value::Bool(bool) => Item::new_atom(bool.to_string().as_bytes()),
+    Value::Null => Item::new_atom(b"null"),
+    Value::Tag(tag, value) => return from_tag(path, *tag, value, options),
+    Value::Array(array) => {
+      let items = array.iter().enumerate();
+      let items =
+        items.map(|(i, value)| from_value(&path.join_index(i), value, options));
+      Item::new_list(items.collect::<Result<Vec<_>, _>>()?)
+    }
+    Value::Map(map) => {
+      let mut entries = Vec::new();
+      for (key, value) in map {
+        let key = from_value(path, key, options)?;
+        let path = path.join(key.clone());
+        entries.push((key, from_value(&path, value, options)?));
+      }
+      Item::new_map(entries)
+    }
+    value => return Err(error(path, format!("unknown value {value:?}"))),
+  })
+}
+
+fn from_tag(
+  path: &Path,
+  tag: u64,
+  value: &Value,
+  options: &CborOptions,
+) -> Result<Item, CborError> {
+  match (tag, value) {
+    (SELF_DESCRIBED, value) => from_value(path, value, options),
+    (BIGPOS, Value::Bytes(bytes)) => Ok(number(decimal(bytes), INT, options)),
+    (BIGNEG, Value::Bytes(bytes)) => {
+      let text = format!("-{}", increment(&decimal(bytes)));
+      Ok(number(text, INT, options))
+    }
+    _ => {
+      let name = match TAGS.iter().find(|(number, _)| *number == tag) {
+        Some((_, name)) => name.to_vec(),
+        None => tag.to_string().into_bytes(),
+      };
+      match from_value(path, value, &CborOptions::default())? {
+        Item::Atom(Atom(bytes, None)) if is_tag(&name) => {
+          Ok(Item::Atom(Atom(bytes, Some(name))))
+        }
+        _ => Err(error(path, format!("tag {tag} has no axp form"))),
+      }
+    }
+  }
+}
+
+/// Encode an item as a CBOR data item.
+///
+/// Maps become maps and lists become arrays. Atoms tagged `int` and `float`
+/// become numbers, bignums if they need to, and the axp tags of
+/// [`from_cbor()`] become CBOR tags. Untagged atoms `true`, `false` and
+/// `null` become booleans and null, unless [`CborOptions::tag_numbers`]
+/// numbers which are written back the same become numbers, the rest become
+/// text or, if not UTF-8, byte strings. Other tags are errors.
+///
+/// ```
+/// # use axp::{from_cbor, parse, to_cbor, CborOptions};
+/// let item = parse(br##"port: 80 id: #(uri)"https://axp.dev#(uri)""##).unwrap();
+/// let bytes = to_cbor(&item, &CborOptions::default()).unwrap();
+/// assert_eq!(bytes, b"\xa2\x64port\x18\x50\x62id\xd8\x20\x6fhttps://axp.dev");
+/// assert_eq!(from_cbor(&bytes, &CborOptions::default()), Ok(item));
+/// ```
+pub fn to_cbor(
+  item: &Item,
+  options: &CborOptions,
+) -> Result<Vec<u8>, CborError> {
+  let value: Value = to_value(&Path::root(), item, options)?;
+  let mut out = Vec::new();
+  ciborium::into_writer(&value, &mut out).expect("writing to a vec");
+  Ok(out)
+}
+
+impl Codec for Value {
+  const FORMAT: &'static str = "Cbor";
+
+  fn scalar(
+    path: &Path,
+    atom: &Atom,
+    options: &CborOptions,
+  ) -> Result<Value, CborError> {
+    let bytes = atom.as_bytes();
+    let text = std::str::from_utf8(bytes).ok();
+    let not = |kind| {
+      let text = String::from_utf8_lossy(bytes);
+      error(path, format!("{text} is not {kind}"))
+    };
+    let Some(tag) = atom.tag() else {
+      return Ok(infer(bytes, options));
+    };
+    let number = match TAGS.iter().find(|(_, name)| *name == tag) {
+      Some((number, _)) => *number,
+      None => match std::str::from_utf8(tag).map(str::parse::<u64>) {
+        Ok(Ok(number)) => number,
+        _ if tag == INT => {
+          return text.and_then(integer).ok_or(not("an integer"));
+        }
+        _ if tag == FLOAT => {
+          let float = text.and_then(|text| text.parse::<f64>().ok());
+          return float.map(Value::Float).ok_or(not("a float"));
+        }
+        _ => {
+          let tag = String::from_utf8_lossy(tag);
+          return Err(error(path, format!("tag {tag} has no CBOR tag")));
+        }
+      },
+    };
+    let value = match number {
+      0 | 32 => Value::Text(text.ok_or(not("UTF-8"))?.to_string()),
+      1 => match text.and_then(integer) {
+        Some(integer) => integer,
+        None => {
+          let float = text.and_then(|text| text.parse::<f64>().ok());
+          float.map(Value::Float).ok_or(not("a number"))?
+        }
+      },
+      37 => Value::Bytes(bytes.to_vec()),
+      _ => infer(bytes, options),
+    };
+    Ok(Value::Tag(number, Box::new(value)))
+  }
+
+  fn array(items: Vec<Value>) -> Value {
+    Value::Array(items)
+  }
+
+  fn map(entries: Vec<(Value, Value)>) -> Value {
+    Value::Map(entries)
+  }
+}
+
+fn infer(bytes: &[u8], options: &CborOptions) -> Value {
+  let Ok(text) = std::str::from_utf8(bytes) else {
+    return Value::Bytes(bytes.to_vec());
+  };
+  match text {
+    "null" => Value::Null,
+    "true" => Value::Bool(true),
+    "false" => Value::Bool(false),
+    text if options.tag_numbers => Value::Text(text.to_string()),
+    text => match (integer(text), text.parse::<f64>()) {
+      (Some(integer), _) => integer,
+      (_, Ok(float)) if float_text(float) == text => Value::Float(float),
+      _ => Value::Text(text.to_string()),
+    },
+  }
+}
+
+// An integer or bignum, if its text is the same
+fn integer(text: &str) -> Option<Value> {
+  let (negative, digits) = match text.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, text),
+  };
+  let canonical = digits == "0" || !digits.starts_with('0');
+  if digits.is_empty()
+    || !canonical
+    || !digits.bytes().all(|b| b.is_ascii_digit())
+  {
+    return None;
+  }
+  if negative && digits == "0" {
+    return None;
+  }
+  if let Ok(integer) = text.parse::<i128>() {
+    return Some(integer.into());
+  }
+  if negative {
+    Some(Value::Tag(
+      BIGNEG,
+      Box::new(Value::Bytes(bignum(&decrement(digits)))),
+    ))
+  } else {
+    Some(Value::Tag(BIGPOS, Box::new(Value::Bytes(bignum(digits)))))
+  }
+}
+
+// The decimal digits of a big-endian unsigned bignum
+fn decimal(bytes: &[u8]) -> String {
+  let mut digits = Vec::new();
+  for &byte in bytes {
+    let mut carry = u32::from(byte);
+    for digit in &mut digits {
+      let value = u32::from(*digit) * 256 + carry;
+      *digit = (value % 10) as u8;
+      carry = value / 10;
+    }
+    while carry > 0 {
+      digits.push((carry % 10) as u8);
+      carry /= 10;
+    }
+  }
+  if digits.is_empty() {
+    "0".to_string()
+  } else {
+    digits.iter().rev().map(|digit| char::from(b'0' + digit)).collect()
+  }
+}
+
+// The big-endian unsigned bignum of decimal digits
+fn bignum(digits: &str) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  for digit in digits.bytes() {
+    let mut carry = u32::from(digit - b'0');
+    for byte in &mut bytes {
+      let value = u32::from(*byte) * 10 + carry;
+      *byte = value as u8;
+      carry = value >> 8;
+    }
+    while carry > 0 {
+      bytes.push(carry as u8);
+      carry >>= 8;
+    }
+  }
+  bytes.reverse();
+  bytes
+}
+
+// Decimal digits plus one, a CBOR negative bignum n is -1 - n
+fn increment(digits: &str) -> String {
+  let mut digits = digits.as_bytes().to_vec();
+  for digit in digits.iter_mut().rev() {
+    match *digit {
+      b'9' => *digit = b'0',
+      _ => {
+        *digit += 1;
+        return String::from_utf8(digits).expect("digits");
+      }
+    }
+  }
+  format!("1{}", String::from_utf8(digits).expect("digits"))
+}
+
+// Decimal digits minus one, at least one
+fn decrement(digits: &str) -> String {
+  let mut digits = digits.as_bytes().to_vec();
+  for digit in digits.iter_mut().rev() {
+    match *digit {
+      b'0' => *digit = b'9',
+      _ => {
+        *digit -= 1;
+        break;
+      }
+    }
+  }
+  let text = String::from_utf8(digits).expect("digits");
+  match text.trim_start_matches('0') {
+    "" => "0".to_string(),
+    text => text.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_cbor, to_cbor, CborOptions};
+  use crate::{canonical, parse, Item};
+
+  const TAGGED: CborOptions = CborOptions { tag_numbers: true };
+
+  fn decode(bytes: &[u8], options: &CborOptions) -> Result<String, String> {
+    let item = from_cbor(bytes, options).map_err(|err| err.to_string())?;
+    Ok(String::from_utf8(canonical(&item)).unwrap())
+  }
+
+  fn encode(input: &str, options: &CborOptions) -> Result<Vec<u8>, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    to_cbor(&item, options).map_err(|err| err.to_string())
+  }
+
+  #[test]
+  fn decodes() {
+    // [-1, 1.5, "x", h'ff', {[1]: {}}, null, undefined]
+    let bytes = b"\x87\x20\xf9\x3e\x00\x61x\x41\xff\xa1\x81\x01\xa0\xf6\xf7";
+    let options = CborOptions::default();
+    assert_eq!(
+      decode(bytes, &options).unwrap(),
+      r#"-1 1.5 x "\xff" ((1): ()) null null"#
+    );
+    assert_eq!(
+      decode(bytes, &TAGGED).unwrap(),
+      r##"#(int)"-1#(int)" #(float)"1.5#(float)" x "\xff" ((#(int)"1#(int)"): ()) null null"##
+    );
+
+    // 1(1363896240), 37(h'01'), 99(2), 55799([])
+    let bytes = b"\x84\xc1\x1a\x51\x4b\x67\xb0\xd8\x25\x41\x01\xd8\x63\x02\xd9\xd9\xf7\x80";
+    assert_eq!(
+      decode(bytes, &TAGGED).unwrap(),
+      r##"#(epoch)"1363896240#(epoch)" #(uuid)"#(uuid)\x01#(uuid)" #(99)"2#(99)" ()"##
+    );
+
+    // 2(h'010000000000000000'), 3(h'010000000000000000'), bignums of 2^64
+    let bytes = b"\x82\xc2\x49\x01\x00\x00\x00\x00\x00\x00\x00\x00\
+      \xc3\x49\x01\x00\x00\x00\x00\x00\x00\x00\x00";
+    assert_eq!(
+      decode(bytes, &options).unwrap(),
+      "18446744073709551616 -18446744073709551617"
+    );
+  }
+
+  #[test]
+  fn encodes() {
+    let options = CborOptions::default();
+    assert_eq!(
+      encode("1 -1 1.5 1e3 01 -0 true null", &options).unwrap(),
+      b"\x88\x01\x20\xf9\x3e\x00\x631e3\x6201\x62-0\xf5\xf6"
+    );
+    assert_eq!(
+      encode(r##"1 #(int)"2#(int)""##, &TAGGED).unwrap(),
+      b"\x82\x611\x02"
+    );
+    let binary = Item::new_atom(b"\xff");
+    assert_eq!(to_cbor(&binary, &options).unwrap(), b"\x41\xff");
+
+    let roundtrip = r##"a: (1 "01" #(float)"2.5#(float)" "" "\xff") (k l): (c: ())
+      b: (#(int)"-18446744073709551617#(int)" #(int)"340282366920938463463374607431768211456#(int)")
+      c: (#(datetime)"2013-03-21T20:04:00Z#(datetime)" #(epoch)"1.5#(epoch)" #(uri)"x#(uri)" #(uuid)"#(uuid)\x01#(uuid)")
+      d: #(99)"x#(99)""##;
+    let item = parse(roundtrip.as_bytes()).unwrap();
+    let bytes = to_cbor(&item, &TAGGED).unwrap();
+    assert_eq!(from_cbor(&bytes, &TAGGED), Ok(item));
+  }
+
+  #[test]
+  fn errors() {
+    let options = CborOptions::default();
+    let err = |input| encode(input, &options).unwrap_err();
+    assert_eq!(
+      err(r##"a: #(int)"01#(int)""##),
+      "CborError: a: 01 is not an integer"
+    );
+    assert_eq!(
+      err(r##"a: (#(float)"#(float)")"##),
+      "CborError: a/0:  is not a float"
+    );
+    assert_eq!(
+      err(r##"#(epoch)"x#(epoch)""##),
+      "CborError: 0: x is not a number"
+    );
+    assert_eq!(
+      err(r##"#(date)"x#(date)""##),
+      "CborError: 0: tag date has no CBOR tag"
+    );
+    let item = Item::Atom(crate::Atom::tagged(b"uri", b"\xff").unwrap());
+    assert_eq!(
+      to_cbor(&item, &options).unwrap_err().reason(),
+      "\u{fffd} is not UTF-8"
+    );
+
+    // 99([1])
+    assert_eq!(
+      decode(b"\x81\xd8\x63\x81\x01", &options).unwrap_err(),
+      "CborError: 0: tag 99 has no axp form"
+    );
+    assert!(decode(b"\x82\x01", &options).is_err());
+    assert_eq!(
+      decode(b"\x01\x02", &options).unwrap_err(),
+      "CborError: bytes after the data item"
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,144 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::Item;
+
+// Both comparators borrow the item so that they can be used ad hoc, for
+// example `Unordered::new(&a) == Unordered::new(&b)`, or as keys of a
+// `HashSet`.
+
+/// Compare items with the order of map entries being significant.
+///
+/// This is the same as comparing the items directly and exists as the
+/// counterpart of [`Unordered`].
+///
+/// ```
+/// # use axp::{parse, Ordered};
+/// let a = parse(b"x: 1 y: 2").unwrap();
+/// let b = parse(b"y: 2 x: 1").unwrap();
+/// assert!(Ordered(&a) != Ordered(&b));
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Ordered<'i>(pub &'i Item);
+
+/// Compare items with the order of map entries being insignificant.
+///
+/// Maps are compared as if their entries were sorted, on all levels. The
+/// order of list items still matters. `Hash` and `Ord` are consistent with
+/// this equality. The maps are sorted once when the comparator is made.
+///
+/// ```
+/// # use axp::{parse, Unordered};
+/// let a = parse(b"x: 1 (2 3): y").unwrap();
+/// let b = parse(b"(2 3): y x: 1").unwrap();
+/// let c = parse(b"(3 2): y x: 1").unwrap();
+/// assert!(Unordered::new(&a) == Unordered::new(&b));
+/// assert!(Unordered::new(&a) != Unordered::new(&c));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Unordered<'i> {
+  item: &'i Item,
+  sorted: Cow<'i, Item>,
+}
+
+impl<'i> Unordered<'i> {
+  pub fn new(item: &'i Item) -> Self {
+    let sorted = if has_map(item) {
+      Cow::Owned(item.sorted())
+    } else {
+      Cow::Borrowed(item)
+    };
+    Unordered { item, sorted }
+  }
+
+  /// The item as given, with its maps in their order
+  pub fn item(&self) -> &'i Item {
+    self.item
+  }
+}
+
+fn has_map(item: &Item) -> bool {
+  match item {
+    Item::Atom(_) => false,
+    Item::List(list) => list.iter().any(has_map),
+    Item::Map(_) => true,
+  }
+}
+
+impl<'i> PartialEq for Unordered<'i> {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl<'i> Eq for Unordered<'i> {}
+
+impl<'i> PartialOrd for Unordered<'i> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<'i> Ord for Unordered<'i> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self.sorted.cmp(&other.sorted)
+  }
+}
+
+impl<'i> Hash for Unordered<'i> {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.sorted.hash(state)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Ordered, Unordered};
+  use crate::{parse, Item};
+  use std::collections::{BTreeSet, HashSet};
+
+  fn doc(input: &str) -> Item {
+    parse(input.as_bytes()).unwrap()
+  }
+
+  #[test]
+  fn total_order() {
+    assert!(Item::new_atom(b"zz") < Item::new_list([]));
+    assert!(doc("a a") < Item::new_map([]));
+    assert!(Item::new_atom(b"a") < Item::new_atom(b"ab"));
+    assert!(Item::new_atom(b"ab") < Item::new_atom(b"b"));
+    assert!(Item::new_atom(b"\xff") > Item::new_atom("ä".as_bytes()));
+    assert!(doc("a b") < doc("a c"));
+    assert!(doc("a") < doc("a a"));
+  }
+
+  #[test]
+  fn items_as_keys() {
+    let items = [doc("a b"), doc("x: 1"), doc("a b"), doc("()")];
+    let hashed = items.iter().cloned().collect::<HashSet<_>>();
+    let ordered = items.iter().cloned().collect::<BTreeSet<_>>();
+    assert_eq!(hashed.len(), 3);
+    assert_eq!(ordered.len(), 3);
+  }
+
+  #[test]
+  fn unordered() {
+    let entry = |k: &str, v| (Item::new_atom(k.as_bytes()), v);
+    let a =
+      Item::new_map([entry("k", doc("x: 1 y: 2")), entry("l", doc("1 2"))]);
+    let b =
+      Item::new_map([entry("l", doc("1 2")), entry("k", doc("y: 2 x: 1"))]);
+    assert!(Ordered(&a) != Ordered(&b));
+    assert!(Unordered::new(&a) == Unordered::new(&b));
+    assert_eq!(Unordered::new(&b).item(), &b);
+
+    let set = [Unordered::new(&a), Unordered::new(&b)];
+    assert_eq!(set.into_iter().collect::<HashSet<_>>().len(), 1);
+    let lists = [doc("1 (x: 1 y: 2)"), doc("1 (y: 2 x: 1)"), doc("1 2")];
+    let sorted = lists.iter().map(Unordered::new).collect::<BTreeSet<_>>();
+    assert_eq!(sorted.len(), 2);
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
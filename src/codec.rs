@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::{Atom, Item, Path};
+
+pub(crate) const INT: &[u8] = b"int";
+pub(crate) const FLOAT: &[u8] = b"float";
+
+/// Options for the MessagePack and CBOR conversions, see
+/// [`MsgpackOptions`](crate::MsgpackOptions) and
+/// [`CborOptions`](crate::CborOptions)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CodecOptions {
+  /// Tag integers `int` and floats `float` when reading, and write only
+  /// tagged atoms as numbers so that a string `"80"` stays a string
+  pub tag_numbers: bool,
+}
+
+/// An error of the MessagePack or CBOR conversions, see
+/// [`MsgpackError`](crate::MsgpackError) and [`CborError`](crate::CborError)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CodecError {
+  format: &'static str,
+  path: Path,
+  reason: String,
+}
+
+impl Error for CodecError {}
+
+impl CodecError {
+  /// The path of the item which can't be converted, the root for decode
+  /// errors
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for CodecError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.path.is_root() {
+      write!(f, "{}Error: {}", self.format, self.reason)
+    } else {
+      write!(f, "{}Error: {}: {}", self.format, self.path, self.reason)
+    }
+  }
+}
+
+// A value of a format like MessagePack or CBOR
+pub(crate) trait Codec: Sized {
+  // The format in error messages, `Cbor` for `CborError`
+  const FORMAT: &'static str;
+
+  fn scalar(
+    path: &Path,
+    atom: &Atom,
+    options: &CodecOptions,
+  ) -> Result<Self, CodecError>;
+
+  fn array(items: Vec<Self>) -> Self;
+
+  fn map(entries: Vec<(Self, Self)>) -> Self;
+
+  fn error(path: &Path, reason: impl Into<String>) -> CodecError {
+    CodecError {
+      format: Self::FORMAT,
+      path: path.clone(),
+      reason: reason.into(),
+    }
+  }
+}
+
+// A number read from the format, tagged if the options say so
+pub(crate) fn number(text: String, tag: &[u8], options: &CodecOptions) -> Item {
+  if options.tag_numbers {
+    Item::Atom(Atom(text.into_bytes(), Some(tag.to_vec())))
+  } else {
+    Item::new_atom(text.as_bytes())
+  }
+}
+
+pub(crate) fn to_value<V: Codec>(
+  path: &Path,
+  item: &Item,
+  options: &CodecOptions,
+) -> Result<V, CodecError> {
+  match item {
+    Item::Atom(atom) => V::scalar(path, atom, options),
+    Item::List(list) => {
+      let items = list.iter().enumerate();
+      let items =
+        items.map(|(i, item)| to_value(&path.join_index(i), item, options));
+      items.collect::<Result<_, _>>().map(V::array)
+    }
+    Item::Map(map) => {
+      let mut entries = Vec::new();
+      for (key, value) in map.iter() {
+        let path = path.join(key.clone());
+        entries.push((
+          to_value(&path, key, options)?,
+          to_value(&path, value, options)?,
+        ));
+      }
+      Ok(V::map(entries))
+    }
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
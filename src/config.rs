@@ -0,0 +1,585 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
+
+use crate::Span;
+use crate::{parse, FromItem, FromItemError, FsLoader, IncludeError, Item};
+use crate::{Layers, Loader, MergeOptions, ParseError, Path, Resolver};
+
+/// Where a layer of a configuration comes from
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Source {
+  File(PathBuf),
+  /// An environment variable by name
+  Env(String),
+}
+
+impl fmt::Display for Source {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Source::File(path) => write!(f, "{}", path.display()),
+      Source::Env(name) => write!(f, "${name}"),
+    }
+  }
+}
+
+/// Where a value of a configuration comes from, the span if from a file
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Location {
+  pub source: Source,
+  pub span: Option<Span>,
+}
+
+impl fmt::Display for Location {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.span {
+      Some(span) => write!(f, "{}:{span}", self.source),
+      None => write!(f, "{}", self.source),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+  Io {
+    path: PathBuf,
+    error: io::Error,
+  },
+  Parse {
+    source: Source,
+    error: ParseError,
+  },
+  /// A file includes a document which could not be loaded or merged
+  Include {
+    path: PathBuf,
+    error: IncludeError,
+  },
+  /// A value could not be converted, see [`FromItem`]
+  Value {
+    path: Path,
+    location: Option<Location>,
+    reason: String,
+  },
+}
+
+impl Error for ConfigError {}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("ConfigError: ")?;
+    match self {
+      ConfigError::Io { path, error } => {
+        write!(f, "{}: {error}", path.display())
+      }
+      ConfigError::Parse { source, error } => write!(f, "{source}: {error}"),
+      ConfigError::Include { path, error } => {
+        write!(f, "{}: {error}", path.display())
+      }
+      ConfigError::Value { path, location: Some(location), reason } => {
+        write!(f, "{path} from {location} {reason}")
+      }
+      ConfigError::Value { path, location: None, reason } => {
+        write!(f, "{path} {reason}")
+      }
+    }
+  }
+}
+
+/// Loads a configuration from files and environment variables.
+///
+/// The files are merged in order, by default these if they exist:
+///
+/// - `/etc/{app}/config.axp`
+/// - `$XDG_CONFIG_HOME/{app}/config.axp` or `~/.config/{app}/config.axp`
+/// - `./{app}.axp`
+///
+/// then the files added by [`ConfigLoader::file()`] which must exist. The
+/// includes of a file, see [`Resolver`], are loaded from its directory. Last
+/// the environment variables with the prefix, by default the uppercase app
+/// name, are merged in: `APP__SERVER__PORT=8080` sets `server/port` to
+/// `8080`. A value is parsed if it is a single item, else taken as atom.
+///
+/// ```
+/// # use axp::ConfigLoader;
+/// let config = ConfigLoader::new("app")
+///   .locations(["app.axp"])
+///   .env([("APP__SERVER__PORT", "http")])
+///   .load()
+///   .unwrap();
+/// let err = config.get::<u16>(&"server/port".parse().unwrap()).unwrap_err();
+/// assert_eq!(
+///   err.to_string(),
+///   "ConfigError: server/port from $APP__SERVER__PORT must be a number"
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConfigLoader {
+  app: String,
+  locations: Option<Vec<PathBuf>>,
+  files: Vec<PathBuf>,
+  env_prefix: String,
+  env: Option<Vec<(String, String)>>,
+  options: MergeOptions,
+}
+
+impl ConfigLoader {
+  pub fn new(app: &str) -> Self {
+    ConfigLoader {
+      app: app.to_string(),
+      locations: None,
+      files: Vec::new(),
+      env_prefix: app.to_uppercase(),
+      env: None,
+      options: MergeOptions::default(),
+    }
+  }
+
+  /// Replace the standard locations, files which do not exist are skipped
+  pub fn locations<I>(mut self, locations: I) -> Self
+  where
+    I: IntoIterator,
+    I::Item: Into<PathBuf>,
+  {
+    self.locations = Some(locations.into_iter().map(Into::into).collect());
+    self
+  }
+
+  /// Add a file which must exist
+  pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+    self.files.push(path.into());
+    self
+  }
+
+  pub fn env_prefix(mut self, prefix: &str) -> Self {
+    self.env_prefix = prefix.to_string();
+    self
+  }
+
+  /// Replace the environment variables of the process
+  pub fn env<I, K, V>(mut self, vars: I) -> Self
+  where
+    I: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<String>,
+  {
+    let vars = vars.into_iter().map(|(k, v)| (k.into(), v.into()));
+    self.env = Some(vars.collect());
+    self
+  }
+
+  pub fn options(mut self, options: MergeOptions) -> Self {
+    self.options = options;
+    self
+  }
+
+  pub fn load(&self) -> Result<Config, ConfigError> {
+    let env = self.vars();
+    let mut config = Config {
+      layers: Layers::new(self.options.clone()),
+      sources: Vec::new(),
+      files: Vec::new(),
+    };
+
+    for path in self.locations_with(&env) {
+      match std::fs::read(&path) {
+        Ok(bytes) => config.push_file(path, &bytes)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => Err(ConfigError::Io { path, error })?,
+      }
+    }
+
+    for path in &self.files {
+      match std::fs::read(path) {
+        Ok(bytes) => config.push_file(path.clone(), &bytes)?,
+        Err(error) => Err(ConfigError::Io { path: path.clone(), error })?,
+      }
+    }
+
+    let mut env = env
+      .into_iter()
+      .filter_map(|(name, value)| Some((self.env_path(&name)?, name, value)))
+      .collect::<Vec<_>>();
+    env.sort();
+    for (path, name, value) in env {
+      config.push_env(name, &path, &value);
+    }
+
+    Ok(config)
+  }
+
+  /// The files which are loaded if they exist, the locations and the files
+  pub fn paths(&self) -> Vec<PathBuf> {
+    let mut paths = self.locations_with(&self.vars());
+    paths.extend(self.files.iter().cloned());
+    paths
+  }
+
+  fn vars(&self) -> Vec<(String, String)> {
+    match &self.env {
+      Some(env) => env.clone(),
+      None => std::env::vars().collect(),
+    }
+  }
+
+  fn locations_with(&self, env: &[(String, String)]) -> Vec<PathBuf> {
+    match &self.locations {
+      Some(locations) => locations.clone(),
+      None => self.standard_locations(env),
+    }
+  }
+
+  fn standard_locations(&self, env: &[(String, String)]) -> Vec<PathBuf> {
+    let var = |name: &str| {
+      let value = env.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+      value.filter(|v| !v.is_empty()).map(PathBuf::from)
+    };
+    let config_home = var("XDG_CONFIG_HOME")
+      .or_else(|| var("HOME").map(|home| home.join(".config")));
+
+    let app = &self.app;
+    let mut locations = vec![PathBuf::from(format!("/etc/{app}/config.axp"))];
+    locations.extend(config_home.map(|dir| dir.join(app).join("config.axp")));
+    locations.push(PathBuf::from(format!("{app}.axp")));
+    locations
+  }
+
+  // `APP__SERVER__PORT` is `server/port`
+  fn env_path(&self, name: &str) -> Option<Path> {
+    let name = name.strip_prefix(&self.env_prefix)?.strip_prefix("__")?;
+    let steps = name.split("__").collect::<Vec<_>>();
+    if steps.iter().any(|step| step.is_empty()) {
+      return None;
+    }
+    let step = |step: &&str| Item::new_atom(step.to_lowercase().as_bytes());
+    Some(Path::new(steps.iter().map(step)))
+  }
+}
+
+/// A loaded configuration which knows where its values come from.
+#[derive(Clone, Debug)]
+pub struct Config {
+  layers: Layers,
+  // The sources of each layer, a file and the files it includes
+  sources: Vec<Vec<Source>>,
+  files: Vec<PathBuf>,
+}
+
+impl Config {
+  fn push_file(
+    &mut self,
+    path: PathBuf,
+    bytes: &[u8],
+  ) -> Result<(), ConfigError> {
+    let dir = path.parent().unwrap_or(FsPath::new("")).to_path_buf();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let loader = FileLoader { name: &name, bytes, dir: FsLoader::new(&dir) };
+    let resolved =
+      Resolver::new(loader).resolve(&name).map_err(|error| match error {
+        IncludeError::Parse { name: n, error } if n == name => {
+          ConfigError::Parse { source: Source::File(path.clone()), error }
+        }
+        error => ConfigError::Include { path: path.clone(), error },
+      })?;
+
+    let included = resolved.names().iter().skip(1).map(|name| dir.join(name));
+    let files: Vec<_> = std::iter::once(path).chain(included).collect();
+    for file in &files {
+      if !self.files.contains(file) {
+        self.files.push(file.clone());
+      }
+    }
+    self.layers.push_resolved(&resolved);
+    self.sources.push(files.into_iter().map(Source::File).collect());
+    Ok(())
+  }
+
+  fn push_env(&mut self, name: String, path: &Path, value: &str) {
+    let value = match parse(value.as_bytes()) {
+      Ok(Item::List(list)) if list.len() == 1 => list.0[0].clone(),
+      _ => Item::new_atom(value.as_bytes()),
+    };
+    let item = path
+      .steps()
+      .iter()
+      .rev()
+      .fold(value, |value, step| Item::new_map([(step.clone(), value)]));
+    self.layers.push(&item);
+    self.sources.push(vec![Source::Env(name)]);
+  }
+
+  /// The merged configuration
+  pub fn item(&self) -> &Item {
+    self.layers.item()
+  }
+
+  /// The files which were read, with the included ones
+  pub fn files(&self) -> &[PathBuf] {
+    &self.files
+  }
+
+  /// Where the value at the path comes from
+  pub fn location(&self, path: &Path) -> Option<Location> {
+    let sources = self.sources.get(self.layers.layer(path)?)?;
+    let (document, span) = match self.layers.located(path) {
+      Some((document, span)) => (document, Some(span)),
+      None => (0, None),
+    };
+    Some(Location { source: sources.get(document)?.clone(), span })
+  }
+
+  /// Convert the value at the path, a missing value is converted from `()`
+  /// so that options are `None`
+  pub fn get<T: FromItem>(&self, path: &Path) -> Result<T, ConfigError> {
+    let result = match self.item().get(path) {
+      Some(item) => T::from_item(item),
+      None => {
+        T::from_item(&Item::nil()).map_err(|_| FromItemError::new("is missing"))
+      }
+    };
+    result.map_err(|err| self.error(path, err))
+  }
+
+  /// Convert the whole configuration
+  pub fn deserialize<T: FromItem>(&self) -> Result<T, ConfigError> {
+    T::from_item(self.item()).map_err(|err| self.error(&Path::root(), err))
+  }
+
+  // The location is of the item or of the nearest parent, the root has no
+  // useful location
+  fn error(&self, path: &Path, err: FromItemError) -> ConfigError {
+    let mut path = path.clone();
+    for step in err.path().steps() {
+      path.push(step.clone());
+    }
+
+    let mut parent = Some(path.clone());
+    let location = std::iter::from_fn(|| {
+      let path = parent.take()?;
+      parent = path.split_last().map(|(parent, _)| parent);
+      Some(path)
+    })
+    .take_while(|path| !path.is_root())
+    .find_map(|path| self.location(&path));
+
+    ConfigError::Value { path, location, reason: err.reason().to_string() }
+  }
+}
+
+// Loads a file from memory and the documents it includes from its directory
+struct FileLoader<'f> {
+  name: &'f str,
+  bytes: &'f [u8],
+  dir: FsLoader,
+}
+
+impl Loader for FileLoader<'_> {
+  fn load(&self, name: &str) -> io::Result<Vec<u8>> {
+    if name == self.name {
+      Ok(self.bytes.to_vec())
+    } else {
+      self.dir.load(name)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{Path as FsPath, PathBuf};
+
+  use super::{ConfigError, ConfigLoader, Source};
+  use crate::{FromItem, FromItemError, Item, Path};
+
+  fn path(s: &str) -> Path {
+    s.parse().unwrap()
+  }
+
+  // A directory which is removed when dropped, also if a test fails
+  struct TempDir(PathBuf);
+
+  impl std::ops::Deref for TempDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+      &self.0
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  // A fresh directory with files
+  fn dir(name: &str, files: &[(&str, &str)]) -> TempDir {
+    let dir = std::env::temp_dir()
+      .join(format!("axp-config-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    for (file, content) in files {
+      let file = dir.join(file);
+      std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+      std::fs::write(file, content).unwrap();
+    }
+    TempDir(dir)
+  }
+
+  #[derive(Debug, PartialEq)]
+  struct Server {
+    host: String,
+    port: u16,
+    workers: Option<u8>,
+  }
+
+  impl FromItem for Server {
+    fn from_item(item: &Item) -> Result<Self, FromItemError> {
+      Ok(Server {
+        host: item.field("host")?,
+        port: item.field("port")?,
+        workers: item.opt_field("workers")?,
+      })
+    }
+  }
+
+  #[test]
+  fn layers_and_env() {
+    let dir = dir(
+      "layers",
+      &[
+        ("etc/app/config.axp", "server: (host: localhost port: 80)\n"),
+        ("home/.config/app/config.axp", "# user\nserver: (port: 8080)\n"),
+      ],
+    );
+    let env = [
+      ("HOME", dir.join("home").to_str().unwrap().to_string()),
+      ("APP__SERVER__WORKERS", "4".to_string()),
+      ("APP__SERVER__HOST", "example.com".to_string()),
+      ("APPX__SERVER__HOST", "ignored".to_string()),
+      ("APP__", "ignored".to_string()),
+    ];
+    let config = ConfigLoader::new("app")
+      .locations([
+        dir.join("etc/app/config.axp"),
+        dir.join("home/.config/app/config.axp"),
+        dir.join("missing.axp"),
+      ])
+      .env(env)
+      .load()
+      .unwrap();
+
+    let server = config.get::<Server>(&path("server")).unwrap();
+    let host = "example.com".to_string();
+    assert_eq!(server, Server { host, port: 8080, workers: Some(4) });
+    assert_eq!(config.get::<Option<u8>>(&path("x/y")).unwrap(), None);
+
+    let location = |s: &str| config.location(&path(s)).unwrap().to_string();
+    let etc = dir.join("etc/app/config.axp");
+    let user = dir.join("home/.config/app/config.axp");
+    assert_eq!(location("server/port"), format!("{}:2:16", user.display()));
+    assert_eq!(location("server/host"), "$APP__SERVER__HOST");
+    assert_eq!(
+      config.location(&path("server/port")).unwrap().source,
+      Source::File(user)
+    );
+
+    let err = config.get::<bool>(&path("server/port")).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      format!(
+        "ConfigError: server/port from {}:2:16 must be true or false",
+        dir.join("home/.config/app/config.axp").display()
+      )
+    );
+    assert!(etc.exists());
+  }
+
+  #[test]
+  fn standard_locations() {
+    let dir = dir("standard", &[("xdg/app/config.axp", "a: 1")]);
+    let xdg = dir.join("xdg");
+    let loader = ConfigLoader::new("app")
+      .env([("XDG_CONFIG_HOME", xdg.to_str().unwrap()), ("HOME", "/nope")]);
+    assert_eq!(
+      loader.standard_locations(loader.env.as_ref().unwrap()),
+      [
+        FsPath::new("/etc/app/config.axp"),
+        &xdg.join("app/config.axp"),
+        FsPath::new("app.axp")
+      ]
+    );
+    let config = loader.load().unwrap();
+    assert_eq!(config.get::<u8>(&path("a")).unwrap(), 1);
+  }
+
+  #[test]
+  fn errors() {
+    let dir = dir("errors", &[("bad.axp", "a: (b: 1\n"), ("ok.axp", "a: 1")]);
+    let loader = ConfigLoader::new("app")
+      .locations([dir.join("none.axp")])
+      .env([("APP__B", "x y"), ("APP__D", "#("), ("APP__E", r#""\xZZ""#)]);
+
+    let err = loader.clone().file(dir.join("missing.axp")).load().unwrap_err();
+    assert!(matches!(err, ConfigError::Io { .. }));
+
+    let err = loader.clone().file(dir.join("bad.axp")).load().unwrap_err();
+    let ConfigError::Parse { source, .. } = err else { panic!("{err}") };
+    assert_eq!(source, Source::File(dir.join("bad.axp")));
+
+    let config = loader.file(dir.join("ok.axp")).load().unwrap();
+    assert_eq!(config.get::<String>(&path("b")).unwrap(), "x y");
+    // env values which don't parse are text
+    assert_eq!(config.get::<String>(&path("d")).unwrap(), "#(");
+    assert_eq!(config.get::<String>(&path("e")).unwrap(), r#""\xZZ""#);
+    let err = config.get::<u8>(&path("c")).unwrap_err();
+    assert_eq!(err.to_string(), "ConfigError: c is missing");
+
+    let err = config.deserialize::<Server>().unwrap_err();
+    let ConfigError::Value { path: p, location, reason } = err else {
+      panic!("{err}")
+    };
+    assert_eq!(
+      (p, location, reason.as_str()),
+      (path("host"), None, "is missing")
+    );
+  }
+
+  #[test]
+  fn includes() {
+    let dir = dir(
+      "includes",
+      &[
+        ("app.axp", "@include: base.axp port: 8080"),
+        ("base.axp", "host: localhost port: 80 db: (@include db/db.axp)"),
+        ("db/db.axp", "name: app"),
+        ("cycle.axp", "@include: cycle.axp"),
+      ],
+    );
+    let loader = ConfigLoader::new("app").locations([dir.join("app.axp")]);
+    let config = loader.load().unwrap();
+    assert_eq!(config.get::<u16>(&path("port")).unwrap(), 8080);
+    assert_eq!(config.get::<String>(&path("db/name")).unwrap(), "app");
+    assert_eq!(
+      config.files(),
+      [dir.join("app.axp"), dir.join("base.axp"), dir.join("db/db.axp")]
+    );
+
+    let location = |s: &str| config.location(&path(s)).unwrap().to_string();
+    let app = dir.join("app.axp").display().to_string();
+    assert_eq!(location("port"), format!("{app}:1:26"));
+    let base = dir.join("base.axp").display().to_string();
+    assert_eq!(location("host"), format!("{base}:1:7"));
+    let db = dir.join("db/db.axp").display().to_string();
+    assert_eq!(location("db/name"), format!("{db}:1:7"));
+
+    let err = loader.file(dir.join("cycle.axp")).load().unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      format!(
+        "ConfigError: {}: IncludeError: include cycle cycle.axp -> cycle.axp",
+        dir.join("cycle.axp").display()
+      )
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::path::PathBuf;
+
+use crate::{Atom, Item, List, Map, Path};
+
+/// Conversion of items to Rust values.
+///
+/// Atoms are converted by their text, so `42` is a number and `true` a bool.
+/// The empty list `()` is `None` for options and empty for maps.
+///
+/// ```
+/// # use axp::{parse, FromItem, FromItemError, Item};
+/// struct Server {
+///   host: String,
+///   port: u16,
+/// }
+///
+/// impl FromItem for Server {
+///   fn from_item(item: &Item) -> Result<Self, FromItemError> {
+///     Ok(Server { host: item.field("host")?, port: item.field("port")? })
+///   }
+/// }
+///
+/// let item = parse(b"server: (host: localhost port: http)").unwrap();
+/// let err = item.field::<Server>("server").err().unwrap();
+/// assert_eq!(err.to_string(), "server/port must be a number");
+/// ```
+pub trait FromItem: Sized {
+  fn from_item(item: &Item) -> Result<Self, FromItemError>;
+}
+
+/// Why an item could not be converted and where
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FromItemError {
+  path: Path,
+  reason: String,
+}
+
+impl FromItemError {
+  pub fn new(reason: impl Into<String>) -> Self {
+    FromItemError { path: Path::root(), reason: reason.into() }
+  }
+
+  /// The path of the bad item relative to the converted item
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+
+  /// Prefix the path with a step, for conversions of children
+  pub fn within(mut self, step: Item) -> Self {
+    self.path =
+      Path::new(std::iter::once(step).chain(self.path.steps().to_vec()));
+    self
+  }
+}
+
+impl fmt::Display for FromItemError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.path.is_root() {
+      write!(f, "{}", self.reason)
+    } else {
+      write!(f, "{} {}", self.path, self.reason)
+    }
+  }
+}
+
+impl std::error::Error for FromItemError {}
+
+impl Item {
+  /// Convert the value of a map entry, a missing entry is an error
+  pub fn field<T: FromItem>(&self, key: &str) -> Result<T, FromItemError> {
+    match self.opt_field(key)? {
+      Some(value) => Ok(value),
+      None => Err(FromItemError::new("is missing").within(key_item(key))),
+    }
+  }
+
+  /// Convert the value of a map entry if it is there
+  pub fn opt_field<T: FromItem>(
+    &self,
+    key: &str,
+  ) -> Result<Option<T>, FromItemError> {
+    let value = match self {
+      Item::Map(map) => map.get(&key_item(key)),
+      Item::List(list) if list.is_empty() => None,
+      _ => return Err(FromItemError::new("must be a map")),
+    };
+    value.map(T::from_item).transpose().map_err(|err| err.within(key_item(key)))
+  }
+}
+
+fn key_item(key: &str) -> Item {
+  Item::new_atom(key.as_bytes())
+}
+
+fn text(item: &Item) -> Option<&str> {
+  match item {
+    Item::Atom(atom) => std::str::from_utf8(atom.as_bytes()).ok(),
+    _ => None,
+  }
+}
+
+impl FromItem for Item {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    Ok(item.clone())
+  }
+}
+
+impl FromItem for Atom {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    match item {
+      Item::Atom(atom) => Ok(atom.clone()),
+      _ => Err(FromItemError::new("must be an atom")),
+    }
+  }
+}
+
+impl FromItem for List {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    match item {
+      Item::List(list) => Ok(list.clone()),
+      _ => Err(FromItemError::new("must be a list")),
+    }
+  }
+}
+
+impl FromItem for Map {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    match item {
+      Item::Map(map) => Ok(map.clone()),
+      _ => Err(FromItemError::new("must be a map")),
+    }
+  }
+}
+
+impl FromItem for String {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    text(item).map(String::from).ok_or(FromItemError::new("must be text"))
+  }
+}
+
+impl FromItem for PathBuf {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    String::from_item(item).map(PathBuf::from)
+  }
+}
+
+impl FromItem for bool {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    match text(item) {
+      Some("true") => Ok(true),
+      Some("false") => Ok(false),
+      _ => Err(FromItemError::new("must be true or false")),
+    }
+  }
+}
+
+macro_rules! from_item_int {
+  ( $( $ty:ty )+ ) => { $(
+    impl FromItem for $ty {
+      fn from_item(item: &Item) -> Result<Self, FromItemError> {
+        use std::num::IntErrorKind::*;
+
+        let text = text(item).ok_or(FromItemError::new("must be a number"))?;
+        text.parse().map_err(|err: std::num::ParseIntError| {
+          match err.kind() {
+            PosOverflow | NegOverflow => FromItemError::new("is out of range"),
+            _ => FromItemError::new("must be a number"),
+          }
+        })
+      }
+    }
+  )+ };
+}
+
+from_item_int! { i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize }
+
+macro_rules! from_item_float {
+  ( $( $ty:ty )+ ) => { $(
+    impl FromItem for $ty {
+      fn from_item(item: &Item) -> Result<Self, FromItemError> {
+        text(item)
+          .and_then(|text| text.parse().ok())
+          .ok_or(FromItemError::new("must be a number"))
+      }
+    }
+  )+ };
+}
+
+from_item_float! { f32 f64 }
+
+impl<T: FromItem> FromItem for Option<T> {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    match item {
+      Item::List(list) if list.is_empty() => Ok(None),
+      _ => T::from_item(item).map(Some),
+    }
+  }
+}
+
+impl<T: FromItem> FromItem for Vec<T> {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    let Item::List(list) = item else {
+      return Err(FromItemError::new("must be a list"));
+    };
+    let convert = |(i, item): (usize, &Item)| {
+      T::from_item(item).map_err(|err| err.within(key_item(&i.to_string())))
+    };
+    list.iter().enumerate().map(convert).collect()
+  }
+}
+
+// Convert the entries of a map or the empty list
+fn entries<K, T, C>(item: &Item) -> Result<C, FromItemError>
+where
+  K: FromItem,
+  T: FromItem,
+  C: FromIterator<(K, T)>,
+{
+  let map = match item {
+    Item::Map(map) => map,
+    Item::List(list) if list.is_empty() => return Ok(C::from_iter([])),
+    _ => return Err(FromItemError::new("must be a map")),
+  };
+  let convert = |(key, value): &(Item, Item)| {
+    let within = |err: FromItemError| err.within(key.clone());
+    Ok((
+      K::from_item(key).map_err(within)?,
+      T::from_item(value).map_err(within)?,
+    ))
+  };
+  map.0.iter().map(convert).collect()
+}
+
+impl<K: FromItem + Ord, T: FromItem> FromItem for BTreeMap<K, T> {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    entries(item)
+  }
+}
+
+impl<K: FromItem + Eq + Hash, T: FromItem> FromItem for HashMap<K, T> {
+  fn from_item(item: &Item) -> Result<Self, FromItemError> {
+    entries(item)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use super::FromItem;
+  use crate::{parse, Item};
+
+  fn doc(s: &str) -> Item {
+    parse(s.as_bytes()).unwrap()
+  }
+
+  fn atom(s: &str) -> Item {
+    Item::new_atom(s.as_bytes())
+  }
+
+  #[test]
+  fn atoms() {
+    assert_eq!(u16::from_item(&atom("8080")), Ok(8080));
+    assert_eq!(i8::from_item(&atom("-3")), Ok(-3));
+    assert_eq!(f64::from_item(&atom("1.5")), Ok(1.5));
+    assert_eq!(bool::from_item(&atom("true")), Ok(true));
+    assert_eq!(String::from_item(&atom("ä")), Ok("ä".to_string()));
+
+    let err = |r: Result<_, super::FromItemError>| r.unwrap_err().to_string();
+    assert_eq!(err(u8::from_item(&atom("256")).map(|_| ())), "is out of range");
+    assert_eq!(err(u8::from_item(&atom("x")).map(|_| ())), "must be a number");
+    assert_eq!(
+      err(bool::from_item(&atom("1")).map(|_| ())),
+      "must be true or false"
+    );
+    let bad = Item::new_atom(b"\xff");
+    assert_eq!(err(String::from_item(&bad).map(|_| ())), "must be text");
+  }
+
+  #[test]
+  fn compounds() {
+    assert_eq!(Vec::<u32>::from_item(&doc("1 2 3")), Ok(vec![1, 2, 3]));
+    assert_eq!(Option::<u32>::from_item(&doc("")), Ok(None));
+    assert_eq!(Option::<u32>::from_item(&atom("4")), Ok(Some(4)));
+
+    let map = BTreeMap::<String, u32>::from_item(&doc("b: 2 a: 1")).unwrap();
+    assert_eq!(
+      map.into_iter().collect::<Vec<_>>(),
+      [("a".to_string(), 1), ("b".to_string(), 2)]
+    );
+    assert_eq!(
+      BTreeMap::<String, u32>::from_item(&doc("")),
+      Ok(BTreeMap::new())
+    );
+  }
+
+  #[test]
+  fn error_paths() {
+    let item = doc("ports: (1 2 x) names: (a: 1 b: (c))");
+    let err = item.field::<Vec<u16>>("ports").unwrap_err();
+    assert_eq!(err.to_string(), "ports/2 must be a number");
+    assert_eq!(err.path(), &"ports/2".parse().unwrap());
+
+    let err = item.field::<BTreeMap<String, u8>>("names").unwrap_err();
+    assert_eq!(err.to_string(), "names/b must be a number");
+
+    let err = item.field::<u8>("missing").unwrap_err();
+    assert_eq!(err.to_string(), "missing is missing");
+    assert_eq!(item.opt_field::<u8>("missing"), Ok(None));
+    assert_eq!(
+      atom("x").opt_field::<u8>("y").unwrap_err().reason(),
+      "must be a map"
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,377 @@
+use std::fmt;
+
+use crate::canonical::write_item;
+use crate::{Item, List, Map, Path};
+
+/// A change between two items, see [`diff()`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Change {
+  Added { path: Path, value: Item },
+  Removed { path: Path, value: Item },
+  Changed { path: Path, old: Item, new: Item },
+}
+
+impl Change {
+  pub fn path(&self) -> &Path {
+    match self {
+      Change::Added { path, .. } => path,
+      Change::Removed { path, .. } => path,
+      Change::Changed { path, .. } => path,
+    }
+  }
+}
+
+/// The structural difference between two items, see [`diff()`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Diff(Vec<Change>);
+
+impl Diff {
+  pub fn changes(&self) -> &[Change] {
+    &self.0
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Change> {
+    self.0.iter()
+  }
+
+  /// A human-readable report, one line per change, optionally with colours.
+  ///
+  /// ```
+  /// # use axp::{diff, parse};
+  /// let old = parse(b"port: 80 debug: true").unwrap();
+  /// let new = parse(b"port: 8080 host: localhost").unwrap();
+  /// assert_eq!(
+  ///   diff(&old, &new).report(false),
+  ///   "~ port: 80 -> 8080\n- debug: true\n+ host: localhost\n"
+  /// );
+  /// ```
+  pub fn report(&self, colour: bool) -> String {
+    let paint = |code: &str, line: String| {
+      if colour {
+        format!("\x1b[{code}m{line}\x1b[0m\n")
+      } else {
+        format!("{line}\n")
+      }
+    };
+
+    let mut report = String::new();
+    for change in self.iter() {
+      let path = match change.path() {
+        path if path.is_root() => "/".to_owned(),
+        path => path.to_string(),
+      };
+      report.push_str(&match change {
+        Change::Added { value, .. } => {
+          paint("32", format!("+ {path}: {}", text(value)))
+        }
+        Change::Removed { value, .. } => {
+          paint("31", format!("- {path}: {}", text(value)))
+        }
+        Change::Changed { old, new, .. } => {
+          paint("33", format!("~ {path}: {} -> {}", text(old), text(new)))
+        }
+      });
+    }
+    report
+  }
+
+  /// The diff as axp document: a list of maps with the keys `op` (`add`,
+  /// `remove` or `replace`), `path`, `value` and for `replace` also `old`.
+  ///
+  /// ```
+  /// # use axp::{diff, parse, canonical};
+  /// let old = parse(b"server: (port: 80)").unwrap();
+  /// let new = parse(b"server: (port: 8080)").unwrap();
+  /// let item = diff(&old, &new).to_item();
+  /// assert_eq!(
+  ///   canonical(&item),
+  ///   b"(op: replace path: server/port old: 80 value: 8080)"
+  /// );
+  /// ```
+  pub fn to_item(&self) -> Item {
+    let atom = |s: &str| Item::new_atom(s.as_bytes());
+    let path = |path: &Path| atom(&path.to_string());
+    Item::new_list(self.iter().map(|change| match change {
+      Change::Added { path: p, value } => Item::new_map([
+        (atom("op"), atom("add")),
+        (atom("path"), path(p)),
+        (atom("value"), value.clone()),
+      ]),
+      Change::Removed { path: p, value } => Item::new_map([
+        (atom("op"), atom("remove")),
+        (atom("path"), path(p)),
+        (atom("value"), value.clone()),
+      ]),
+      Change::Changed { path: p, old, new } => Item::new_map([
+        (atom("op"), atom("replace")),
+        (atom("path"), path(p)),
+        (atom("old"), old.clone()),
+        (atom("value"), new.clone()),
+      ]),
+    }))
+  }
+}
+
+impl fmt::Display for Diff {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.report(false))
+  }
+}
+
+fn text(item: &Item) -> String {
+  let mut out = Vec::new();
+  write_item(item, &mut out);
+  String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Compute the structural difference between two items.
+///
+/// Map entries are matched by key, a map with duplicate keys is changed as a
+/// whole. List items are aligned by their longest common subsequence, so an
+/// insertion shows up as one added item. Items which are at the same place of
+/// a gap in the alignment are compared recursively.
+///
+/// The paths of removed items are paths into the old item, the paths of
+/// added and changed items are paths into the new item.
+///
+/// ```
+/// # use axp::{diff, parse};
+/// let old = parse(b"list: (a b c)").unwrap();
+/// let new = parse(b"list: (a x b c)").unwrap();
+/// assert_eq!(diff(&old, &new).report(false), "+ list/1: x\n");
+/// ```
+pub fn diff(old: &Item, new: &Item) -> Diff {
+  let mut changes = Vec::new();
+  diff_items(&Path::root(), old, new, &mut changes);
+  Diff(changes)
+}
+
+fn diff_items(path: &Path, old: &Item, new: &Item, changes: &mut Vec<Change>) {
+  match (old, new) {
+    _ if old == new => (),
+    (Item::Map(old), Item::Map(new)) => diff_maps(path, old, new, changes),
+    (Item::List(old), Item::List(new)) => diff_lists(path, old, new, changes),
+    _ => changes.push(Change::Changed {
+      path: path.clone(),
+      old: old.clone(),
+      new: new.clone(),
+    }),
+  }
+}
+
+// A path holds only the key, so a map with duplicate keys changes as a whole
+fn diff_maps(path: &Path, old: &Map, new: &Map, changes: &mut Vec<Change>) {
+  if has_duplicates(old) || has_duplicates(new) {
+    changes.push(Change::Changed {
+      path: path.clone(),
+      old: Item::Map(old.clone()),
+      new: Item::Map(new.clone()),
+    });
+    return;
+  }
+
+  for (key, old_value) in old.iter() {
+    match new.get(key) {
+      Some(new_value) => {
+        diff_items(&path.join(key.clone()), old_value, new_value, changes)
+      }
+      None => changes.push(Change::Removed {
+        path: path.join(key.clone()),
+        value: old_value.clone(),
+      }),
+    }
+  }
+
+  for (key, value) in new.iter().filter(|(key, _)| old.get(key).is_none()) {
+    changes.push(Change::Added {
+      path: path.join(key.clone()),
+      value: value.clone(),
+    })
+  }
+}
+
+fn has_duplicates(map: &Map) -> bool {
+  let mut keys = std::collections::HashSet::new();
+  !map.iter().all(|(key, _)| keys.insert(key))
+}
+
+fn diff_lists(path: &Path, old: &List, new: &List, changes: &mut Vec<Change>) {
+  let (old, new) = (&old.0, &new.0);
+  let mut pairs = lcs(old, new);
+  pairs.push((old.len(), new.len()));
+
+  let (mut i, mut j) = (0, 0);
+  for (next_i, next_j) in pairs {
+    let n = (next_i - i).min(next_j - j);
+    for k in 0..n {
+      diff_items(&path.join_index(j + k), &old[i + k], &new[j + k], changes);
+    }
+    for (k, value) in old.iter().enumerate().take(next_i).skip(i + n) {
+      let value = value.clone();
+      changes.push(Change::Removed { path: path.join_index(k), value });
+    }
+    for (k, value) in new.iter().enumerate().take(next_j).skip(j + n) {
+      let value = value.clone();
+      changes.push(Change::Added { path: path.join_index(k), value });
+    }
+    (i, j) = (next_i + 1, next_j + 1);
+  }
+}
+
+// The indices of the longest common subsequence of two lists, in linear
+// space with Hirschberg's algorithm after trimming the common ends
+fn lcs(old: &[Item], new: &[Item]) -> Vec<(usize, usize)> {
+  let ends = old.iter().zip(new);
+  let prefix = ends.take_while(|(old, new)| old == new).count();
+  let (old_rest, new_rest) = (&old[prefix..], &new[prefix..]);
+  let ends = old_rest.iter().rev().zip(new_rest.iter().rev());
+  let suffix = ends.take_while(|(old, new)| old == new).count();
+
+  let mut pairs: Vec<_> = (0..prefix).map(|k| (k, k)).collect();
+  let old_middle = &old_rest[..old_rest.len() - suffix];
+  let new_middle = &new_rest[..new_rest.len() - suffix];
+  hirschberg(old_middle, new_middle, (prefix, prefix), &mut pairs);
+  let (i, j) = (old.len() - suffix, new.len() - suffix);
+  pairs.extend((0..suffix).map(|k| (i + k, j + k)));
+  pairs
+}
+
+// Push the pairs of the lcs of two lists at the offsets into the whole lists
+fn hirschberg(
+  old: &[Item],
+  new: &[Item],
+  (i, j): (usize, usize),
+  pairs: &mut Vec<(usize, usize)>,
+) {
+  match old {
+    [] => return,
+    [item] => {
+      if let Some(k) = new.iter().position(|new| new == item) {
+        pairs.push((i, j + k));
+      }
+      return;
+    }
+    _ if new.is_empty() => return,
+    _ => (),
+  }
+
+  // Split new where the lcs of the halves of old are longest together
+  let mid = old.len() / 2;
+  let front = lengths(old[..mid].iter(), new.iter());
+  let back = lengths(old[mid..].iter().rev(), new.iter().rev());
+  let m = new.len();
+  let split = (0..=m).max_by_key(|&k| (front[k] + back[m - k], k)).unwrap();
+
+  hirschberg(&old[..mid], &new[..split], (i, j), pairs);
+  hirschberg(&old[mid..], &new[split..], (i + mid, j + split), pairs);
+}
+
+// The lengths of the lcs of old and the first k items of new for all k
+fn lengths<'i>(
+  old: impl Iterator<Item = &'i Item>,
+  new: impl Iterator<Item = &'i Item> + Clone,
+) -> Vec<usize> {
+  let mut row = vec![0; new.clone().count() + 1];
+  for old in old {
+    let mut diagonal = 0;
+    for (k, new) in new.clone().enumerate() {
+      let above = row[k + 1];
+      row[k + 1] = if old == new {
+        diagonal + 1
+      } else {
+        above.max(row[k])
+      };
+      diagonal = above;
+    }
+  }
+  row
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{diff, Change};
+  use crate::{parse, Item};
+
+  fn report(old: &str, new: &str) -> String {
+    let old = parse(old.as_bytes()).unwrap();
+    let new = parse(new.as_bytes()).unwrap();
+    diff(&old, &new).report(false)
+  }
+
+  #[test]
+  fn equal() {
+    assert_eq!(report("a: (b c)", "a:(b c) # comment"), "");
+  }
+
+  #[test]
+  fn maps() {
+    assert_eq!(
+      report("a: 1 b: (x: 1 y: 2) c: 3", "b: (x: 1 y: 3) c: 3 d: 4"),
+      "- a: 1\n~ b/y: 2 -> 3\n+ d: 4\n"
+    );
+    assert_eq!(
+      report("k: 1 k: 2", "k: 1 k: 3"),
+      "~ /: (k: 1 k: 2) -> (k: 1 k: 3)\n"
+    );
+    assert_eq!(
+      report("a: (k: 1 k: 2)", "a: (k: 1)"),
+      "~ a: (k: 1 k: 2) -> (k: 1)\n"
+    );
+  }
+
+  #[test]
+  fn lists() {
+    assert_eq!(report("a b c d", "a c d e"), "- 1: b\n+ 3: e\n");
+    assert_eq!(report("a b c", "a (x: 1) c"), "~ 1: b -> (x: 1)\n");
+    assert_eq!(report("(x: 1) b", "(x: 2) b"), "~ 0/x: 1 -> 2\n");
+    assert_eq!(report("a b", ""), "- 0: a\n- 1: b\n");
+    assert_eq!(
+      report("a b c a b b a", "c b a b a c"),
+      "~ 0: a -> c\n- 2: c\n- 5: b\n+ 5: c\n"
+    );
+  }
+
+  #[test]
+  fn long_lists() {
+    let list = |items: &[String]| {
+      Item::new_list(items.iter().map(|item| Item::new_atom(item.as_bytes())))
+    };
+    let old: Vec<_> = (0..3000).map(|i| i.to_string()).collect();
+    let mut new = old.clone();
+    new[0] = "x".into();
+    new.remove(1500);
+    new.push("y".into());
+    assert_eq!(
+      diff(&list(&old), &list(&new)).report(false),
+      "~ 0: 0 -> x\n- 1500: 1500\n+ 2999: y\n"
+    );
+  }
+
+  #[test]
+  fn root() {
+    let old = Item::new_atom(b"a");
+    let new = Item::new_list([]);
+    let diff = diff(&old, &new);
+    assert_eq!(diff.report(false), "~ /: a -> ()\n");
+    assert!(
+      matches!(&diff.changes()[0], Change::Changed { path, .. } if path.is_root())
+    );
+  }
+
+  #[test]
+  fn colour() {
+    assert_eq!(report("a: 1", "a: 1 b: 2").replace('\n', ""), "+ b: 2");
+    let old = parse(b"a: 1").unwrap();
+    let new = parse(b"b: 2").unwrap();
+    assert_eq!(
+      diff(&old, &new).report(true),
+      "\x1b[31m- a: 1\x1b[0m\n\x1b[32m+ b: 2\x1b[0m\n"
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
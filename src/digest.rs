@@ -0,0 +1,162 @@
+use crate::canonical::write_atom;
+use crate::Item;
+
+/// A hash function to compute the digest of an item, see [`Item::digest()`].
+///
+/// Implement it to plug in your own hash function. [`Fnv1a`] is built in, and
+/// with the feature `sha256` there is an implementation for `sha2::Sha256`.
+pub trait ContentHasher: Default {
+  type Output;
+
+  fn update(&mut self, bytes: &[u8]);
+
+  fn finish(self) -> Self::Output;
+}
+
+/// The 64 bit FNV-1a hash, fast and stable but not cryptographic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+  fn default() -> Self {
+    Fnv1a(0xcbf29ce484222325)
+  }
+}
+
+impl ContentHasher for Fnv1a {
+  type Output = u64;
+
+  fn update(&mut self, bytes: &[u8]) {
+    for &byte in bytes {
+      self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+  }
+
+  fn finish(self) -> u64 {
+    self.0
+  }
+}
+
+#[cfg(feature = "sha256")]
+impl ContentHasher for sha2::Sha256 {
+  type Output = [u8; 32];
+
+  fn update(&mut self, bytes: &[u8]) {
+    sha2::Digest::update(self, bytes)
+  }
+
+  fn finish(self) -> [u8; 32] {
+    sha2::Digest::finalize(self).into()
+  }
+}
+
+impl Item {
+  /// Hash the canonical form of the item, see [`canonical()`], with the
+  /// parentheses of the top level and whether it is an atom, list or map.
+  /// Empty maps are hashed as `(:)`, which no item is written as, so that
+  /// they differ from empty lists at every level.
+  ///
+  /// The digest does not depend on formatting: documents that differ only in
+  /// white space or comments have the same digest, while unequal items such
+  /// as an atom and a list of it have different digests.
+  ///
+  /// ```
+  /// # use axp::{parse, Fnv1a};
+  /// let a = parse(b"name: x # comment\nlist: ( 1 2 )").unwrap();
+  /// let b = parse(b"name: x list: (1 2)").unwrap();
+  /// assert_eq!(a.digest::<Fnv1a>(), b.digest::<Fnv1a>());
+  /// ```
+  ///
+  /// [`canonical()`]: crate::canonical
+  pub fn digest<H: ContentHasher>(&self) -> H::Output {
+    let kind = match self {
+      Item::Atom(_) => b'a',
+      Item::List(_) => b'l',
+      Item::Map(_) => b'm',
+    };
+    let mut out = vec![kind];
+    write_digested(self, &mut out);
+    let mut hasher = H::default();
+    hasher.update(&out);
+    hasher.finish()
+  }
+}
+
+// Like canonical::write_item() but with `(:)` for empty maps
+fn write_digested(item: &Item, out: &mut Vec<u8>) {
+  match item {
+    Item::Atom(atom) => write_atom(atom, out),
+    Item::Map(map) if map.is_empty() => out.extend_from_slice(b"(:)"),
+    Item::List(list) => {
+      out.push(b'(');
+      for (i, item) in list.iter().enumerate() {
+        if i > 0 {
+          out.push(b' ');
+        }
+        write_digested(item, out);
+      }
+      out.push(b')');
+    }
+    Item::Map(map) => {
+      out.push(b'(');
+      for (i, (key, value)) in map.iter().enumerate() {
+        if i > 0 {
+          out.push(b' ');
+        }
+        write_digested(key, out);
+        out.extend_from_slice(b": ");
+        write_digested(value, out);
+      }
+      out.push(b')');
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Fnv1a;
+  use crate::{parse, Item};
+
+  #[test]
+  fn digest() {
+    let a = parse(b"a: 1 b: (x y)").unwrap();
+    let b = parse(b"a:1\n\nb: ( x\n y ) # comment").unwrap();
+    let c = parse(b"a: 1 b: (y x)").unwrap();
+    assert_eq!(a.digest::<Fnv1a>(), b.digest::<Fnv1a>());
+    assert_ne!(a.digest::<Fnv1a>(), c.digest::<Fnv1a>());
+  }
+
+  #[test]
+  fn unequal_items() {
+    let items = [
+      Item::new_atom(b"a"),
+      parse(b"a").unwrap(),
+      parse(b"(a)").unwrap(),
+      Item::nil(),
+      Item::new_map([]),
+      Item::new_list([Item::nil()]),
+      Item::new_list([Item::new_map([])]),
+      Item::new_list([Item::new_list([Item::nil()])]),
+      Item::new_list([Item::new_list([Item::new_map([])])]),
+      Item::new_map([(Item::new_atom(b"k"), Item::nil())]),
+      Item::new_map([(Item::new_atom(b"k"), Item::new_map([]))]),
+      parse(b"a b").unwrap(),
+      parse(b"\"a b\"").unwrap(),
+    ];
+    for (i, a) in items.iter().enumerate() {
+      for b in &items[i + 1..] {
+        assert_ne!(a, b);
+        assert_ne!(a.digest::<Fnv1a>(), b.digest::<Fnv1a>(), "{a:?} {b:?}");
+      }
+    }
+  }
+
+  #[cfg(feature = "sha256")]
+  #[test]
+  fn sha256() {
+    let digest = parse(b"a").unwrap().digest::<sha2::Sha256>();
+    assert_eq!(digest[..4], [0x63, 0x3e, 0x3e, 0xb6]);
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
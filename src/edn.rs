@@ -0,0 +1,587 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::atom::is_tag;
+use crate::{Atom, Item, Path, Span};
+
+const KEYWORD: &[u8] = b"keyword";
+const SYMBOL: &[u8] = b"symbol";
+const CHAR: &[u8] = b"char";
+
+// Elements nested deeper are an error, as in the default BinaryLimits
+const MAX_DEPTH: usize = 128;
+
+const NAMED_CHARS: [(&str, char); 4] =
+  [("newline", '\n'), ("space", ' '), ("tab", '\t'), ("return", '\r')];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EdnError {
+  /// The input is not valid EDN
+  Read { span: Span, reason: String },
+  /// The item at the path has no EDN form
+  Write { path: Path, reason: String },
+}
+
+impl Error for EdnError {}
+
+impl EdnError {
+  pub fn reason(&self) -> &str {
+    match self {
+      EdnError::Read { reason, .. } => reason,
+      EdnError::Write { reason, .. } => reason,
+    }
+  }
+}
+
+impl fmt::Display for EdnError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      EdnError::Read { span, reason } => {
+        write!(f, "EdnError: {span}: {reason}")
+      }
+      EdnError::Write { path, reason } if path.is_root() => {
+        write!(f, "EdnError: {reason}")
+      }
+      EdnError::Write { path, reason } => {
+        write!(f, "EdnError: {path}: {reason}")
+      }
+    }
+  }
+}
+
+/// Read EDN, one item per top level element.
+///
+/// - Maps become maps, vectors, lists and sets become lists
+/// - Strings, numbers, `true`, `false` and `nil` become atoms of their text
+/// - Keywords, symbols and characters become atoms tagged `keyword`,
+///   `symbol` and `char`, `:a` is `#(keyword)"a#(keyword)"`
+/// - A tagged element like `#inst "1985-04-12"` becomes an atom with that
+///   tag, the element must be a string or a number and the tag a valid
+///   [`Atom`] tag
+/// - Elements nested deeper than 128 are an error
+///
+/// ```
+/// # use axp::{from_edn, parse, Item};
+/// let items = from_edn(r#"{:ports [80 443], :at #inst "2023-10-14"} x"#);
+/// let expected = parse(br#"
+///   (#(keyword)"ports#(keyword)": (80 443) #(keyword)"at#(keyword)": #(inst)"2023-10-14#(inst)")
+///   #(symbol)"x#(symbol)"
+/// "#);
+/// assert_eq!(Item::new_list(items.unwrap()), expected.unwrap());
+/// ```
+pub fn from_edn(input: &str) -> Result<Vec<Item>, EdnError> {
+  let mut reader = Reader {
+    input: input.as_bytes(),
+    pos: 0,
+    lines: Span::lines(input.as_bytes()),
+    depth: 0,
+  };
+  let mut items = Vec::new();
+  while reader.skip()? {
+    items.push(reader.element()?);
+  }
+  Ok(items)
+}
+
+fn is_delimiter(byte: u8) -> bool {
+  byte.is_ascii_whitespace() || b",()[]{}\";".contains(&byte)
+}
+
+struct Reader<'a> {
+  input: &'a [u8],
+  pos: usize,
+  lines: Vec<usize>,
+  depth: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn error(&self, start: usize, reason: impl Into<String>) -> EdnError {
+    let span = Span::new(self.input, &self.lines, start, self.pos);
+    EdnError::Read { span, reason: reason.into() }
+  }
+
+  // Read one level deeper
+  fn nested<T>(
+    &mut self,
+    read: impl FnOnce(&mut Self) -> Result<T, EdnError>,
+  ) -> Result<T, EdnError> {
+    if self.depth == MAX_DEPTH {
+      let reason = format!("nested deeper than {MAX_DEPTH}");
+      return Err(self.error(self.pos, reason));
+    }
+    self.depth += 1;
+    let result = read(self);
+    self.depth -= 1;
+    result
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.input.get(self.pos).copied()
+  }
+
+  // Skip white space, commas, comments and discarded elements, false at the
+  // end of the input
+  fn skip(&mut self) -> Result<bool, EdnError> {
+    loop {
+      match self.peek() {
+        None => return Ok(false),
+        Some(b';') => {
+          while self.peek().is_some_and(|byte| byte != b'\n') {
+            self.pos += 1;
+          }
+        }
+        Some(byte) if byte.is_ascii_whitespace() || byte == b',' => {
+          self.pos += 1
+        }
+        Some(b'#') if self.input[self.pos..].starts_with(b"#_") => {
+          let start = self.pos;
+          self.pos += 2;
+          self.nested(|reader| {
+            if reader.skip()? {
+              reader.element()
+            } else {
+              Err(reader.error(start, "nothing to discard"))
+            }
+          })?;
+        }
+        Some(_) => return Ok(true),
+      }
+    }
+  }
+
+  fn token(&mut self) -> &'a str {
+    let start = self.pos;
+    while self.peek().is_some_and(|byte| !is_delimiter(byte)) {
+      self.pos += 1;
+    }
+    // the input is a str and delimiters are ascii
+    std::str::from_utf8(&self.input[start..self.pos]).unwrap()
+  }
+
+  fn element(&mut self) -> Result<Item, EdnError> {
+    self.nested(Self::read_element)
+  }
+
+  fn read_element(&mut self) -> Result<Item, EdnError> {
+    let start = self.pos;
+    let tagged =
+      |tag, text: &str| Item::Atom(Atom(text.as_bytes().to_vec(), Some(tag)));
+    match self.peek() {
+      Some(b'(') => self.sequence(b')').map(Item::new_list),
+      Some(b'[') => self.sequence(b']').map(Item::new_list),
+      Some(b'{') => {
+        let items = self.sequence(b'}')?;
+        if items.len() % 2 == 1 {
+          return Err(self.error(start, "map needs pairs of elements"));
+        }
+        let mut items = items.into_iter();
+        let entries =
+          std::iter::from_fn(|| Some((items.next()?, items.next()?)));
+        Ok(Item::new_map(entries.collect::<Vec<_>>()))
+      }
+      Some(b'#') if self.input[self.pos..].starts_with(b"#{") => {
+        self.pos += 1;
+        self.sequence(b'}').map(Item::new_list)
+      }
+      Some(b'#') => {
+        self.pos += 1;
+        let tag = self.token();
+        if !self.skip()? {
+          return Err(self.error(start, format!("#{tag} needs an element")));
+        }
+        match self.element()? {
+          Item::Atom(Atom(atom, None)) if is_tag(tag.as_bytes()) => {
+            Ok(Item::Atom(Atom(atom, Some(tag.as_bytes().to_vec()))))
+          }
+          Item::Atom(Atom(_, None)) => {
+            Err(self.error(start, format!("#{tag} is not a valid tag")))
+          }
+          _ => Err(self.error(start, format!("#{tag} needs a string"))),
+        }
+      }
+      Some(b'"') => self.string(),
+      Some(b'\\') => {
+        self.pos += 1;
+        // the first character can be a delimiter
+        let first = std::str::from_utf8(&self.input[self.pos..])
+          .ok()
+          .and_then(|rest| rest.chars().next());
+        let Some(first) = first else {
+          return Err(self.error(start, "character expected"));
+        };
+        self.pos += first.len_utf8();
+        let name = format!("{first}{}", self.token());
+        let named = NAMED_CHARS.iter().find(|(n, _)| *n == name);
+        let char = match named {
+          Some((_, char)) => *char,
+          None if name.chars().count() == 1 => first,
+          None => match name.strip_prefix('u').map(unicode) {
+            Some(Some(char)) => char,
+            _ => {
+              return Err(
+                self.error(start, format!("unknown character {name}")),
+              )
+            }
+          },
+        };
+        Ok(tagged(CHAR.to_vec(), &char.to_string()))
+      }
+      Some(b':') => {
+        self.pos += 1;
+        match self.token() {
+          "" => Err(self.error(start, "keyword needs a name")),
+          name => Ok(tagged(KEYWORD.to_vec(), name)),
+        }
+      }
+      Some(byte @ (b')' | b']' | b'}')) => {
+        self.pos += 1;
+        Err(self.error(start, format!("unexpected {}", byte as char)))
+      }
+      _ => match self.token() {
+        token @ ("nil" | "true" | "false") => {
+          Ok(Item::new_atom(token.as_bytes()))
+        }
+        token if is_numeric(token) => Ok(Item::new_atom(token.as_bytes())),
+        token => Ok(tagged(SYMBOL.to_vec(), token)),
+      },
+    }
+  }
+
+  // The elements up to the closing delimiter
+  fn sequence(&mut self, close: u8) -> Result<Vec<Item>, EdnError> {
+    let start = self.pos;
+    self.pos += 1;
+    let mut items = Vec::new();
+    loop {
+      if !self.skip()? {
+        let reason = format!("{} not closed", self.input[start] as char);
+        return Err(self.error(start, reason));
+      }
+      if self.peek() == Some(close) {
+        self.pos += 1;
+        return Ok(items);
+      }
+      items.push(self.element()?);
+    }
+  }
+
+  fn string(&mut self) -> Result<Item, EdnError> {
+    let start = self.pos;
+    self.pos += 1;
+    let mut out = String::new();
+    let rest = std::str::from_utf8(&self.input[self.pos..]).unwrap();
+    let mut chars = rest.char_indices();
+    while let Some((i, char)) = chars.next() {
+      let escaped = match char {
+        '"' => {
+          self.pos += i + 1;
+          return Ok(Item::new_atom(out.as_bytes()));
+        }
+        '\\' => chars.next().map(|(_, char)| char),
+        char => {
+          out.push(char);
+          continue;
+        }
+      };
+      let char = match escaped {
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some(char @ ('"' | '\\')) => char,
+        Some('u') => {
+          let hex = chars.by_ref().take(4).map(|(_, char)| char);
+          match unicode(&hex.collect::<String>()) {
+            Some(char) => char,
+            None => {
+              self.pos += i;
+              return Err(self.error(self.pos, "bad \\u escape"));
+            }
+          }
+        }
+        _ => {
+          self.pos += i;
+          return Err(self.error(self.pos, "unknown escape"));
+        }
+      };
+      out.push(char);
+    }
+    self.pos = self.input.len();
+    Err(self.error(start, "string not closed"))
+  }
+}
+
+fn unicode(hex: &str) -> Option<char> {
+  if hex.len() != 4 {
+    return None;
+  }
+  char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+}
+
+// Starts like a number: a digit, optionally after a sign
+fn is_numeric(token: &str) -> bool {
+  let unsigned = token.strip_prefix(['+', '-']).unwrap_or(token);
+  unsigned.starts_with(|char: char| char.is_ascii_digit())
+}
+
+// A number in EDN: integers with an optional `N`, floats with an optional
+// `M`
+fn is_number(text: &str) -> bool {
+  let number = text.strip_suffix(['N', 'M']).unwrap_or(text);
+  let unsigned = number.strip_prefix(['+', '-']).unwrap_or(number);
+  let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+  let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+    Some((mantissa, exponent)) => {
+      let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+      (mantissa, Some(exponent))
+    }
+    None => (unsigned, None),
+  };
+  let (int, frac) = match mantissa.split_once('.') {
+    Some((int, frac)) => (int, Some(frac)),
+    None => (mantissa, None),
+  };
+  let float = frac.is_some() || exponent.is_some();
+  digits(int)
+    && frac.is_none_or(|frac| frac.is_empty() || digits(frac))
+    && exponent.is_none_or(digits)
+    && !(float && text.ends_with('N'))
+}
+
+// A symbol or keyword name which reads back the same
+fn is_symbol(text: &str) -> bool {
+  !text.is_empty()
+    && !text.bytes().any(is_delimiter)
+    && !text.starts_with([':', '\\', '#'])
+    && !is_numeric(text)
+    && !matches!(text, "nil" | "true" | "false")
+}
+
+/// Write an item as EDN.
+///
+/// Maps become maps and lists become vectors. Atoms tagged `keyword`,
+/// `symbol` and `char` become those, other tags become tagged elements.
+/// Untagged atoms which are numbers, `true`, `false` and `nil` are written
+/// bare, the rest as strings. Atoms which are not UTF-8 are errors.
+///
+/// ```
+/// # use axp::{parse, to_edn};
+/// let item = parse(br##"#(keyword)"a#(keyword)": (1 "x y" #(symbol)"z#(symbol)" #(inst)"2023#(inst)")"##);
+/// let edn = to_edn(&item.unwrap()).unwrap();
+/// assert_eq!(edn, r#"{:a [1 "x y" z #inst "2023"]}"#);
+/// ```
+pub fn to_edn(item: &Item) -> Result<String, EdnError> {
+  let mut out = String::new();
+  write_element(&Path::root(), item, &mut out)?;
+  Ok(out)
+}
+
+fn write_element(
+  path: &Path,
+  item: &Item,
+  out: &mut String,
+) -> Result<(), EdnError> {
+  match item {
+    Item::Atom(atom) => write_atom(path, atom, out)?,
+    Item::List(list) => {
+      out.push('[');
+      for (i, item) in list.iter().enumerate() {
+        if i > 0 {
+          out.push(' ');
+        }
+        write_element(&path.join_index(i), item, out)?;
+      }
+      out.push(']');
+    }
+    Item::Map(map) => {
+      out.push('{');
+      for (i, (key, value)) in map.iter().enumerate() {
+        if i > 0 {
+          out.push_str(", ");
+        }
+        let path = path.join(key.clone());
+        write_element(&path, key, out)?;
+        out.push(' ');
+        write_element(&path, value, out)?;
+      }
+      out.push('}');
+    }
+  }
+  Ok(())
+}
+
+fn write_atom(
+  path: &Path,
+  atom: &Atom,
+  out: &mut String,
+) -> Result<(), EdnError> {
+  let error = |reason: String| EdnError::Write { path: path.clone(), reason };
+  let Ok(text) = std::str::from_utf8(atom.as_bytes()) else {
+    return Err(error("atom is not UTF-8".to_string()));
+  };
+
+  match atom.tag() {
+    Some(KEYWORD) if is_symbol(text) => out.push_str(&format!(":{text}")),
+    Some(SYMBOL) if is_symbol(text) => out.push_str(text),
+    Some(tag @ (KEYWORD | SYMBOL)) => {
+      let tag = String::from_utf8_lossy(tag);
+      return Err(error(format!("{text} is not a {tag}")));
+    }
+    Some(CHAR) => {
+      let mut chars = text.chars();
+      let (Some(char), None) = (chars.next(), chars.next()) else {
+        return Err(error(format!("{text} is not a char")));
+      };
+      out.push('\\');
+      match NAMED_CHARS.iter().find(|(_, c)| *c == char) {
+        Some((name, _)) => out.push_str(name),
+        None if char.is_control() => {
+          out.push_str(&format!("u{:04x}", char as u32))
+        }
+        None => out.push(char),
+      }
+    }
+    Some(tag) => {
+      let tag = String::from_utf8_lossy(tag);
+      if !tag.starts_with(|char: char| char.is_ascii_alphabetic())
+        || !is_symbol(&tag)
+      {
+        return Err(error(format!("tag {tag} is not an EDN tag")));
+      }
+      out.push_str(&format!("#{tag} "));
+      write_string(text, out);
+    }
+    None if is_number(text) || matches!(text, "nil" | "true" | "false") => {
+      out.push_str(text)
+    }
+    None => write_string(text, out),
+  }
+  Ok(())
+}
+
+fn write_string(text: &str, out: &mut String) {
+  out.push('"');
+  for char in text.chars() {
+    match char {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\t' => out.push_str("\\t"),
+      '\r' => out.push_str("\\r"),
+      char if char.is_control() => {
+        out.push_str(&format!("\\u{:04x}", char as u32))
+      }
+      char => out.push(char),
+    }
+  }
+  out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_edn, to_edn};
+  use crate::{canonical, parse, Atom, Item};
+
+  fn read(input: &str) -> Result<String, String> {
+    let items = from_edn(input).map_err(|err| err.to_string())?;
+    let item = Item::new_list(items);
+    Ok(String::from_utf8(canonical(&item)).unwrap())
+  }
+
+  fn write(input: &str) -> Result<String, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    to_edn(&item).map_err(|err| err.to_string())
+  }
+
+  #[test]
+  fn reads() {
+    assert_eq!(
+      read(
+        r#"nil true 42 -1.5M 7N "a\"\u00e4\n" ; comment
+        :k/w sym + - \a \space \u0041 \( #_ [discarded] x"#
+      )
+      .unwrap(),
+      r##"nil true 42 -1.5M 7N "a\"ä\n" #(keyword)"k/w#(keyword)" #(symbol)"sym#(symbol)" "##
+        .to_string()
+        + r##"#(symbol)"+#(symbol)" #(symbol)"-#(symbol)" #(char)"a#(char)" #(char)" #(char)" #(char)"A#(char)" "##
+        + r##"#(char)"(#(char)" #(symbol)"x#(symbol)""##
+    );
+    assert_eq!(
+      read("{:a [1 2] :b #{(x)} \"c\" {}} #uuid \"f81d\"").unwrap(),
+      r##"(#(keyword)"a#(keyword)": (1 2) #(keyword)"b#(keyword)": ((#(symbol)"x#(symbol)")) c: ()) "##
+        .to_string()
+        + r##"#(uuid)"f81d#(uuid)""##
+    );
+    assert_eq!(read("").unwrap(), "");
+  }
+
+  #[test]
+  fn read_errors() {
+    let err = |input: &str| read(input).unwrap_err();
+    assert_eq!(err("[1 2"), "EdnError: 1:1: [ not closed");
+    assert_eq!(err("(\n)]"), "EdnError: 2:2: unexpected ]");
+    assert_eq!(err("{:a}"), "EdnError: 1:1: map needs pairs of elements");
+    assert_eq!(err(" \"a\\q\""), "EdnError: 1:4: unknown escape");
+    assert_eq!(err("\"a"), "EdnError: 1:1: string not closed");
+    assert_eq!(err("#inst [1]"), "EdnError: 1:1: #inst needs a string");
+    assert_eq!(
+      err("#toolongtag 1"),
+      "EdnError: 1:1: #toolongtag is not a valid tag"
+    );
+    assert_eq!(err("\\xy"), "EdnError: 1:1: unknown character xy");
+    assert_eq!(err(": 1"), "EdnError: 1:1: keyword needs a name");
+    assert_eq!(err("#_"), "EdnError: 1:1: nothing to discard");
+
+    let deep = "[".repeat(128) + &"]".repeat(128);
+    assert!(read(&deep).is_ok());
+    assert_eq!(
+      err(&format!("[{deep}]")),
+      "EdnError: 1:129: nested deeper than 128"
+    );
+    assert_eq!(
+      err(&"#_".repeat(200)),
+      "EdnError: 1:259: nested deeper than 128"
+    );
+    assert!(err(&"#a ".repeat(200)).ends_with("nested deeper than 128"));
+  }
+
+  #[test]
+  fn writes() {
+    assert_eq!(
+      write(r##"a: (1 1.5e3 1e 01 +2N 2.5N nil "" "a\"\x5cb\n" #(keyword)"x#(keyword)")"##)
+        .unwrap(),
+      r#"{"a" [1 1.5e3 "1e" 01 +2N "2.5N" nil "" "a\"\\b\n" :x]}"#
+    );
+    assert_eq!(
+      write(r##"#(char)"#(char)\n#(char)" #(char)"(#(char)" #(char)"#(char)\x01#(char)" #(symbol)"a/b#(symbol)""##).unwrap(),
+      r#"[\newline \( \u0001 a/b]"#
+    );
+
+    let input = r#"{:a [1 "x y" #inst "2023" \c], "b" {x nil}, [] #{}}"#;
+    let items = from_edn(input).unwrap();
+    let output = to_edn(&items[0]).unwrap();
+    assert_eq!(output, r#"{:a [1 "x y" #inst "2023" \c], "b" {x nil}, [] []}"#);
+    assert_eq!(from_edn(&output).unwrap(), items);
+  }
+
+  #[test]
+  fn write_errors() {
+    let err = |input| write(input).unwrap_err();
+    assert_eq!(
+      err(r##"#(keyword)"a b#(keyword)""##),
+      "EdnError: 0: a b is not a keyword"
+    );
+    assert_eq!(
+      err(r##"#(symbol)"1#(symbol)""##),
+      "EdnError: 0: 1 is not a symbol"
+    );
+    assert_eq!(
+      err(r##"a: #(char)"ab#(char)""##),
+      "EdnError: a: ab is not a char"
+    );
+    assert_eq!(err(r##"#(1)"x#(1)""##), "EdnError: 0: tag 1 is not an EDN tag");
+    let item = Item::Atom(Atom::new(b"\xff"));
+    assert_eq!(to_edn(&item).unwrap_err().reason(), "atom is not UTF-8");
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -1,99 +1,1615 @@
-#![allow(dead_code)]
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
 
-use crate::{Atom, Item, List};
+use crate::{FromItem, Item, List, Path, Span, Spans};
 
-pub fn evaluate(item: &Item) -> Item {
+static NO_SPANS: Spans = Spans(BTreeMap::new());
+
+/// What went wrong in an [`EvalError`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum EvalErrorKind {
+  /// The operator names no primitive or special form
+  UnknownOperator,
+  /// The operator got fewer or more arguments than it takes
+  ArityMismatch,
+  /// An operator or an argument is of the wrong kind of item
+  TypeError,
+  /// `set!` of a name which is not defined
+  UnboundSymbol,
+  /// The evaluation went beyond the [`EvalLimits`] of the interpreter
+  LimitExceeded,
+  /// Writing to the output of the interpreter failed
+  Io,
+  /// An exact number or result is out of the range of 64-bit integers
+  Overflow,
+  /// An exact number divided by zero
+  DivisionByZero,
+}
+
+/// An error evaluating a form, with the forms enclosing it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EvalError(Box<Details>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Details {
+  kind: EvalErrorKind,
+  reason: String,
+  form: Item,
+  span: Option<Span>,
+  stack: Vec<Item>,
+  // Whether form and span are set, the error is placed by the innermost call
+  placed: bool,
+}
+
+impl Error for EvalError {}
+
+impl EvalError {
+  pub fn kind(&self) -> EvalErrorKind {
+    self.0.kind
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.0.reason
+  }
+
+  /// The form which failed
+  pub fn form(&self) -> &Item {
+    &self.0.form
+  }
+
+  /// The span of the form, if it was evaluated with [`evaluate_spanned()`]
+  /// and is part of the input rather than built while evaluating
+  pub fn span(&self) -> Option<Span> {
+    self.0.span
+  }
+
+  /// The forms enclosing the form, innermost first
+  pub fn stack(&self) -> &[Item] {
+    &self.0.stack
+  }
+
+  // Record the form the error is from, or one enclosing it
+  fn within(mut self, form: &List, span: Option<Span>) -> EvalError {
+    let details = &mut self.0;
+    if details.placed {
+      details.stack.push(Item::List(form.clone()));
+    } else {
+      details.form = Item::List(form.clone());
+      details.span = span;
+      details.placed = true;
+    }
+    self
+  }
+}
+
+impl fmt::Display for EvalError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let details = &self.0;
+    match details.span {
+      Some(span) => write!(f, "EvalError: {span}: {}", details.reason)?,
+      None => write!(f, "EvalError: {}", details.reason)?,
+    }
+    write!(f, " in {}", details.form)?;
+    for form in &details.stack {
+      write!(f, "\n  in {form}")?;
+    }
+    Ok(())
+  }
+}
+
+pub(crate) fn error(
+  kind: EvalErrorKind,
+  reason: impl Into<String>,
+) -> EvalError {
+  let reason = reason.into();
+  let (form, span, stack) = (Item::nil(), None, Vec::new());
+  EvalError(Box::new(Details {
+    kind,
+    reason,
+    form,
+    span,
+    stack,
+    placed: false,
+  }))
+}
+
+/// Evaluate an item as a Lisp expression with a new [`Interpreter`].
+///
+/// Maps, the empty list and tagged atoms evaluate to themselves, other atoms
+/// to what they are bound to or themselves if unbound. A list is a call:
+/// special forms like `quote`, `if`, `define`, `let` and `lambda` get their
+/// arguments as they are, primitives and lambdas get them evaluated.
+///
+/// ```
+/// # use axp::{evaluate, parse, EvalErrorKind};
+/// let command = parse(b"first (tail (quote (a b c)))").unwrap();
+/// assert_eq!(format!("{}", evaluate(&command).unwrap()), "b");
+///
+/// let command = parse(b"first (frist (quote (a b c)))").unwrap();
+/// let err = evaluate(&command).unwrap_err();
+/// assert_eq!(err.kind(), EvalErrorKind::UnknownOperator);
+/// assert_eq!(
+///   err.to_string(),
+///   "EvalError: unknown operator frist in (frist (quote (a b c)))\n  \
+///   in (first (frist (quote (a b c))))"
+/// );
+/// ```
+pub fn evaluate(item: &Item) -> Result<Item, EvalError> {
+  Interpreter::new().evaluate(item)
+}
+
+/// Evaluate an item with the spans from [`parse_spanned()`] so that errors
+/// point at the input.
+///
+/// ```
+/// # use axp::{evaluate_spanned, parse_spanned};
+/// let (command, spans) = parse_spanned(b"if true\n  (first x)").unwrap();
+/// let err = evaluate_spanned(&command, &spans).unwrap_err();
+/// assert_eq!(err.span().map(|span| span.line), Some(2));
+/// assert_eq!(
+///   err.to_string(),
+///   "EvalError: 2:3: first takes a list, not x in (first x)\n  \
+///   in (if true (first x))"
+/// );
+/// ```
+///
+/// [`parse_spanned()`]: crate::parse_spanned
+pub fn evaluate_spanned(item: &Item, spans: &Spans) -> Result<Item, EvalError> {
+  Interpreter::new().evaluate_spanned(item, spans)
+}
+
+/// Evaluate an item in an environment, definitions stay in it.
+///
+/// A lambda in the result is given as its form. Lambdas bound in `env` can
+/// be called in later evaluations, also closures over the environment of a
+/// call.
+///
+/// ```
+/// # use axp::{evaluate_in, parse, Env};
+/// let env = Env::new();
+/// let define = parse(b"define pair (lambda (x) (list x x))").unwrap();
+/// evaluate_in(&define, &env).unwrap();
+/// let result = evaluate_in(&parse(b"pair a").unwrap(), &env).unwrap();
+/// assert_eq!(format!("{result}"), "(a a)");
+/// assert_eq!(format!("{}", env.get(b"pair").unwrap()), "(lambda (x) (list x x))");
+/// ```
+pub fn evaluate_in(item: &Item, env: &Env) -> Result<Item, EvalError> {
+  Interpreter::new().evaluate_in(item, env)
+}
+
+/// An evaluator which owns its operators, the built-in ones and those the
+/// host registers, its global environment, its output and its limits.
+///
+/// Interpreters are independent of each other and `Send`, so each thread or
+/// request can have its own.
+///
+/// ```
+/// # use axp::{parse, Arity, Interpreter, Item};
+/// let interpreter = Interpreter::new()
+///   .primitive("port", Arity::exactly(1), "The port of a service", |ctx, args| {
+///     let port = match ctx.arg::<String>(args, 0)?.as_str() {
+///       "http" => "80",
+///       _ => "443",
+///     };
+///     Ok(Item::new_atom(port.as_bytes()))
+///   })
+///   .remove("print");
+/// let command = parse(b"list (port http) (port https)").unwrap();
+/// assert_eq!(format!("{}", interpreter.evaluate(&command).unwrap()), "(80 443)");
+/// assert!(interpreter.evaluate(&parse(b"print x").unwrap()).is_err());
+/// assert_eq!(interpreter.docs("port"), Some("The port of a service"));
+/// ```
+pub struct Interpreter {
+  // Shared with other interpreters until one registers or removes operators
+  operators: Arc<HashMap<String, Operator>>,
+  globals: Env,
+  output: RefCell<Box<dyn Write + Send>>,
+  limits: EvalLimits,
+  depth: Cell<usize>,
+  steps: Cell<usize>,
+}
+
+/// Limits for an [`Interpreter`], an evaluation beyond them is an error of
+/// kind [`EvalErrorKind::LimitExceeded`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EvalLimits {
+  /// The nesting of calls, lambdas recursing included, 256 by default
+  pub max_depth: usize,
+  /// The calls of one evaluation, 16 Mi by default
+  pub max_steps: usize,
+}
+
+impl Default for EvalLimits {
+  fn default() -> Self {
+    EvalLimits { max_depth: 256, max_steps: 1 << 24 }
+  }
+}
+
+/// A primitive registered with [`Interpreter::primitive()`], it gets its
+/// arguments evaluated
+pub type Primitive =
+  dyn Fn(&mut Ctx<'_>, &List) -> Result<Item, EvalError> + Send + Sync;
+
+#[derive(Clone)]
+struct Operator {
+  function: Function,
+  arity: Arity,
+  docs: String,
+}
+
+#[derive(Clone)]
+enum Function {
+  Special(SpecialForm),
+  Primitive(Arc<Primitive>),
+}
+
+impl Default for Interpreter {
+  fn default() -> Self {
+    Interpreter::new()
+  }
+}
+
+impl Interpreter {
+  /// An interpreter with the built-in special forms and primitives.
+  ///
+  /// The numeric primitives `+ - * / mod < <= = >= > min max abs` read
+  /// atoms as exact integers `-12` and rationals `3/4` or as floats `1.5`,
+  /// `1e3` and `inf`, results are written back as canonical atoms. Exact
+  /// operands give exact results, `/ 1 3` is `1/3`, and a float operand a
+  /// float result. Exact numbers are 64-bit, a number or result beyond that
+  /// is an error of kind [`EvalErrorKind::Overflow`], unless the `bigint`
+  /// feature makes them unbounded. Floats overflow to `inf` and dividing a
+  /// float by zero gives `inf` or `nan` as in IEEE 754, while exact division
+  /// by zero is an error. Comparisons give `true` or `()`.
+  ///
+  /// ```
+  /// # use axp::{parse, Interpreter, Item};
+  /// let interpreter =
+  ///   Interpreter::new().define("base_port", Item::new_atom(b"8080"));
+  /// let command = parse(b"list (+ base_port 1) (/ 1 3) (* 1.5 2) (< 1 2 3)");
+  /// let result = interpreter.evaluate(&command.unwrap()).unwrap();
+  /// assert_eq!(format!("{result}"), "(8081 1/3 3.0 true)");
+  /// ```
+  pub fn new() -> Self {
+    static BUILT_IN: OnceLock<Arc<HashMap<String, Operator>>> = OnceLock::new();
+    let operators = BUILT_IN.get_or_init(|| Interpreter::built_in().operators);
+    Interpreter { operators: operators.clone(), ..Interpreter::empty() }
+  }
+
+  // An interpreter with the built-in operators, new ones share its table
+  fn built_in() -> Self {
+    use crate::number::*;
+    use Arity as A;
+    Interpreter::empty()
+      .special("quote", A::exactly(1), "The argument unevaluated", prim_quote)
+      .special(
+        "if",
+        A::between(2, 3),
+        "The second or third argument, the second if the first is not ()",
+        prim_if,
+      )
+      .special(
+        "eval",
+        A::exactly(1),
+        "The value of the value of the argument",
+        prim_eval,
+      )
+      .special(
+        "begin",
+        A::at_least(0),
+        "Evaluate the arguments in turn, the last value",
+        prim_begin,
+      )
+      .special(
+        "define",
+        A::exactly(2),
+        "Bind a name in the current environment",
+        prim_define,
+      )
+      .special(
+        "set!",
+        A::exactly(2),
+        "Rebind a name where it is defined",
+        prim_set,
+      )
+      .special(
+        "lambda",
+        A::at_least(1),
+        "A closure of parameter names and a body",
+        prim_lambda,
+      )
+      .special(
+        "let",
+        A::at_least(1),
+        "Bind names and evaluate a body",
+        prim_let,
+      )
+      .special(
+        "let*",
+        A::at_least(1),
+        "Bind names in turn and evaluate a body",
+        prim_let_star,
+      )
+      .primitive("first", A::exactly(1), "The first item of a list", prim_first)
+      .primitive(
+        "tail",
+        A::exactly(1),
+        "A list without its first item",
+        prim_tail,
+      )
+      .primitive("list", A::at_least(0), "A list of the arguments", prim_list)
+      .primitive("print", A::at_least(0), "Print the arguments", prim_print)
+      .primitive("+", A::at_least(0), "The sum of numbers", prim_add)
+      .primitive("-", A::at_least(1), "The difference of numbers", prim_sub)
+      .primitive("*", A::at_least(0), "The product of numbers", prim_mul)
+      .primitive("/", A::at_least(1), "The quotient of numbers", prim_div)
+      .primitive(
+        "mod",
+        A::exactly(2),
+        "The remainder of flooring division",
+        prim_mod,
+      )
+      .primitive(
+        "abs",
+        A::exactly(1),
+        "The absolute value of a number",
+        prim_abs,
+      )
+      .primitive("min", A::at_least(1), "The least number", prim_min)
+      .primitive("max", A::at_least(1), "The greatest number", prim_max)
+      .primitive("<", A::at_least(1), "Whether numbers increase", prim_lt)
+      .primitive(
+        "<=",
+        A::at_least(1),
+        "Whether numbers don't decrease",
+        prim_le,
+      )
+      .primitive("=", A::at_least(1), "Whether numbers are equal", prim_eq)
+      .primitive(
+        ">=",
+        A::at_least(1),
+        "Whether numbers don't increase",
+        prim_ge,
+      )
+      .primitive(">", A::at_least(1), "Whether numbers decrease", prim_gt)
+  }
+
+  /// An interpreter without operators, for a sandbox of only host primitives
+  pub fn empty() -> Self {
+    Interpreter {
+      operators: Arc::default(),
+      globals: Env::new(),
+      output: RefCell::new(Box::new(io::stdout())),
+      limits: EvalLimits::default(),
+      depth: Cell::new(0),
+      steps: Cell::new(0),
+    }
+  }
+
+  /// Write the output of `print` to the writer instead of stdout
+  pub fn output(mut self, output: impl Write + Send + 'static) -> Self {
+    self.output = RefCell::new(Box::new(output));
+    self
+  }
+
+  pub fn limits(mut self, limits: EvalLimits) -> Self {
+    self.limits = limits;
+    self
+  }
+
+  /// Bind a name in the global environment
+  pub fn define(self, name: &str, item: Item) -> Self {
+    self.globals.define(name.as_bytes(), item);
+    self
+  }
+
+  /// The environment in which [`Interpreter::evaluate()`] evaluates
+  pub fn globals(&self) -> &Env {
+    &self.globals
+  }
+
+  /// Register a primitive, replacing an operator of the name
+  pub fn primitive<F>(
+    mut self,
+    name: &str,
+    arity: Arity,
+    docs: &str,
+    function: F,
+  ) -> Self
+  where
+    F: Fn(&mut Ctx<'_>, &List) -> Result<Item, EvalError>
+      + Send
+      + Sync
+      + 'static,
+  {
+    let function = Function::Primitive(Arc::new(function));
+    let operator = Operator { function, arity, docs: docs.to_string() };
+    Arc::make_mut(&mut self.operators).insert(name.to_string(), operator);
+    self
+  }
+
+  fn special(
+    mut self,
+    name: &str,
+    arity: Arity,
+    docs: &str,
+    form: SpecialForm,
+  ) -> Self {
+    let function = Function::Special(form);
+    let operator = Operator { function, arity, docs: docs.to_string() };
+    Arc::make_mut(&mut self.operators).insert(name.to_string(), operator);
+    self
+  }
+
+  /// Remove an operator, built-in or registered
+  pub fn remove(mut self, name: &str) -> Self {
+    Arc::make_mut(&mut self.operators).remove(name);
+    self
+  }
+
+  /// The names of the operators, sorted
+  pub fn names(&self) -> Vec<&str> {
+    let mut names: Vec<_> = self.operators.keys().map(String::as_str).collect();
+    names.sort();
+    names
+  }
+
+  pub fn arity(&self, name: &str) -> Option<Arity> {
+    self.operators.get(name).map(|operator| operator.arity)
+  }
+
+  pub fn docs(&self, name: &str) -> Option<&str> {
+    self.operators.get(name).map(|operator| operator.docs.as_str())
+  }
+
+  /// Evaluate an item in the global environment, definitions stay in it.
+  /// See [`evaluate()`].
+  ///
+  /// ```
+  /// # use axp::{parse, Interpreter};
+  /// let interpreter = Interpreter::new();
+  /// let define = parse(b"define hosts (quote (a b))").unwrap();
+  /// interpreter.evaluate(&define).unwrap();
+  /// let result = interpreter.evaluate(&parse(b"first hosts").unwrap());
+  /// assert_eq!(format!("{}", result.unwrap()), "a");
+  /// assert!(Interpreter::new().globals().get(b"hosts").is_none());
+  /// ```
+  pub fn evaluate(&self, item: &Item) -> Result<Item, EvalError> {
+    self.evaluate_in(item, &self.globals)
+  }
+
+  /// Evaluate an item in the global environment, see [`evaluate_spanned()`]
+  pub fn evaluate_spanned(
+    &self,
+    item: &Item,
+    spans: &Spans,
+  ) -> Result<Item, EvalError> {
+    self.run(item, spans, &self.globals.0)
+  }
+
+  /// Evaluate an item in an environment, see [`evaluate_in()`]
+  pub fn evaluate_in(&self, item: &Item, env: &Env) -> Result<Item, EvalError> {
+    self.run(item, &NO_SPANS, &env.0)
+  }
+
+  fn run(
+    &self,
+    item: &Item,
+    spans: &Spans,
+    env: &Scope,
+  ) -> Result<Item, EvalError> {
+    // Steps count from the outermost evaluation, not from a primitive's
+    if self.depth.get() == 0 {
+      self.steps.set(0);
+    }
+    let eval = Eval { interpreter: self, spans };
+    let value = evaluate_item(eval, item, Some(&Path::root()), env)?;
+    Ok(value.into_item())
+  }
+
+  // Count a call and its nesting, undone by leave
+  fn enter(&self) -> Result<(), EvalError> {
+    let limits = &self.limits;
+    let reason = match (self.depth.get(), self.steps.get()) {
+      (depth, _) if depth >= limits.max_depth => {
+        format!("calls nested deeper than {}", limits.max_depth)
+      }
+      (_, steps) if steps >= limits.max_steps => {
+        format!("more than {} calls", limits.max_steps)
+      }
+      (depth, steps) => {
+        self.depth.set(depth + 1);
+        self.steps.set(steps + 1);
+        return Ok(());
+      }
+    };
+    Err(error(EvalErrorKind::LimitExceeded, reason))
+  }
+
+  fn leave(&self) {
+    self.depth.set(self.depth.get() - 1);
+  }
+
+  fn operator(&self, name: &[u8]) -> Option<&Operator> {
+    self.operators.get(std::str::from_utf8(name).ok()?)
+  }
+
+  fn is_special(&self, name: &[u8]) -> bool {
+    let operator = self.operator(name);
+    matches!(operator, Some(Operator { function: Function::Special(_), .. }))
+  }
+}
+
+// The frames of the environments with a common root, collected to break
+// the cycles of lambdas bound in the environments they refer to
+#[derive(Default)]
+struct Frames(Mutex<Vec<Weak<Frame>>>);
+
+// Drop the frames which only other frames and the lambdas bound in them
+// refer to, by clearing their variables to break the cycles. The frame of
+// an Env being dropped doesn't count that Env.
+//
+// The references from outside are what is left of the strong counts after
+// subtracting those from the frames: one from each child and one from each
+// lambda bound only in frames. A frame is in use if it has references from
+// outside or if one in use refers to it.
+fn collect(frames: &mut Vec<Weak<Frame>>, dropping: Option<&Scope>) {
+  let scopes: Vec<_> = frames.iter().filter_map(Weak::upgrade).collect();
+  let index: HashMap<*const Frame, usize> = scopes
+    .iter()
+    .enumerate()
+    .map(|(i, frame)| (Arc::as_ptr(frame), i))
+    .collect();
+  let mut outside: Vec<usize> =
+    scopes.iter().map(|frame| Arc::strong_count(frame) - 1).collect();
+  let dropping = dropping.map(|scope| Arc::as_ptr(&scope.0));
+  if let Some(&i) = dropping.and_then(|frame| index.get(&frame)) {
+    outside[i] -= 1;
+  }
+  // The frames each one refers to, and for each lambda its frame, its
+  // strong count and how often it is bound
+  let mut edges = vec![Vec::new(); scopes.len()];
+  let mut lambdas = HashMap::new();
+  for (i, frame) in scopes.iter().enumerate() {
+    if let Some(parent) = &frame.parent {
+      edges[i].push(Arc::as_ptr(&parent.0));
+      if let Some(&parent) = index.get(&Arc::as_ptr(&parent.0)) {
+        outside[parent] -= 1;
+      }
+    }
+    for value in frame.vars.lock().unwrap().values() {
+      if let Value::Lambda(lambda) = value {
+        let env = Arc::as_ptr(&lambda.env.0);
+        edges[i].push(env);
+        let count = Arc::strong_count(lambda);
+        lambdas.entry(Arc::as_ptr(lambda)).or_insert((env, count, 0)).2 += 1;
+      }
+    }
+  }
+  for (env, count, bound) in lambdas.into_values() {
+    match index.get(&env) {
+      Some(&i) if count == bound => outside[i] -= 1,
+      _ => (),
+    }
+  }
+
+  let mut in_use = vec![false; scopes.len()];
+  let mut todo: Vec<_> =
+    (0..scopes.len()).filter(|&i| outside[i] > 0).collect();
+  while let Some(i) = todo.pop() {
+    if !std::mem::replace(&mut in_use[i], true) {
+      todo.extend(edges[i].iter().filter_map(|env| index.get(env)));
+    }
+  }
+
+  frames.clear();
+  for (frame, in_use) in scopes.iter().zip(in_use) {
+    if in_use {
+      frames.push(Arc::downgrade(frame));
+    } else {
+      let vars = std::mem::take(&mut *frame.vars.lock().unwrap());
+      drop(vars);
+    }
+  }
+}
+
+/// What a primitive can reach of the call it is in
+pub struct Ctx<'a> {
+  scope: &'a Scope,
+  env: OnceCell<Env>,
+  interpreter: &'a Interpreter,
+}
+
+impl Ctx<'_> {
+  /// The environment of the call
+  pub fn env(&self) -> &Env {
+    self.env.get_or_init(|| Env(self.scope.clone()))
+  }
+
+  /// Evaluate an item in the environment of the call
+  pub fn evaluate(&mut self, item: &Item) -> Result<Item, EvalError> {
+    self.interpreter.run(item, &NO_SPANS, self.scope)
+  }
+
+  /// Convert an argument of the call, a missing one is `()`
+  ///
+  /// ```
+  /// # use axp::{parse, Arity, EvalErrorKind, Interpreter, Item};
+  /// let interpreter = Interpreter::empty().primitive(
+  ///   "port",
+  ///   Arity::between(1, 2),
+  ///   "A port and an offset",
+  ///   |ctx, args| {
+  ///     let port: u16 = ctx.arg(args, 0)?;
+  ///     let offset: Option<u16> = ctx.arg(args, 1)?;
+  ///     let port = port + offset.unwrap_or(0);
+  ///     Ok(Item::new_atom(port.to_string().as_bytes()))
+  ///   },
+  /// );
+  /// let run = |input: &[u8]| interpreter.evaluate(&parse(input).unwrap());
+  /// assert_eq!(run(b"port 80"), Ok(Item::new_atom(b"80")));
+  /// assert_eq!(run(b"port 80 1"), Ok(Item::new_atom(b"81")));
+  /// let err = run(b"port http").unwrap_err();
+  /// assert_eq!(err.kind(), EvalErrorKind::TypeError);
+  /// assert_eq!(err.reason(), "argument 1 must be a number");
+  /// ```
+  pub fn arg<T: FromItem>(
+    &self,
+    args: &List,
+    index: usize,
+  ) -> Result<T, EvalError> {
+    let arg = args.iter().nth(index).cloned().unwrap_or_else(Item::nil);
+    T::from_item(&arg).map_err(|err| {
+      let reason = format!("argument {} {err}", index + 1);
+      error(EvalErrorKind::TypeError, reason)
+    })
+  }
+
+  /// Write to the output of the interpreter, an error is of kind
+  /// [`EvalErrorKind::Io`]
+  pub fn write(&mut self, bytes: &[u8]) -> Result<(), EvalError> {
+    let mut output = self.interpreter.output.borrow_mut();
+    output.write_all(bytes).map_err(|err| {
+      error(EvalErrorKind::Io, format!("writing the output: {err}"))
+    })
+  }
+}
+
+// The interpreter and the spans of the input being evaluated
+#[derive(Clone, Copy)]
+struct Eval<'a> {
+  interpreter: &'a Interpreter,
+  spans: &'a Spans,
+}
+
+impl Eval<'_> {
+  // For forms built while evaluating, which are not in the input
+  fn unspanned(self) -> Self {
+    Eval { spans: &NO_SPANS, ..self }
+  }
+}
+
+// The path is where the item is in the input, none if it was built
+fn evaluate_item(
+  eval: Eval<'_>,
+  item: &Item,
+  path: Option<&Path>,
+  env: &Scope,
+) -> Result<Value, EvalError> {
+  match item {
+    Item::List(list) if !list.is_empty() => {
+      let call =
+        Call { args: list.tail(), path: path.cloned(), eval, env: env.clone() };
+      let span = path.and_then(|path| {
+        let spans = eval.spans;
+        let span = spans.get(path).or(spans.get(&path.join_index(0)));
+        span.copied()
+      });
+      let interpreter = eval.interpreter;
+      let result = interpreter.enter().and_then(|()| {
+        let result = call.apply(&list.first());
+        interpreter.leave();
+        result
+      });
+      result.map_err(|err| err.within(list, span))
+    }
+    Item::Atom(atom) if atom.tag().is_none() => {
+      Ok(env.lookup(atom.as_bytes()).unwrap_or(Value::Item(item.clone())))
+    }
+    item => Ok(Value::Item(item.clone())),
+  }
+}
+
+/// The variables of the evaluator, an environment sees those of its parents.
+///
+/// Environments are shared, a lambda refers to the one it was made in. When
+/// an `Env` is dropped, the environments which are only reachable through
+/// lambdas bound in environments are freed, so that recursive definitions
+/// and closures don't keep each other alive.
+#[derive(Clone, Default)]
+pub struct Env(Scope);
+
+// An environment as the evaluator refers to it, only dropping an Env
+// collects
+#[derive(Clone)]
+struct Scope(Arc<Frame>);
+
+struct Frame {
+  vars: Mutex<HashMap<Vec<u8>, Value>>,
+  parent: Option<Scope>,
+  frames: Arc<Frames>,
+}
+
+impl fmt::Debug for Env {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.0.fmt(f)
+  }
+}
+
+impl fmt::Debug for Scope {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    // Lambdas refer to their environments, don't follow them
+    let vars = self.0.vars.lock().unwrap();
+    let mut names: Vec<_> =
+      vars.keys().map(|name| String::from_utf8_lossy(name)).collect();
+    names.sort();
+    f.debug_struct("Env").field("names", &names).finish_non_exhaustive()
+  }
+}
+
+impl Drop for Env {
+  fn drop(&mut self) {
+    let frames = self.0 .0.frames.clone();
+    collect(&mut frames.0.lock().unwrap(), Some(&self.0));
+  }
+}
+
+impl Env {
+  pub fn new() -> Env {
+    Env::default()
+  }
+
+  /// A new environment in which names fall back to this one
+  pub fn child(&self) -> Env {
+    Env(self.0.child())
+  }
+
+  /// Bind the name here, shadowing a binding of a parent
+  pub fn define(&self, name: &[u8], item: Item) {
+    self.0.bind(name, Value::Item(item));
+  }
+
+  /// The item bound to the name here or in a parent, a lambda as its form
+  pub fn get(&self, name: &[u8]) -> Option<Item> {
+    self.0.lookup(name).map(Value::into_item)
+  }
+}
+
+impl Default for Scope {
+  fn default() -> Self {
+    Scope::new(None, Arc::default())
+  }
+}
+
+impl Scope {
+  // Collect before growing the frames so that collecting takes amortized
+  // constant time
+  fn new(parent: Option<Scope>, frames: Arc<Frames>) -> Scope {
+    let vars = Mutex::default();
+    let frame = Arc::new(Frame { vars, parent, frames });
+    let mut frames = frame.frames.0.lock().unwrap();
+    if frames.len() == frames.capacity() {
+      collect(&mut frames, None);
+      let len = frames.len();
+      frames.reserve(len);
+    }
+    frames.push(Arc::downgrade(&frame));
+    drop(frames);
+    Scope(frame)
+  }
+
+  fn child(&self) -> Scope {
+    Scope::new(Some(self.clone()), self.0.frames.clone())
+  }
+
+  fn bind(&self, name: &[u8], value: Value) {
+    self.0.vars.lock().unwrap().insert(name.to_vec(), value);
+  }
+
+  fn lookup(&self, name: &[u8]) -> Option<Value> {
+    let mut env = self;
+    loop {
+      if let Some(value) = env.0.vars.lock().unwrap().get(name) {
+        return Some(value.clone());
+      }
+      env = env.0.parent.as_ref()?;
+    }
+  }
+
+  // Rebind the name where it is bound, false if it is not
+  fn set(&self, name: &[u8], value: Value) -> bool {
+    let mut env = self;
+    loop {
+      if let Some(bound) = env.0.vars.lock().unwrap().get_mut(name) {
+        *bound = value;
+        return true;
+      }
+      match &env.0.parent {
+        Some(parent) => env = parent,
+        None => return false,
+      }
+    }
+  }
+}
+
+/// The result of evaluating, a lambda only exists while evaluating
+#[derive(Clone, Debug)]
+pub enum Value {
+  Item(Item),
+  Lambda(Arc<Lambda>),
+}
+
+impl Value {
+  pub fn into_item(self) -> Item {
+    match self {
+      Value::Item(item) => item,
+      Value::Lambda(lambda) => lambda.form.clone(),
+    }
+  }
+
+  fn is_true(&self) -> bool {
+    match self {
+      Value::Item(item) => !item.is_empty(),
+      Value::Lambda(_) => true,
+    }
+  }
+}
+
+/// A closure over the environment its `lambda` form was evaluated in
+#[derive(Debug)]
+pub struct Lambda {
+  params: Vec<Vec<u8>>,
+  body: List,
+  env: Scope,
+  form: Item,
+}
+
+impl Lambda {
+  fn call(&self, eval: Eval<'_>, args: Vec<Value>) -> Result<Value, EvalError> {
+    let env = self.env.child();
+    for (param, arg) in self.params.iter().zip(args) {
+      env.bind(param, arg);
+    }
+    let mut result = Value::Item(Item::nil());
+    for form in self.body.iter() {
+      result = evaluate_item(eval.unspanned(), form, None, &env)?;
+    }
+    Ok(result)
+  }
+}
+
+/// The arguments of a call as they are, with where they are in the input
+pub struct Call<'a> {
+  args: List,
+  path: Option<Path>,
+  eval: Eval<'a>,
+  env: Scope,
+}
+
+impl Call<'_> {
+  pub fn args(&self) -> &List {
+    &self.args
+  }
+
+  /// Evaluate the argument at the index, nil if there is none
+  pub fn evaluate(&self, index: usize) -> Result<Value, EvalError> {
+    self.evaluate_in(index, &self.env)
+  }
+
+  fn evaluate_in(&self, index: usize, env: &Scope) -> Result<Value, EvalError> {
+    let Some(arg) = self.args.iter().nth(index) else {
+      return Ok(Value::Item(Item::nil()));
+    };
+    let path = self.path.as_ref().map(|path| path.join_index(index + 1));
+    evaluate_item(self.eval, arg, path.as_ref(), env)
+  }
+
+  fn evaluate_args(&self) -> Result<Vec<Value>, EvalError> {
+    (0..self.args.len()).map(|index| self.evaluate(index)).collect()
+  }
+
+  fn apply(&self, op: &Item) -> Result<Value, EvalError> {
+    let interpreter = self.eval.interpreter;
+    let value = match op {
+      Item::Atom(atom) if interpreter.is_special(atom.as_bytes()) => {
+        Value::Item(op.clone())
+      }
+      op => {
+        let path = self.path.as_ref().map(|path| path.join_index(0));
+        evaluate_item(self.eval, op, path.as_ref(), &self.env)?
+      }
+    };
+    let count = self.args.len();
+    let name = match value {
+      Value::Lambda(lambda) => {
+        Arity::exactly(lambda.params.len()).check(&op.to_string(), count)?;
+        return lambda.call(self.eval, self.evaluate_args()?);
+      }
+      Value::Item(Item::Atom(atom)) => atom,
+      Value::Item(item) => {
+        let reason = format!("{item} is not an operator");
+        return Err(error(EvalErrorKind::TypeError, reason));
+      }
+    };
+    let display = String::from_utf8_lossy(name.as_bytes());
+    let Some(operator) = interpreter.operator(name.as_bytes()) else {
+      let reason = format!("unknown operator {display}");
+      return Err(error(EvalErrorKind::UnknownOperator, reason));
+    };
+    operator.arity.check(&display, count)?;
+    match &operator.function {
+      Function::Special(form) => form(self),
+      Function::Primitive(primitive) => {
+        let args = self.evaluate_args()?.into_iter().map(Value::into_item);
+        let env = OnceCell::new();
+        let mut ctx = Ctx { scope: &self.env, env, interpreter };
+        primitive(&mut ctx, &List::new(args)).map(Value::Item)
+      }
+    }
+  }
+}
+
+/// The number of arguments an operator takes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Arity {
+  pub min: usize,
+  pub max: Option<usize>,
+}
+
+impl Arity {
+  pub const fn exactly(count: usize) -> Arity {
+    Arity { min: count, max: Some(count) }
+  }
+
+  pub const fn between(min: usize, max: usize) -> Arity {
+    Arity { min, max: Some(max) }
+  }
+
+  pub const fn at_least(min: usize) -> Arity {
+    Arity { min, max: None }
+  }
+
+  fn check(&self, name: &str, count: usize) -> Result<(), EvalError> {
+    if count >= self.min && self.max.is_none_or(|max| count <= max) {
+      return Ok(());
+    }
+    let takes = match self.max {
+      Some(1) if self.min == 1 => "1 argument".to_string(),
+      Some(max) if max == self.min => format!("{max} arguments"),
+      Some(max) => format!("{} to {max} arguments", self.min),
+      None => format!("at least {} arguments", self.min),
+    };
+    let reason = format!("{name} takes {takes}, got {count}");
+    Err(error(EvalErrorKind::ArityMismatch, reason))
+  }
+}
+
+/// A special form gets its arguments as they are and evaluates them itself
+pub type SpecialForm = fn(&Call<'_>) -> Result<Value, EvalError>;
+
+// The name bound by a special form, an untagged atom
+fn name(item: &Item) -> Result<Vec<u8>, EvalError> {
   match item {
-    Item::Atom(atom) => evaluate_atom(atom.clone(), List::nil()),
-    Item::List(list) => evaluate_list(list),
-    Item::Map(_) => item.clone(),
+    Item::Atom(atom) if atom.tag().is_none() => Ok(atom.as_bytes().to_vec()),
+    item => {
+      let reason = format!("{item} is not a name");
+      Err(error(EvalErrorKind::TypeError, reason))
+    }
   }
 }
 
-pub fn operator(op: &Item) -> Atom {
-  match op {
-    Item::Atom(atom) => atom.clone(),
-    Item::List(list) => operator(&evaluate_list(list)),
-    Item::Map(_) => Atom::new(b"map_as_operator"),
+/// Special form to evaluate the value of its argument
+pub fn prim_eval(call: &Call<'_>) -> Result<Value, EvalError> {
+  let form = call.evaluate(0)?.into_item();
+  evaluate_item(call.eval.unspanned(), &form, None, &call.env)
+}
+
+pub fn prim_quote(call: &Call<'_>) -> Result<Value, EvalError> {
+  Ok(Value::Item(call.args().first()))
+}
+
+/// Special form to evaluate its arguments in turn, giving the last
+pub fn prim_begin(call: &Call<'_>) -> Result<Value, EvalError> {
+  let mut result = Value::Item(Item::nil());
+  for index in 0..call.args().len() {
+    result = call.evaluate(index)?;
   }
+  Ok(result)
 }
 
-pub fn evaluate_list(list: &List) -> Item {
-  evaluate_atom(operator(&list.first()), list.tail())
+/// Special form to bind a name in the current environment
+///
+/// ```
+/// # use axp::{evaluate, parse};
+/// let command = parse(b"begin (define x (quote (a b))) (tail x)").unwrap();
+/// assert_eq!(format!("{}", evaluate(&command).unwrap()), "(b)");
+/// ```
+pub fn prim_define(call: &Call<'_>) -> Result<Value, EvalError> {
+  let name = name(&call.args().first())?;
+  let value = call.evaluate(1)?;
+  call.env.bind(&name, value.clone());
+  Ok(value)
 }
 
-pub fn evaluate_atom(atom: Atom, args: List) -> Item {
-  let primitives = PRIMITIVES.get_or_init(|| define_primitives());
-  let name: &[u8] = &atom.0;
-  primitives.get(name).map_or(Item::nil(), |primitive| primitive(&args))
+/// Special form to rebind a name where it is defined
+pub fn prim_set(call: &Call<'_>) -> Result<Value, EvalError> {
+  let name = name(&call.args().first())?;
+  let value = call.evaluate(1)?;
+  if call.env.set(&name, value.clone()) {
+    Ok(value)
+  } else {
+    let reason = format!("{} is not defined", String::from_utf8_lossy(&name));
+    Err(error(EvalErrorKind::UnboundSymbol, reason))
+  }
 }
 
-pub type Primitive = fn(&List) -> Item;
+/// Special form to make a closure of parameter names and a body
+///
+/// ```
+/// # use axp::{evaluate, parse};
+/// let command = parse(b"(lambda (x y) (list y x)) a b").unwrap();
+/// assert_eq!(format!("{}", evaluate(&command).unwrap()), "(b a)");
+/// ```
+pub fn prim_lambda(call: &Call<'_>) -> Result<Value, EvalError> {
+  let params = match call.args().first() {
+    Item::List(params) => params.iter().map(name).collect::<Result<_, _>>()?,
+    item => {
+      let reason = format!("lambda takes a list of names, not {item}");
+      return Err(error(EvalErrorKind::TypeError, reason));
+    }
+  };
+  let body = call.args().tail();
+  let lambda = Item::new_atom(b"lambda");
+  let form =
+    Item::new_list(std::iter::once(lambda).chain(call.args().iter().cloned()));
+  let env = call.env.clone();
+  Ok(Value::Lambda(Arc::new(Lambda { params, body, env, form })))
+}
+
+/// Special form to bind names for its body, `let*` sees earlier bindings.
+///
+/// The bindings are a list of name and value lists or a map.
+///
+/// ```
+/// # use axp::{evaluate, parse};
+/// let command = parse(b"let ((x a) (y b)) (list x y)").unwrap();
+/// assert_eq!(format!("{}", evaluate(&command).unwrap()), "(a b)");
+/// let command = parse(b"let* (x: a y: (list x x)) y").unwrap();
+/// assert_eq!(format!("{}", evaluate(&command).unwrap()), "(a a)");
+/// ```
+pub fn prim_let(call: &Call<'_>) -> Result<Value, EvalError> {
+  bind_and_evaluate(call, false)
+}
+
+pub fn prim_let_star(call: &Call<'_>) -> Result<Value, EvalError> {
+  bind_and_evaluate(call, true)
+}
+
+fn bind_and_evaluate(
+  call: &Call<'_>,
+  sequential: bool,
+) -> Result<Value, EvalError> {
+  let path = call.path.as_ref().map(|path| path.join_index(1));
+  let mut bindings = Vec::new();
+  match call.args().first() {
+    Item::Map(map) => {
+      for (key, value) in map.iter() {
+        let path = path.as_ref().map(|path| path.join(key.clone()));
+        bindings.push((name(key)?, value.clone(), path));
+      }
+    }
+    Item::List(list) => {
+      for (index, binding) in list.iter().enumerate() {
+        match binding {
+          Item::List(pair) if pair.len() == 2 => {
+            let path =
+              path.as_ref().map(|path| path.join_index(index).join_index(1));
+            bindings.push((name(&pair.first())?, pair.tail().first(), path));
+          }
+          item => {
+            let reason = format!("{item} is not a name and a value");
+            return Err(error(EvalErrorKind::TypeError, reason));
+          }
+        }
+      }
+    }
+    item => {
+      let reason = format!("{item} is not a list or map of bindings");
+      return Err(error(EvalErrorKind::TypeError, reason));
+    }
+  }
+
+  let env = call.env.child();
+  let scope = if sequential {
+    &env
+  } else {
+    &call.env
+  };
+  for (name, form, path) in bindings {
+    let value = evaluate_item(call.eval, &form, path.as_ref(), scope)?;
+    env.bind(&name, value);
+  }
+  let mut result = Value::Item(Item::nil());
+  for index in 1..call.args().len() {
+    result = call.evaluate_in(index, &env)?;
+  }
+  Ok(result)
+}
 
-#[allow(non_upper_case_globals)]
-pub const prim_eval: Primitive = evaluate_list;
+fn list_arg(name: &str, args: &List) -> Result<List, EvalError> {
+  match args.first() {
+    Item::List(list) => Ok(list),
+    item => {
+      let reason = format!("{name} takes a list, not {item}");
+      Err(error(EvalErrorKind::TypeError, reason))
+    }
+  }
+}
 
-pub fn prim_quote(args: &List) -> Item {
-  Item::List(args.clone())
+pub fn prim_first(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  Ok(list_arg("first", args)?.first())
 }
 
-pub fn prim_first(args: &List) -> Item {
-  args.first()
+pub fn prim_tail(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  Ok(Item::List(list_arg("tail", args)?.tail()))
 }
 
-pub fn prim_tail(args: &List) -> Item {
-  Item::List(args.tail())
+pub fn prim_list(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  Ok(Item::List(args.clone()))
 }
 
-/// Primitive to implement if
+/// Special form to implement if, only the branch taken is evaluated
 ///
 /// ```
 /// # use axp::parse;
 /// # use axp::evaluate;
 /// let command = parse(b"if true a").unwrap();
 /// assert_eq!(format!("{command}"), "(if true a)");
-/// let result = evaluate(&command);
+/// let result = evaluate(&command).unwrap();
 /// assert_eq!(format!("{result}"), "a");
+///
+/// let command = parse(b"if (first (quote ())) a b").unwrap();
+/// assert_eq!(format!("{}", evaluate(&command).unwrap()), "b");
+/// let command = parse(b"if (if () x (tail (quote (y)))) a (first (quote (c)))");
+/// assert_eq!(format!("{}", evaluate(&command.unwrap()).unwrap()), "c");
 /// ```
-pub fn prim_if(args: &List) -> Item {
-  if args.first().is_empty() {
-    args.tail().tail().first()
+pub fn prim_if(call: &Call<'_>) -> Result<Value, EvalError> {
+  if call.evaluate(0)?.is_true() {
+    call.evaluate(1)
   } else {
-    args.tail().first()
+    call.evaluate(2)
   }
 }
 
-pub fn prim_print(args: &List) -> Item {
-  print!("( ");
+pub fn prim_print(ctx: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  let mut text = String::from("( ");
   for item in args.iter() {
-    print!("{item} ");
+    text.push_str(&format!("{item} "));
   }
-  print!(")");
-  Item::nil()
+  text.push(')');
+  ctx.write(text.as_bytes())?;
+  Ok(Item::nil())
 }
 
-type Primitives = std::collections::HashMap<&'static [u8], Primitive>;
+#[cfg(test)]
+mod tests {
+  use std::io::{self, Write};
+  use std::sync::{Arc, Mutex};
+
+  use super::{evaluate, evaluate_in, evaluate_spanned, Env, EvalErrorKind};
+  use super::{Arity, EvalLimits, Interpreter, Value};
+  use crate::{parse, parse_spanned, FromItem, Item};
+
+  fn eval(input: &str) -> String {
+    evaluate(&parse(input.as_bytes()).unwrap()).unwrap().to_string()
+  }
+
+  fn err(input: &str) -> (EvalErrorKind, String) {
+    let err = evaluate(&parse(input.as_bytes()).unwrap()).unwrap_err();
+    (err.kind(), err.to_string())
+  }
+
+  #[test]
+  fn arguments() {
+    assert_eq!(eval("first (quote (a b))"), "a");
+    assert_eq!(eval("tail (tail (quote (a b c)))"), "(c)");
+    assert_eq!(eval("eval (quote (first (quote (x y))))"), "x");
+    assert_eq!(eval("(first (quote (tail))) (quote (a b))"), "(b)");
+    assert_eq!(eval("quote (first x)"), "(first x)");
+    assert_eq!(eval("first ()"), "()");
+  }
+
+  #[test]
+  fn special_forms() {
+    assert_eq!(eval("if (first (quote ())) a b"), "b");
+    assert_eq!(eval("if (first (quote (x))) a b"), "a");
+    assert_eq!(eval("if () a"), "()");
+    // the branch not taken is not evaluated
+    assert_eq!(eval("if x a (print side effect)"), "a");
+    assert_eq!(eval("if () (first (quote (a))) (tail (quote (a b)))"), "(b)");
+  }
+
+  #[test]
+  fn variables() {
+    assert_eq!(eval("begin (define x a) (list x (quote x))"), "(a x)");
+    assert_eq!(eval("begin (define x a) (set! x b) x"), "b");
+    assert_eq!(eval("let ((x a)) (let ((x b) (y x)) (list x y))"), "(b a)");
+    assert_eq!(eval("let ((x a)) (let* ((x b) (y x)) (list x y))"), "(b b)");
+    // set! rebinds where the name is defined, define shadows
+    assert_eq!(
+      eval("begin (define x a) (let ((y b)) (set! x y) (define y c)) x"),
+      "b"
+    );
+    assert_eq!(eval("begin (define x a) (let () (define x b)) x"), "a");
+    assert_eq!(eval("begin (define f first) (f (quote (a b)))"), "a");
+    // tagged atoms are strings, not names
+    assert_eq!(eval("begin (define x a) #(s)\"x#(s)\""), "#(s)x");
+  }
+
+  #[test]
+  fn closures() {
+    let counter = "begin
+      (define counter (lambda ()
+        (let ((n ()))
+          (lambda () (set! n (list n))))))
+      (define c (counter))
+      (c)
+      (c)
+      (list (c) ((counter)))";
+    assert_eq!(eval(counter), "((((()))) (()))");
+    let twice = "begin
+      (define twice (lambda (f) (lambda (x) (f (f x)))))
+      ((twice tail) (quote (a b c)))";
+    assert_eq!(eval(twice), "(c)");
+    // lexical, not dynamic scope
+    let scope = "begin
+      (define x a)
+      (define get (lambda () x))
+      (let ((x b)) (get))";
+    assert_eq!(eval(scope), "a");
+    assert_eq!(eval("lambda (x) x"), "(lambda (x) x)");
+
+    let env = Env::new();
+    env.define(b"name", Item::new_atom(b"axp"));
+    let define = parse(b"define greet (lambda (x) (list x name))").unwrap();
+    evaluate_in(&define, &env).unwrap();
+    let greet = parse(b"greet hello").unwrap();
+    assert_eq!(evaluate_in(&greet, &env).unwrap().to_string(), "(hello axp)");
+    assert_eq!(env.child().get(b"name"), Some(Item::new_atom(b"axp")));
+    assert_eq!(env.get(b"x"), None);
+
+    // a closure outlives the interpreter it was made by
+    let define = parse(b"define c (let ((n a)) (lambda () n))").unwrap();
+    evaluate_in(&define, &env).unwrap();
+    let c = evaluate_in(&parse(b"c").unwrap(), &env).unwrap();
+    assert_eq!(c, Item::new_atom(b"a"));
+  }
+
+  #[test]
+  fn shared_operators() {
+    let (a, b) = (Interpreter::new(), Interpreter::new());
+    assert!(Arc::ptr_eq(&a.operators, &b.operators));
+    let a = a.remove("print");
+    assert!(!Arc::ptr_eq(&a.operators, &b.operators));
+    assert_eq!(a.arity("print"), None);
+    assert_eq!(b.arity("print"), Some(Arity::at_least(0)));
+  }
 
-macro_rules! primitives {
-  ($($name:ident $(,)?),+) => {{
-    let mut map = Primitives::new();
+  #[test]
+  fn no_cycles() {
+    // lambdas bound in their own environments are no cycles
+    let interpreter = Interpreter::new();
+    let define = "begin
+      (define f (lambda (n) (f n)))
+      (define g (let () (define h (lambda () (h))) h))";
+    interpreter.evaluate(&parse(define.as_bytes()).unwrap()).unwrap();
+    let globals = Arc::downgrade(&interpreter.globals().0 .0);
+    let Some(Value::Lambda(g)) = interpreter.globals().0.lookup(b"g") else {
+      panic!("g is a lambda")
+    };
+    let env = Arc::downgrade(&g.env.0);
+    drop(g);
+    drop(interpreter);
+    assert!(globals.upgrade().is_none());
+    assert!(env.upgrade().is_none());
 
-    paste::paste! {
-      $(
-        let name = stringify!($name);
-        let present = map.insert(name.as_bytes(), [<prim_ $name>] as Primitive);
-        assert_eq!(present, None, "duplicate key {name}");
-      )+
+    // environments no lambda refers to are dropped while evaluating, also
+    // those which bind a lambda made in them
+    let interpreter = Interpreter::new();
+    let define = "begin
+      (define f (lambda (n) (let () (lambda () n))))
+      (define g (lambda (n) (let () (define h (lambda () n)) (h))))
+      (define k (let ((n a)) (define m (lambda () n)) m))";
+    interpreter.evaluate(&parse(define.as_bytes()).unwrap()).unwrap();
+    let run = |input: &[u8]| interpreter.evaluate(&parse(input).unwrap());
+    for _ in 0..1000 {
+      run(b"(f x)").unwrap();
+      assert_eq!(run(b"g x").unwrap(), Item::new_atom(b"x"));
     }
+    let frames = &interpreter.globals().0 .0.frames;
+    assert!(frames.0.lock().unwrap().len() < 64);
+    // a closure in use keeps its environment
+    assert_eq!(run(b"k").unwrap(), Item::new_atom(b"a"));
+  }
 
-    map
-  }};
-}
+  #[test]
+  fn no_leaks() {
+    // an environment of the caller is freed when it is dropped, with the
+    // interpreter which evaluated in it gone already
+    for define in [
+      "define f (lambda (n) (f n))",
+      "define c (let ((n a)) (lambda () n))",
+      "define counter (lambda () (let ((n ())) (lambda () (set! n (list n)))))
+       define c (counter)",
+    ] {
+      let env = Env::new();
+      for form in define.lines() {
+        evaluate_in(&parse(form.as_bytes()).unwrap(), &env).unwrap();
+      }
+      let child = env.child();
+      evaluate_in(&parse(b"c").unwrap(), &child).ok();
+      let frame = Arc::downgrade(&env.0 .0);
+      drop(env);
+      assert!(frame.upgrade().is_some(), "{define}: the child refers to it");
+      drop(child);
+      assert!(frame.upgrade().is_none(), "{define}");
+    }
+  }
 
-pub fn define_primitives() -> Primitives {
-  primitives![if, print, eval, first, tail, quote]
-}
+  #[test]
+  fn interpreter() {
+    let run = |interpreter: &Interpreter, input: &str| {
+      let item = parse(input.as_bytes()).unwrap();
+      match interpreter.evaluate(&item) {
+        Ok(item) => item.to_string(),
+        Err(err) => err.to_string(),
+      }
+    };
+
+    // a host primitive shadows a built-in and a special form
+    let interpreter = Interpreter::new()
+      .primitive("first", Arity::exactly(1), "Always x", |_, _| {
+        Ok(Item::new_atom(b"x"))
+      })
+      .primitive("quote", Arity::at_least(0), "Count", |_, args| {
+        Ok(Item::new_atom(args.len().to_string().as_bytes()))
+      });
+    assert_eq!(run(&interpreter, "first (list a b)"), "x");
+    assert_eq!(run(&interpreter, "quote (list a b) c"), "2");
+    assert_eq!(interpreter.docs("first"), Some("Always x"));
+
+    // a sandbox without definitions
+    let sandbox = Interpreter::new().remove("define").remove("set!");
+    assert_eq!(
+      run(&sandbox, "define x a"),
+      "EvalError: unknown operator define in (define x a)"
+    );
+    assert_eq!(run(&sandbox, "let ((x a)) x"), "a");
+    assert!(!sandbox.names().contains(&"define"));
+
+    // host primitives see the environment and typed arguments
+    let interpreter = Interpreter::empty()
+      .primitive("lookup", Arity::exactly(1), "", |ctx, args| {
+        ctx.evaluate(&args.first())
+      })
+      .primitive("add", Arity::between(1, 2), "", |ctx, args| {
+        let a: u64 = ctx.arg(args, 0)?;
+        let b: Option<u64> = ctx.arg(args, 1)?;
+        let offset = ctx.env().get(b"offset").unwrap_or_else(Item::nil);
+        let offset = Option::<u64>::from_item(&offset).unwrap();
+        let sum = a + b.unwrap_or(0) + offset.unwrap_or(0);
+        Ok(Item::new_atom(sum.to_string().as_bytes()))
+      });
+    assert_eq!(interpreter.names(), ["add", "lookup"]);
+    assert_eq!(interpreter.arity("add"), Some(Arity::between(1, 2)));
+    let env = Env::new();
+    env.define(b"offset", Item::new_atom(b"10"));
+    env.define(b"x", Item::new_atom(b"y"));
+    env.define(b"y", Item::new_atom(b"z"));
+    let run_in = |input: &str| {
+      let item = parse(input.as_bytes()).unwrap();
+      interpreter.evaluate_in(&item, &env).map(|item| item.to_string())
+    };
+    assert_eq!(run_in("add 1 2").unwrap(), "13");
+    assert_eq!(run_in("add 2").unwrap(), "12");
+    assert_eq!(run_in("lookup x").unwrap(), "z");
+    let err = run_in("add 1 x").unwrap_err();
+    assert_eq!(err.kind(), EvalErrorKind::TypeError);
+    assert_eq!(
+      err.to_string(),
+      "EvalError: argument 2 must be a number in (add 1 x)"
+    );
+    assert_eq!(run_in("add").unwrap_err().kind(), EvalErrorKind::ArityMismatch);
+    assert_eq!(
+      run_in("list 1").unwrap_err().to_string(),
+      "EvalError: unknown operator list in (list 1)"
+    );
+  }
+
+  #[derive(Clone, Default)]
+  struct Output(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for Output {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+      self.0.lock().unwrap().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  struct Broken;
+
+  impl Write for Broken {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+      Err(io::Error::other("broken"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn instances() {
+    fn send<T: Send>(_: &T) {}
+
+    let output = Output::default();
+    let one = Interpreter::new().output(output.clone());
+    let two =
+      Interpreter::new().define("x", Item::new_atom(b"two")).remove("print");
+    send(&one);
+    one.evaluate(&parse(b"define x one").unwrap()).unwrap();
+    let x = parse(b"list x").unwrap();
+    assert_eq!(one.evaluate(&x).unwrap().to_string(), "(one)");
+    assert_eq!(two.evaluate(&x).unwrap().to_string(), "(two)");
+
+    let print = parse(b"print x (list x)").unwrap();
+    assert_eq!(one.evaluate(&print), Ok(Item::nil()));
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"( one (one) )");
+    assert_eq!(
+      two.evaluate(&print).unwrap_err().kind(),
+      EvalErrorKind::UnknownOperator
+    );
+
+    // an interpreter moves to another thread with its state
+    let thread = std::thread::spawn(move || one.evaluate(&x).unwrap());
+    assert_eq!(thread.join().unwrap().to_string(), "(one)");
+
+    let broken = Interpreter::new().output(Broken);
+    let err = broken.evaluate(&print).unwrap_err();
+    assert_eq!(err.kind(), EvalErrorKind::Io);
+    assert_eq!(err.reason(), "writing the output: broken");
+  }
 
-static PRIMITIVES: std::sync::OnceLock<Primitives> = std::sync::OnceLock::new();
+  #[test]
+  fn limits() {
+    let interpreter = Interpreter::new();
+    let define = "define loop (lambda (x) (loop (list x)))";
+    interpreter.evaluate(&parse(define.as_bytes()).unwrap()).unwrap();
+    let err = interpreter.evaluate(&parse(b"loop a").unwrap()).unwrap_err();
+    assert_eq!(err.kind(), EvalErrorKind::LimitExceeded);
+    assert_eq!(err.reason(), "calls nested deeper than 256");
+    assert_eq!(err.stack().len(), 256);
+    // the interpreter is usable after an error
+    let first = parse(b"first (list a)").unwrap();
+    assert_eq!(interpreter.evaluate(&first).unwrap().to_string(), "a");
+
+    let limits = EvalLimits { max_depth: 3, max_steps: 5 };
+    let interpreter = Interpreter::new().limits(limits);
+    let deep = parse(b"list (list (list (list a)))").unwrap();
+    assert_eq!(
+      interpreter.evaluate(&deep).unwrap_err().kind(),
+      EvalErrorKind::LimitExceeded
+    );
+    let wide =
+      parse(b"list (list a) (list b) (list c) (list d) (list e)").unwrap();
+    assert_eq!(
+      interpreter.evaluate(&wide).unwrap_err().to_string(),
+      "EvalError: more than 5 calls in (list e)\n  \
+      in (list (list a) (list b) (list c) (list d) (list e))"
+    );
+    // steps count per evaluation
+    let four = parse(b"list (list a) (list b) (list c)").unwrap();
+    assert!(interpreter.evaluate(&four).is_ok());
+    assert!(interpreter.evaluate(&four).is_ok());
+  }
+
+  #[test]
+  fn errors() {
+    use EvalErrorKind::*;
+    assert_eq!(
+      err("frist x"),
+      (
+        UnknownOperator,
+        "EvalError: unknown operator frist in (frist x)".into()
+      )
+    );
+    assert_eq!(
+      err("quote a b"),
+      (
+        ArityMismatch,
+        "EvalError: quote takes 1 argument, got 2 in (quote a b)".into()
+      )
+    );
+    assert_eq!(
+      err("if a").1,
+      "EvalError: if takes 2 to 3 arguments, got 1 in (if a)"
+    );
+    assert_eq!(
+      err("(k: v) x"),
+      (TypeError, "EvalError: (k: v) is not an operator in ((k: v) x)".into())
+    );
+    assert_eq!(err("(quote (a)) x").0, TypeError);
+    assert_eq!(
+      err("eval (quote (tail x))").1,
+      "EvalError: tail takes a list, not x in (tail x)\n  \
+      in (eval (quote (tail x)))"
+    );
+    // an error in the condition, not in the branch not taken
+    assert_eq!(err("if (first a) (nope) b").0, TypeError);
+
+    assert_eq!(
+      err("set! x a"),
+      (UnboundSymbol, "EvalError: x is not defined in (set! x a)".into())
+    );
+    assert_eq!(
+      err("define (x) a").1,
+      "EvalError: (x) is not a name in (define (x) a)"
+    );
+    assert_eq!(err("lambda x x").0, TypeError);
+    assert_eq!(
+      err("let (x) x").1,
+      "EvalError: x is not a name and a value in (let (x) x)"
+    );
+    assert_eq!(
+      err("begin (define f (lambda (x) (first x))) (f a b)").1,
+      "EvalError: f takes 1 argument, got 2 in (f a b)\n  \
+      in (begin (define f (lambda (x) (first x))) (f a b))"
+    );
+    assert_eq!(
+      err("begin (define f (lambda (x) (first x))) (f a)").1,
+      "EvalError: first takes a list, not a in (first x)\n  \
+      in (f a)\n  \
+      in (begin (define f (lambda (x) (first x))) (f a))"
+    );
+  }
+
+  #[test]
+  fn spans() {
+    let input = b"if (first (quote (x)))\n  (first (quote (a)))\n  (bad x)";
+    let (command, spans) = parse_spanned(input).unwrap();
+    assert_eq!(evaluate_spanned(&command, &spans).unwrap().to_string(), "a");
+
+    let input = b"if (first (quote ()))\n  a\n  (first\n    (bad x))";
+    let (command, spans) = parse_spanned(input).unwrap();
+    let err = evaluate_spanned(&command, &spans).unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.col), (4, 5));
+    assert_eq!(err.stack().len(), 2);
+    assert_eq!(err.stack()[0].to_string(), "(first (bad x))");
+
+    // forms built while evaluating have no span
+    let (command, spans) = parse_spanned(b"eval (quote (bad))").unwrap();
+    let err = evaluate_spanned(&command, &spans).unwrap_err();
+    assert_eq!(err.span(), None);
+  }
+}
 
 // Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Component, PathBuf};
+
+use crate::merge::merge_maps;
+use crate::{parse_spanned, Item, ParseError, Path};
+use crate::{Span, Spans};
+
+const INCLUDE: &[u8] = b"@include";
+
+/// Loads included documents by name, see [`Resolver`]
+pub trait Loader {
+  fn load(&self, name: &str) -> io::Result<Vec<u8>>;
+}
+
+/// Loads files below a root directory, names are relative to the root and
+/// must not leave it.
+#[derive(Clone, Debug)]
+pub struct FsLoader {
+  root: PathBuf,
+}
+
+impl FsLoader {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    FsLoader { root: root.into() }
+  }
+}
+
+impl Loader for FsLoader {
+  fn load(&self, name: &str) -> io::Result<Vec<u8>> {
+    let path = std::path::Path::new(name);
+    let inside =
+      |c: Component| matches!(c, Component::Normal(_) | Component::CurDir);
+    if !path.components().all(inside) {
+      let msg = format!("{name} is outside of {}", self.root.display());
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+    std::fs::read(self.root.join(path))
+  }
+}
+
+/// Documents in memory by name, for tests
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLoader(HashMap<String, Vec<u8>>);
+
+impl MemoryLoader {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn insert(&mut self, name: &str, document: &[u8]) -> &mut Self {
+    self.0.insert(name.to_string(), document.to_vec());
+    self
+  }
+}
+
+impl<'a> FromIterator<(&'a str, &'a str)> for MemoryLoader {
+  fn from_iter<I: IntoIterator<Item = (&'a str, &'a str)>>(iter: I) -> Self {
+    let documents = iter.into_iter();
+    MemoryLoader(documents.map(|(k, v)| (k.into(), v.into())).collect())
+  }
+}
+
+impl Loader for MemoryLoader {
+  fn load(&self, name: &str) -> io::Result<Vec<u8>> {
+    let document = self.0.get(name).cloned();
+    document.ok_or_else(|| io::ErrorKind::NotFound.into())
+  }
+}
+
+#[derive(Debug)]
+pub enum IncludeError {
+  Load {
+    name: String,
+    error: io::Error,
+  },
+  Parse {
+    name: String,
+    error: ParseError,
+  },
+  /// The names of the documents including each other, the first is the last
+  Cycle(Vec<String>),
+  /// The names of the documents including each other, too many of them
+  Depth(Vec<String>),
+  /// A bad include in a document, the name is empty for the expanded item
+  Bad {
+    name: String,
+    path: Path,
+    reason: String,
+  },
+}
+
+impl Error for IncludeError {}
+
+impl fmt::Display for IncludeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("IncludeError: ")?;
+    match self {
+      IncludeError::Load { name, error } => write!(f, "{name}: {error}"),
+      IncludeError::Parse { name, error } => write!(f, "{name}: {error}"),
+      IncludeError::Cycle(names) => {
+        write!(f, "include cycle {}", names.join(" -> "))
+      }
+      IncludeError::Depth(names) => {
+        write!(f, "includes too deep {}", names.join(" -> "))
+      }
+      IncludeError::Bad { name, path, reason } => {
+        write!(f, "{name} at {path}: {reason}")
+      }
+    }
+  }
+}
+
+/// Expands include directives with documents from a [`Loader`].
+///
+/// A list `(@include "name")` is replaced by the document. A map entry
+/// `@include: name` or `@include: (name ...)` merges the maps of the
+/// documents into the map, its other entries take precedence.
+///
+/// ```
+/// # use axp::{parse, MemoryLoader, Resolver};
+/// let loader = MemoryLoader::from_iter([
+///   ("main.axp", "@include: defaults.axp db: (@include db.axp)"),
+///   ("defaults.axp", "name: app db: none"),
+///   ("db.axp", "host: localhost\nport: 5432"),
+/// ]);
+/// let resolved = Resolver::new(loader).resolve("main.axp").unwrap();
+/// let expected = "name: app db: (host: localhost port: 5432)";
+/// assert_eq!(resolved.item(), &parse(expected.as_bytes()).unwrap());
+///
+/// let (name, span) = resolved.span(&"db/port".parse().unwrap()).unwrap();
+/// assert_eq!(format!("{name}:{span}"), "db.axp:2:7");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Resolver<L: Loader> {
+  loader: L,
+  max_depth: usize,
+}
+
+impl<L: Loader> Resolver<L> {
+  /// A resolver which allows 16 nested includes
+  pub fn new(loader: L) -> Self {
+    Resolver { loader, max_depth: 16 }
+  }
+
+  pub fn max_depth(mut self, max_depth: usize) -> Self {
+    self.max_depth = max_depth;
+    self
+  }
+
+  /// Load a document and expand its includes
+  pub fn resolve(&self, name: &str) -> Result<Resolved, IncludeError> {
+    let mut expand = Expand::new(self);
+    let item = expand.document(name, &Path::root())?;
+    Ok(expand.resolved(item))
+  }
+
+  /// Expand the includes of a parsed item
+  pub fn expand(&self, item: &Item) -> Result<Resolved, IncludeError> {
+    let mut expand = Expand::new(self);
+    let file = File { name: "", index: None, spans: Spans::default() };
+    let root = Path::root();
+    let item = expand.item(&file, &root, &root, item)?;
+    Ok(expand.resolved(item))
+  }
+}
+
+/// A document with expanded includes and the spans of its items in the
+/// documents they come from
+#[derive(Clone, Debug)]
+pub struct Resolved {
+  item: Item,
+  names: Vec<String>,
+  spans: BTreeMap<Path, (usize, Span)>,
+}
+
+impl Resolved {
+  pub fn item(&self) -> &Item {
+    &self.item
+  }
+
+  pub fn into_item(self) -> Item {
+    self.item
+  }
+
+  /// The names of the loaded documents in load order
+  pub fn names(&self) -> &[String] {
+    &self.names
+  }
+
+  /// The name of the document and the span of the item at the path
+  pub fn span(&self, path: &Path) -> Option<(&str, Span)> {
+    let (index, span) = self.spans.get(path)?;
+    Some((&self.names[*index], *span))
+  }
+
+  // The index into names of the document of the item at the path and its
+  // span there
+  pub(crate) fn located(&self, path: &Path) -> Option<(usize, Span)> {
+    self.spans.get(path).copied()
+  }
+}
+
+// A loaded document, the index is into `Resolved::names`
+struct File<'n> {
+  name: &'n str,
+  index: Option<usize>,
+  spans: Spans,
+}
+
+struct Expand<'r, L: Loader> {
+  resolver: &'r Resolver<L>,
+  stack: Vec<String>,
+  names: Vec<String>,
+  spans: BTreeMap<Path, (usize, Span)>,
+}
+
+impl<'r, L: Loader> Expand<'r, L> {
+  fn new(resolver: &'r Resolver<L>) -> Self {
+    let (stack, names, spans) = Default::default();
+    Expand { resolver, stack, names, spans }
+  }
+
+  // Forget the spans of items replaced by merging
+  fn resolved(mut self, item: Item) -> Resolved {
+    self.spans.retain(|path, _| item.get(path).is_some());
+    Resolved { item, names: self.names, spans: self.spans }
+  }
+
+  // Load, parse and expand a document for the path in the result
+  fn document(&mut self, name: &str, out: &Path) -> Result<Item, IncludeError> {
+    let mut names = self.stack.clone();
+    names.push(name.to_string());
+    if self.stack.iter().any(|n| n == name) {
+      return Err(IncludeError::Cycle(names));
+    }
+    if self.stack.len() > self.resolver.max_depth {
+      return Err(IncludeError::Depth(names));
+    }
+
+    let bytes = self.resolver.loader.load(name);
+    let bytes = bytes
+      .map_err(|error| IncludeError::Load { name: name.to_string(), error })?;
+    let (item, spans) = parse_spanned(&bytes)
+      .map_err(|error| IncludeError::Parse { name: name.to_string(), error })?;
+
+    let index = self.names.len();
+    self.names.push(name.to_string());
+    let file = File { name, index: Some(index), spans };
+
+    self.stack.push(name.to_string());
+    let item = self.item(&file, &Path::root(), out, &item);
+    self.stack.pop();
+    item
+  }
+
+  // Expand an item at the path in its document and the path in the result
+  fn item(
+    &mut self,
+    file: &File,
+    at: &Path,
+    out: &Path,
+    item: &Item,
+  ) -> Result<Item, IncludeError> {
+    if let (Some(index), Some(span)) = (file.index, file.spans.get(at)) {
+      self.spans.insert(out.clone(), (index, *span));
+    }
+
+    let bad = |reason: &str| IncludeError::Bad {
+      name: file.name.to_string(),
+      path: at.clone(),
+      reason: reason.to_string(),
+    };
+
+    match item {
+      Item::Atom(_) => Ok(item.clone()),
+      Item::List(list) if list.get(0) == Some(&Item::new_atom(INCLUDE)) => {
+        match list.0.as_slice() {
+          [_, Item::Atom(name)] => self.document(&text(name, bad)?, out),
+          _ => Err(bad("include needs one name")),
+        }
+      }
+      Item::List(list) => {
+        let items = list.iter().enumerate().map(|(i, item)| {
+          self.item(file, &at.join_index(i), &out.join_index(i), item)
+        });
+        Ok(Item::new_list(items.collect::<Result<Vec<_>, _>>()?))
+      }
+      Item::Map(map) => {
+        let include = Item::new_atom(INCLUDE);
+        let (mut layers, mut bases) = (Vec::new(), Vec::new());
+        for (key, value) in map.iter().filter(|(key, _)| *key == include) {
+          let names = match value {
+            Item::Atom(name) => vec![text(name, bad)?],
+            Item::List(list) => list
+              .iter()
+              .map(|name| match name {
+                Item::Atom(name) => text(name, bad),
+                _ => Err(bad("include names must be atoms")),
+              })
+              .collect::<Result<_, _>>()?,
+            Item::Map(_) => Err(bad("include names must be atoms"))?,
+          };
+          for name in names {
+            layers.push(self.document(&name, out)?);
+            bases.push((key, name));
+          }
+        }
+
+        let entries = map.iter().filter(|(key, _)| *key != include);
+        let entries = entries.map(|(key, value)| {
+          let (at, out) = (at.join(key.clone()), out.join(key.clone()));
+          Ok((key.clone(), self.item(file, &at, &out, value)?))
+        });
+        let entries = entries.collect::<Result<Vec<_>, _>>()?;
+        if !entries.is_empty() {
+          layers.push(Item::new_map(entries));
+        }
+        merge_maps(&layers).map_err(|index| {
+          let (key, name) = &bases[index];
+          IncludeError::Bad {
+            name: file.name.to_string(),
+            path: at.join((*key).clone()),
+            reason: format!("{name} is not a map"),
+          }
+        })
+      }
+    }
+  }
+}
+
+fn text<E>(atom: &crate::Atom, bad: impl Fn(&str) -> E) -> Result<String, E> {
+  let name = std::str::from_utf8(atom.as_bytes());
+  name.map(String::from).map_err(|_| bad("include name must be text"))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+
+  use super::{FsLoader, IncludeError, Loader, MemoryLoader, Resolver};
+  use crate::{canonical, parse};
+
+  fn resolve(documents: &[(&str, &str)]) -> Result<String, IncludeError> {
+    let loader = documents.iter().copied().collect::<MemoryLoader>();
+    let resolved = Resolver::new(loader).resolve(documents[0].0)?;
+    Ok(String::from_utf8(canonical(resolved.item())).unwrap())
+  }
+
+  #[test]
+  fn includes() {
+    assert_eq!(
+      resolve(&[
+        ("a", "x: (1 (@include b) 3) @include: (c d) z: 5"),
+        ("b", "2"),
+        ("c", "y: 0 z: 0 w: (p: 1)"),
+        ("d", "w: (q: 2)"),
+      ])
+      .unwrap(),
+      "y: 0 z: 5 w: (p: 1 q: 2) x: (1 (2) 3)"
+    );
+    assert_eq!(resolve(&[("a", "@include: b"), ("b", "")]).unwrap(), "");
+    assert_eq!(resolve(&[("a", "x"), ("b", "y")]).unwrap(), "x");
+  }
+
+  #[test]
+  fn errors() {
+    let err = |documents| resolve(documents).unwrap_err().to_string();
+    assert_eq!(
+      err(&[
+        ("a", "(@include b)"),
+        ("b", "x: (@include c)"),
+        ("c", "@include: a")
+      ]),
+      "IncludeError: include cycle a -> b -> c -> a"
+    );
+    assert_eq!(
+      err(&[("a", "(@include a)")]),
+      "IncludeError: include cycle a -> a"
+    );
+    assert_eq!(
+      err(&[("a", "x: (@include b c)")]),
+      "IncludeError: a at x: include needs one name"
+    );
+    assert_eq!(
+      err(&[("a", "x: (@include: b)"), ("b", "1 2")]),
+      "IncludeError: a at x/@include: b is not a map"
+    );
+    assert_eq!(
+      err(&[("a", "(@include b)")]),
+      "IncludeError: b: entity not found"
+    );
+    assert!(err(&[("a", "(@include b)"), ("b", "(")])
+      .starts_with("IncludeError: b: ParseError"));
+
+    let loader = MemoryLoader::from_iter([
+      ("0", "(@include 1)"),
+      ("1", "(@include 2)"),
+      ("2", "x"),
+    ]);
+    let resolver = Resolver::new(loader).max_depth(1);
+    assert_eq!(
+      resolver.resolve("0").unwrap_err().to_string(),
+      "IncludeError: includes too deep 0 -> 1 -> 2"
+    );
+    assert!(resolver.max_depth(2).resolve("0").is_ok());
+  }
+
+  #[test]
+  fn spans() {
+    let loader = MemoryLoader::from_iter([
+      ("a", "l: (x (@include b))\n@include: c\nk: 1"),
+      ("b", "# b\n  (p: 1)"),
+      ("c", "k: 0\nm: n"),
+    ]);
+    let resolved = Resolver::new(loader).resolve("a").unwrap();
+    let spans = resolved
+      .spans
+      .keys()
+      .map(|path| {
+        let (name, span) = resolved.span(path).unwrap();
+        format!("{path} {name}:{span}")
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      spans,
+      [
+        "k a:3:4",
+        "l a:1:4",
+        "l/0 a:1:5",
+        "l/1 a:1:7",
+        "l/1/0 b:2:3",
+        "l/1/0/p b:2:7",
+        "m c:2:4"
+      ]
+    );
+    assert_eq!(resolved.names(), ["a", "c", "b"]);
+  }
+
+  // A directory which is removed when dropped, also if a test fails
+  struct TempDir(PathBuf);
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  #[test]
+  fn expand_and_fs() {
+    let dir =
+      std::env::temp_dir().join(format!("axp-include-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let _guard = TempDir(dir.clone());
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/db.axp"), "port: 5432").unwrap();
+
+    let loader = FsLoader::new(&dir);
+    assert!(loader.load("../etc/passwd").is_err());
+    assert!(loader.load("/etc/passwd").is_err());
+
+    let item = parse(b"db: (@include sub/db.axp)").unwrap();
+    let resolved = Resolver::new(loader).expand(&item).unwrap();
+    assert_eq!(resolved.item(), &parse(b"db: (port: 5432)").unwrap());
+    let (name, span) = resolved.span(&"db/port".parse().unwrap()).unwrap();
+    assert_eq!((name, span.line, span.col), ("sub/db.axp", 1, 7));
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::{Atom, Item, Path};
+
+/// Options for [`interpolate()`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InterpolateOptions {
+  /// Undefined variables are errors instead of empty
+  pub strict: bool,
+  /// The environment variables, `None` for the ones of the process
+  pub env: Option<HashMap<String, String>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InterpolateError {
+  path: Path,
+  reason: String,
+}
+
+impl Error for InterpolateError {}
+
+impl InterpolateError {
+  /// The path of the atom which could not be expanded
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for InterpolateError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "InterpolateError: {}: {}", self.path, self.reason)
+  }
+}
+
+/// Expand variables in the atoms of the values of a document.
+///
+/// - `${ENV:NAME}` is the environment variable `NAME`
+/// - `${path:server/host}` is the atom at the path in the document, if the
+///   whole atom is the reference it can also be a list or a map
+/// - `${ENV:PORT:-8080}` is `8080` if the variable is undefined or empty,
+///   the default can contain references as well
+/// - `$$` is a literal `$`, so `$${` is a literal `${`
+///
+/// Map keys are not expanded. Undefined variables are empty unless strict.
+/// Tagged atoms keep their tags.
+/// Bare atoms cannot contain `:`, so variables go into quoted atoms.
+///
+/// ```
+/// # use axp::{interpolate, parse, InterpolateOptions};
+/// let env = [("HOME".to_string(), "/home/me".to_string())];
+/// let options = InterpolateOptions { env: Some(env.into()), ..Default::default() };
+/// let item = parse(br#"
+///   dir: "${ENV:HOME}/app"
+///   log: "${path:dir}/log"
+///   port: "${ENV:PORT:-8080}"
+///   doc: "use $${ENV:HOME}"
+/// "#).unwrap();
+/// let expected = parse(br#"
+///   dir: /home/me/app
+///   log: /home/me/app/log
+///   port: 8080
+///   doc: "use ${ENV:HOME}"
+/// "#).unwrap();
+/// assert_eq!(interpolate(&item, &options), Ok(expected));
+/// ```
+pub fn interpolate(
+  item: &Item,
+  options: &InterpolateOptions,
+) -> Result<Item, InterpolateError> {
+  let mut interpolate = Interpolate {
+    root: item,
+    options,
+    stack: Vec::new(),
+    done: HashMap::new(),
+  };
+  interpolate.item(&Path::root(), item)
+}
+
+struct Interpolate<'i> {
+  root: &'i Item,
+  options: &'i InterpolateOptions,
+  // the paths being expanded, a reference to them is a cycle
+  stack: Vec<Path>,
+  done: HashMap<Path, Item>,
+}
+
+impl<'i> Interpolate<'i> {
+  fn error(&self, path: &Path, reason: impl Into<String>) -> InterpolateError {
+    InterpolateError { path: path.clone(), reason: reason.into() }
+  }
+
+  fn item(
+    &mut self,
+    path: &Path,
+    item: &Item,
+  ) -> Result<Item, InterpolateError> {
+    if self.stack.contains(path) {
+      let mut cycle = self.stack.iter().skip_while(|p| *p != path);
+      let cycle = cycle.by_ref().chain([path]).map(ToString::to_string);
+      let cycle = cycle.collect::<Vec<_>>().join(" -> ");
+      return Err(
+        self.error(self.stack.last().unwrap(), format!("cycle {cycle}")),
+      );
+    }
+
+    self.stack.push(path.clone());
+    let result = match item {
+      Item::Atom(atom) if !atom.as_bytes().contains(&b'$') => {
+        Ok(item.clone())
+      }
+      // the expanded atom keeps the tag
+      Item::Atom(atom) => match self.atom(path, atom.as_bytes()) {
+        Ok(Item::Atom(Atom(bytes, _))) => {
+          Ok(Item::Atom(Atom(bytes, atom.1.clone())))
+        }
+        result => result,
+      },
+      Item::List(list) => {
+        let items = list.iter().enumerate();
+        let items = items.map(|(i, item)| self.item(&path.join_index(i), item));
+        items.collect::<Result<Vec<_>, _>>().map(Item::new_list)
+      }
+      Item::Map(map) => {
+        let entries = map.iter().map(|(key, value)| {
+          Ok((key.clone(), self.item(&path.join(key.clone()), value)?))
+        });
+        entries.collect::<Result<Vec<_>, _>>().map(Item::new_map)
+      }
+    };
+    self.stack.pop();
+    result
+  }
+
+  // The expanded item at a path of the document
+  fn reference(
+    &mut self,
+    path: &Path,
+  ) -> Option<Result<Item, InterpolateError>> {
+    if let Some(item) = self.done.get(path) {
+      return Some(Ok(item.clone()));
+    }
+    let item = self.item(path, self.root.get(path)?);
+    if let Ok(item) = &item {
+      self.done.insert(path.clone(), item.clone());
+    }
+    Some(item)
+  }
+
+  fn atom(
+    &mut self,
+    path: &Path,
+    atom: &[u8],
+  ) -> Result<Item, InterpolateError> {
+    let mut out = Vec::new();
+    let mut rest = atom;
+    while let Some(pos) = rest.iter().position(|&byte| byte == b'$') {
+      out.extend_from_slice(&rest[..pos]);
+      rest = &rest[pos..];
+      // `$$` is a literal `$`, a `$` without `{` is kept
+      let skip = match rest {
+        [b'$', b'$', ..] => 2,
+        [b'$', b'{', ..] => 0,
+        _ => 1,
+      };
+      if skip > 0 {
+        out.push(b'$');
+        rest = &rest[skip..];
+        continue;
+      }
+
+      let Some(len) = closing(&rest[2..]) else {
+        return Err(self.error(path, "unterminated ${"));
+      };
+      let expression = &rest[2..2 + len];
+      let whole = out.is_empty() && 3 + len == rest.len();
+      rest = &rest[3 + len..];
+
+      match self.expression(path, expression)? {
+        item if whole && !item.is_atom() => return Ok(item),
+        Item::Atom(atom) => out.extend_from_slice(atom.as_bytes()),
+        _ => {
+          let expression = String::from_utf8_lossy(expression);
+          return Err(self.error(path, format!("{expression} is not an atom")));
+        }
+      }
+    }
+    out.extend_from_slice(rest);
+    Ok(Item::new_atom(&out))
+  }
+
+  // `source:name` or `source:name:-default`
+  fn expression(
+    &mut self,
+    path: &Path,
+    expression: &[u8],
+  ) -> Result<Item, InterpolateError> {
+    let (name, default) = match find(expression, b":-") {
+      Some(pos) => (&expression[..pos], Some(&expression[pos + 2..])),
+      None => (expression, None),
+    };
+    let name = String::from_utf8_lossy(name);
+
+    let value = match name.split_once(':') {
+      Some(("ENV", var)) => match &self.options.env {
+        Some(env) => env.get(var).cloned(),
+        None => std::env::var(var).ok(),
+      }
+      .filter(|value| !value.is_empty())
+      .map(|value| Item::new_atom(value.as_bytes())),
+      Some(("path", reference)) => {
+        let Ok(reference) = reference.parse::<Path>() else {
+          return Err(self.error(path, format!("bad path {reference}")));
+        };
+        self.reference(&reference).transpose()?
+      }
+      _ => return Err(self.error(path, format!("unknown variable {name}"))),
+    };
+
+    match (value, default) {
+      (Some(value), _) => Ok(value),
+      (None, Some(default)) => self.atom(path, default),
+      (None, None) if self.options.strict => {
+        Err(self.error(path, format!("undefined {name}")))
+      }
+      (None, None) => Ok(Item::new_atom(b"")),
+    }
+  }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// The length up to the `}` closing a `${`, nested ones included
+fn closing(bytes: &[u8]) -> Option<usize> {
+  let mut depth = 0;
+  for (i, &byte) in bytes.iter().enumerate() {
+    match byte {
+      b'{' if i > 0 && bytes[i - 1] == b'$' => depth += 1,
+      b'}' if depth == 0 => return Some(i),
+      b'}' => depth -= 1,
+      _ => (),
+    }
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{interpolate, InterpolateOptions};
+  use crate::{canonical, parse};
+
+  fn expand(input: &str, strict: bool) -> Result<String, String> {
+    let env = [("A", "a"), ("EMPTY", ""), ("B", "${ENV:A}")];
+    let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+    let options = InterpolateOptions { strict, env: Some(env.collect()) };
+    let item = parse(input.as_bytes()).unwrap();
+    match interpolate(&item, &options) {
+      Ok(item) => Ok(String::from_utf8(canonical(&item)).unwrap()),
+      Err(err) => Err(err.to_string()),
+    }
+  }
+
+  #[test]
+  fn variables() {
+    let ok = |input| expand(input, false).unwrap();
+    assert_eq!(ok(r#"x: "${ENV:A}${ENV:A}""#), "x: aa");
+    assert_eq!(ok(r#"x: "${ENV:B}""#), r#"x: "${ENV:A}""#);
+    assert_eq!(ok(r#"x: "${ENV:NONE}""#), r#"x: """#);
+    assert_eq!(ok(r#"x: "${ENV:EMPTY:-d}""#), "x: d");
+    assert_eq!(ok(r#"x: "${ENV:NONE:-${ENV:A}-}""#), "x: a-");
+    assert_eq!(ok(r#"x: "$${ENV:A}" y: $${z}"#), r#"x: "${ENV:A}" y: ${z}"#);
+    assert_eq!(
+      ok(r#"x: "$$${ENV:A}" y: "$$$${ENV:A}""#),
+      r#"x: $a y: "$${ENV:A}""#
+    );
+    assert_eq!(ok(r#"x: "a$b$" y: "$$$""#), r#"x: a$b$ y: $$"#);
+    assert_eq!(ok(r#""${ENV:A}": x"#), r#""${ENV:A}": x"#);
+    assert_eq!(
+      ok(r##"x: #(date)"2023-10-14#(date)" y: #(p)"${ENV:A}/b#(p)""##),
+      r##"x: #(date)"2023-10-14#(date)" y: #(p)"a/b#(p)""##
+    );
+  }
+
+  #[test]
+  fn references() {
+    let ok = |input| expand(input, false).unwrap();
+    assert_eq!(
+      ok(r#"a: (h: x p: 1) b: "${path:a}" c: "${path:a/h}-${path:a/p}""#),
+      "a: (h: x p: 1) b: (h: x p: 1) c: x-1"
+    );
+    assert_eq!(
+      ok(r#"a: "${path:b}" b: "${path:c/0}" c: ("${ENV:A}")"#),
+      "a: a b: a c: (a)"
+    );
+    assert_eq!(ok(r#"a: "${path:b:-none}""#), "a: none");
+  }
+
+  #[test]
+  fn errors() {
+    let err = |input, strict| expand(input, strict).unwrap_err();
+    assert_eq!(
+      err(r#"x: "${ENV:NONE}""#, true),
+      "InterpolateError: x: undefined ENV:NONE"
+    );
+    assert_eq!(
+      err(r#"x: "${path:y}""#, true),
+      "InterpolateError: x: undefined path:y"
+    );
+    assert_eq!(
+      err(r#"a: "${path:b}" b: ("${path:a}")"#, false),
+      "InterpolateError: b/0: cycle a -> b -> b/0 -> a"
+    );
+    assert_eq!(
+      err(r#"a: (x: "${path:a}")"#, false),
+      "InterpolateError: a/x: cycle a -> a/x -> a"
+    );
+    assert_eq!(
+      err(r#"x: "${ENV:A""#, false),
+      "InterpolateError: x: unterminated ${"
+    );
+    assert_eq!(
+      err(r#"x: "${FOO:A}""#, false),
+      "InterpolateError: x: unknown variable FOO:A"
+    );
+    assert_eq!(
+      err(r#"a: (1 2) b: "x${path:a}""#, false),
+      "InterpolateError: b: path:a is not an atom"
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -9,8 +9,27 @@ use crate::{Atom, List, Map};
 /// let atom = Item::new_atom(b"atom");
 /// assert_eq!(format!("{atom}"), "atom");
 /// ```
+///
+/// Items have a total order: atoms come before lists and lists before maps.
+/// Atoms compare bytewise, lists and maps lexicographically by their items
+/// and entries. `Hash` is consistent with this order, so items can be used as
+/// keys of a `HashMap` or a `BTreeMap`.
+///
+/// ```
+/// # use axp::Item;
+/// let mut items = vec![
+///   Item::new_map([(Item::new_atom(b"k"), Item::new_atom(b"v"))]),
+///   Item::new_list([Item::new_atom(b"a")]),
+///   Item::new_atom(b"b"),
+///   Item::new_list([]),
+///   Item::new_atom(b"a"),
+/// ];
+/// items.sort();
+/// let items = items.iter().map(|i| format!("{i}")).collect::<Vec<_>>();
+/// assert_eq!(items, ["a", "b", "()", "(a)", "(k: v)"]);
+/// ```
 #[allow(dead_code)]
-#[derive(Clone, Eq, PartialEq)]
+#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Item {
   Atom(Atom),
   List(List),
@@ -102,6 +121,29 @@ impl Item {
   pub fn is_map(&self) -> bool {
     matches!(self, Item::Map(_))
   }
+
+  /// Clone the item with the entries of all maps sorted, recursively.
+  ///
+  /// ```
+  /// # use axp::Item;
+  /// let map = Item::new_map([
+  ///   (Item::new_atom(b"b"), Item::new_atom(b"2")),
+  ///   (Item::new_atom(b"a"), Item::new_atom(b"1")),
+  /// ]);
+  /// assert_eq!(format!("{}", map.sorted()), "(a: 1 b: 2)");
+  /// ```
+  pub fn sorted(&self) -> Item {
+    match self {
+      Item::Atom(_) => self.clone(),
+      Item::List(list) => Item::new_list(list.iter().map(Item::sorted)),
+      Item::Map(map) => {
+        let entries = map.0.iter().map(|(k, v)| (k.sorted(), v.sorted()));
+        let mut entries = entries.collect::<Vec<_>>();
+        entries.sort();
+        Item::new_map(entries)
+      }
+    }
+  }
 }
 
 // Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
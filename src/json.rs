@@ -0,0 +1,182 @@
+use serde_json::{Number, Value};
+
+use crate::canonical::write_item;
+use crate::Item;
+
+/// How [`to_json()`] writes atoms
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Infer {
+  /// Atoms which are JSON numbers and are written back the same become
+  /// numbers, `true`, `false` and `null` become booleans and null, the rest
+  /// become strings
+  #[default]
+  Scalars,
+  /// All atoms become strings
+  Strings,
+}
+
+/// Options for [`to_json()`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct JsonOptions {
+  pub infer: Infer,
+}
+
+/// Convert JSON to an item.
+///
+/// Objects become maps and arrays become lists. Strings, numbers, booleans
+/// and null become atoms of their text, so the types are lost: `"42"` and
+/// `42` are the same atom.
+///
+/// ```
+/// # use axp::{from_json, parse};
+/// let json = serde_json::json!({"port": 80, "tags": [true, null]});
+/// let item = parse(b"port: 80 tags: (true null)").unwrap();
+/// assert_eq!(from_json(&json), item);
+/// ```
+pub fn from_json(value: &Value) -> Item {
+  match value {
+    Value::Null => Item::new_atom(b"null"),
+    Value::Bool(bool) => Item::new_atom(bool.to_string().as_bytes()),
+    Value::Number(number) => Item::new_atom(number.to_string().as_bytes()),
+    Value::String(string) => Item::new_atom(string.as_bytes()),
+    Value::Array(array) => Item::new_list(array.iter().map(from_json)),
+    Value::Object(object) => Item::new_map(
+      object
+        .iter()
+        .map(|(key, value)| (Item::new_atom(key.as_bytes()), from_json(value))),
+    ),
+  }
+}
+
+/// Convert an item to JSON.
+///
+/// Maps become objects and lists become arrays, atoms depend on
+/// [`JsonOptions::infer`]. Atoms which are not UTF-8 are converted lossily
+/// and tags are dropped.
+///
+/// JSON keys are strings: atom keys are their text, list and map keys are
+/// written in the [`canonical()`](crate::canonical()) form with parentheses,
+/// `(a b): 1` becomes `{"(a b)": 1}`. If keys
+/// end up the same the last entry wins.
+///
+/// ```
+/// # use axp::{parse, to_json, Infer, JsonOptions};
+/// let item = parse(b"port: 80 debug: true (a b): x").unwrap();
+/// let json = to_json(&item, &JsonOptions::default());
+/// assert_eq!(json.to_string(), r#"{"port":80,"debug":true,"(a b)":"x"}"#);
+/// let options = JsonOptions { infer: Infer::Strings };
+/// let json = to_json(&item, &options);
+/// let expected = r#"{"port":"80","debug":"true","(a b)":"x"}"#;
+/// assert_eq!(json.to_string(), expected);
+/// ```
+pub fn to_json(item: &Item, options: &JsonOptions) -> Value {
+  match item {
+    Item::Atom(atom) => {
+      let text = String::from_utf8_lossy(atom.as_bytes());
+      match options.infer {
+        Infer::Scalars if atom.tag().is_none() => scalar(&text),
+        _ => None,
+      }
+      .unwrap_or_else(|| Value::String(text.into_owned()))
+    }
+    Item::List(list) => {
+      Value::Array(list.iter().map(|item| to_json(item, options)).collect())
+    }
+    Item::Map(map) => Value::Object(
+      map
+        .iter()
+        .map(|(key, value)| (key_text(key), to_json(value, options)))
+        .collect(),
+    ),
+  }
+}
+
+fn scalar(text: &str) -> Option<Value> {
+  match text {
+    "null" => Some(Value::Null),
+    "true" => Some(Value::Bool(true)),
+    "false" => Some(Value::Bool(false)),
+    _ => {
+      // only if lossless, `1e3` would be written as `1000.0`
+      let number = serde_json::from_str::<Number>(text).ok()?;
+      (number.to_string() == text).then_some(Value::Number(number))
+    }
+  }
+}
+
+fn key_text(key: &Item) -> String {
+  match key {
+    Item::Atom(atom) => String::from_utf8_lossy(atom.as_bytes()).into_owned(),
+    _ => {
+      let mut text = Vec::new();
+      write_item(key, &mut text);
+      String::from_utf8_lossy(&text).into_owned()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::{from_json, to_json, Infer, JsonOptions};
+  use crate::{parse, Atom, Item};
+
+  fn json(input: &str) -> String {
+    let item = parse(input.as_bytes()).unwrap();
+    to_json(&item, &JsonOptions::default()).to_string()
+  }
+
+  #[test]
+  fn scalars() {
+    assert_eq!(
+      json("a: 1 b: -2.5 c: 1e3 d: 01 e: 18446744073709551615"),
+      r#"{"a":1,"b":-2.5,"c":"1e3","d":"01","e":18446744073709551615}"#
+    );
+    assert_eq!(
+      json(r#"true false null True "" x"#),
+      r#"[true,false,null,"True","","x"]"#
+    );
+    let tagged = Item::Atom(Atom::tagged(b"n", b"1").unwrap());
+    assert_eq!(to_json(&tagged, &JsonOptions::default()), json!("1"));
+    let options = JsonOptions { infer: Infer::Strings };
+    assert_eq!(to_json(&Item::new_atom(b"1"), &options), json!("1"));
+    assert_eq!(to_json(&Item::new_atom(b"\xff"), &options), json!("\u{fffd}"));
+  }
+
+  #[test]
+  fn structure() {
+    assert_eq!(json(""), "[]");
+    assert_eq!(json("a: (b: (1 (c: ())))"), r#"{"a":{"b":[1,{"c":[]}]}}"#);
+    assert_eq!(
+      json(r#"(a "b c"): 1 (k: v): 2 "x y": 3 "(a \"b c\")": 4"#),
+      r#"{"(a \"b c\")":4,"(k: v)":2,"x y":3}"#
+    );
+  }
+
+  #[test]
+  fn roundtrip() {
+    let value = json!({
+      "z": {"b": [1, 2.5, "x y", true, null, []], "a": {}},
+      "": "",
+      "(": ")"
+    });
+    let item = from_json(&value);
+    assert_eq!(
+      crate::canonical(&item),
+      br#"z: (b: (1 2.5 "x y" true null ()) a: ()) "": "" "(": ")""#
+    );
+    assert_eq!(to_json(&item, &JsonOptions::default()), value);
+  }
+
+  #[test]
+  fn roundtrip_text() {
+    let value = json!({"server": {"port": 8080, "hosts": ["a", "b c"]}});
+    let text = crate::canonical(&from_json(&value));
+    assert_eq!(text, br#"server: (port: 8080 hosts: (a "b c"))"#);
+    let item = parse(&text).unwrap();
+    assert_eq!(to_json(&item, &JsonOptions::default()), value);
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
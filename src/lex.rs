@@ -79,9 +79,12 @@ impl<'b> fmt::Debug for Comment<'b> {
 }
 
 #[derive(Logos, Clone, Copy, Eq, PartialEq)]
-#[logos(subpattern guard=br#"(#[^ \n\r\t\\"]{0,8})?"#)]
+// the guard of the end is as long as the longest opening guard `#(12345678)`
+#[logos(subpattern guard=br#"(#[^ \n\r\t\\"]{0,10})?"#)]
 pub enum Quoted<'b> {
-  #[regex(br#"[^\\"]{1,20}"#, slice)]
+  // a `#` is a part of its own, it may start the guard of the end
+  #[regex(br##"[^\\"#]{1,20}"##, slice)]
+  #[token(b"#", slice)]
   Part(&'b [u8]),
 
   #[regex(br#"(?&guard)\\["enrt0]"#, slice)]
@@ -127,6 +130,7 @@ enum Lex<'b> {
 pub struct AxpLexer<'b> {
   lex: Lex<'b>,
   guard: &'b [u8],
+  pending: Option<Token<'b>>,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -138,6 +142,8 @@ pub enum Token<'b> {
   Open,
   Close,
   Bad(&'b [u8]),
+  /// Opening or closing quote of a string, including its guard
+  Quote(&'b [u8]),
   Quoted(&'b [u8]),
   Esc(&'b [u8]),
 }
@@ -155,6 +161,7 @@ impl<'b> fmt::Debug for Token<'b> {
       Token::Open => f.write_str("Open"),
       Token::Close => f.write_str("Close"),
       Token::Bad(s) => write!(f, "Bad({})", s.pretty_short(width)),
+      Token::Quote(s) => write!(f, "Quote({})", s.pretty_short(width)),
       Token::Quoted(s) => write!(f, "Quoted({})", s.pretty_short(width)),
       Token::Esc(s) => write!(f, "Esc({})", s.pretty_short(width)),
     }
@@ -172,6 +179,7 @@ impl<'b> fmt::Display for Token<'b> {
       Token::Open => f.write_str("("),
       Token::Close => f.write_str(")"),
       Token::Bad(s) => write!(f, "bad token `{}`", s.pretty_short(width)),
+      Token::Quote(s) => write!(f, "quote `{}`", s.pretty_short(width)),
       Token::Quoted(s) => write!(f, "quoted part `{}`", s.pretty_short(width)),
       Token::Esc(s) => write!(f, "esc `{}`", s.pretty_short(width)),
     }
@@ -187,88 +195,110 @@ impl<'b> Iterator for AxpLexer<'b> {
       &bytes[..n]
     }
 
-    loop {
-      match &mut self.lex {
-        Lex::Base(lex_base) => {
-          let token = lex_base.next();
-          log::trace!("base: {token:.15?}");
-
-          if let Some(Ok(base)) = token {
-            match base {
-              Base::WhiteSpace(s) => return Some(Token::WhiteSpace(s)),
-              Base::Comment(s) => {
-                self.lex = Lex::Comment(lex_base.to_owned().morph());
-                return Some(Token::Comment(s));
-              }
-              Base::Bare(bare) => return Some(Token::Bare(bare)),
-              Base::Colon => return Some(Token::Colon),
-              Base::Open => return Some(Token::Open),
-              Base::Close => return Some(Token::Close),
-              Base::Bad(s) => return Some(Token::Bad(s)),
-              Base::Quoted(guard) => {
-                self.guard = slice_without_last(guard);
-                self.lex = Lex::Quoted(lex_base.to_owned().morph());
-                continue;
-              }
+    if let Some(token) = self.pending.take() {
+      return Some(token);
+    }
+
+    match &mut self.lex {
+      Lex::Base(lex_base) => {
+        let token = lex_base.next();
+        log::trace!("base: {token:.15?}");
+
+        if let Some(Ok(base)) = token {
+          match base {
+            Base::WhiteSpace(s) => return Some(Token::WhiteSpace(s)),
+            Base::Comment(s) => {
+              self.lex = Lex::Comment(lex_base.to_owned().morph());
+              return Some(Token::Comment(s));
+            }
+            Base::Bare(bare) => return Some(Token::Bare(bare)),
+            Base::Colon => return Some(Token::Colon),
+            Base::Open => return Some(Token::Open),
+            Base::Close => return Some(Token::Close),
+            Base::Bad(s) => return Some(Token::Bad(s)),
+            Base::Quoted(guard) => {
+              self.guard = slice_without_last(guard);
+              self.lex = Lex::Quoted(lex_base.to_owned().morph());
+              return Some(Token::Quote(guard));
             }
-          } else if token.is_none() {
-            return None;
           }
-
-          unreachable!("unexpected result from lex_base.next(): {token:?}");
+        } else if token.is_none() {
+          return None;
         }
 
-        Lex::Comment(lex_comment) => {
-          let token = lex_comment.next();
-          log::trace!("comment: {token:.15?}");
+        // input which no token matches, like `#(` at the end
+        Some(Token::Bad(lex_base.slice()))
+      }
 
-          if let Some(Ok(comment)) = token {
-            match comment {
-              Comment::Part(s) => return Some(Token::Comment(s)),
-              Comment::End(s) => {
-                self.lex = Lex::Base(lex_comment.to_owned().morph());
-                return Some(Token::WhiteSpace(s));
-              }
+      Lex::Comment(lex_comment) => {
+        let token = lex_comment.next();
+        log::trace!("comment: {token:.15?}");
+
+        if let Some(Ok(comment)) = token {
+          match comment {
+            Comment::Part(s) => return Some(Token::Comment(s)),
+            Comment::End(s) => {
+              self.lex = Lex::Base(lex_comment.to_owned().morph());
+              return Some(Token::WhiteSpace(s));
             }
-          } else if token.is_none() {
-            return None;
           }
-
-          unreachable!("unexpected result from lex_comment.next(): {token:?}");
+        } else if token.is_none() {
+          return None;
         }
 
-        Lex::Quoted(lex_quoted) => {
-          let token = lex_quoted.next();
-          log::trace!("quoted: {token:.15?}");
-
-          if let Some(Ok(quoted)) = token {
-            match quoted {
-              Quoted::Part(s) => return Some(Token::Quoted(s)),
-              Quoted::End(guard) => {
-                if self.guard == slice_without_last(guard) {
-                  self.lex = Lex::Base(lex_quoted.to_owned().morph());
-                  continue;
-                } else {
-                  return Some(Token::Quoted(guard));
-                }
-              } // todo handle guard
-              Quoted::Esc(s) => return Some(Token::Esc(s)),
-              Quoted::Bad(s) => return Some(Token::Bad(s)),
+        // input which no token matches
+        Some(Token::Bad(lex_comment.slice()))
+      }
+
+      Lex::Quoted(lex_quoted) => {
+        let token = lex_quoted.next();
+        log::trace!("quoted: {token:.15?}");
+
+        if let Some(Ok(quoted)) = token {
+          match quoted {
+            Quoted::Part(s) => return Some(Token::Quoted(s)),
+            Quoted::End(guard) => {
+              // text before the guard, like the `#` in `"a#"`, is returned
+              // first and the quote is pending
+              let end_guard = slice_without_last(guard);
+              let Some(text) = end_guard.strip_suffix(self.guard) else {
+                return Some(Token::Quoted(guard));
+              };
+              self.lex = Lex::Base(lex_quoted.to_owned().morph());
+              if text.is_empty() {
+                return Some(Token::Quote(guard));
+              }
+              self.pending = Some(Token::Quote(&guard[text.len()..]));
+              return Some(Token::Quoted(text));
             }
-          } else if token.is_none() {
-            self.lex = Lex::Base(lex_quoted.to_owned().morph());
-            return Some(Token::Bad(b"\"")); // unexpected end of string
+            Quoted::Esc(s) => return Some(Token::Esc(s)),
+            Quoted::Bad(s) => return Some(Token::Bad(s)),
           }
-
-          unreachable!("unexpected result from lex_quoted.next(): {token:?}");
+        } else if token.is_none() {
+          self.lex = Lex::Base(lex_quoted.to_owned().morph());
+          return Some(Token::Bad(b"\"")); // unexpected end of string
         }
+
+        // input which no token matches, like the escape `\xZZ`
+        Some(Token::Bad(lex_quoted.slice()))
       }
     }
   }
 }
 
+impl<'b> AxpLexer<'b> {
+  /// The byte range of the last token
+  pub fn span(&self) -> std::ops::Range<usize> {
+    match &self.lex {
+      Lex::Base(lex) => lex.span(),
+      Lex::Comment(lex) => lex.span(),
+      Lex::Quoted(lex) => lex.span(),
+    }
+  }
+}
+
 pub fn lex(input: &[u8]) -> AxpLexer<'_> {
-  AxpLexer { lex: Lex::Base(Base::lexer(input)), guard: b"" }
+  AxpLexer { lex: Lex::Base(Base::lexer(input)), guard: b"", pending: None }
 }
 
 #[cfg(test)]
@@ -308,6 +338,7 @@ mod tests {
         Bad(b"\\"),
         Bare(b"j"),
         Bad(b"#k"),
+        Quote(b"\""),
         Bad(b"\""),
       ]
     );
@@ -343,9 +374,11 @@ mod tests {
       &[
         Bare(b"a-bare"),
         WhiteSpace(b" "),
+        Quote(b"\""),
         Quoted(b"text"),
         Esc(b"\\n"),
         Quoted(b"line"),
+        Quote(b"\""),
       ]
     );
   }
@@ -353,7 +386,52 @@ mod tests {
   #[test]
   fn lex_escapes() {
     // todo
-    assert_eq!(lex_str(r#""\"\e""#), &[Esc(b"\\\""), Esc(b"\\e")]);
+    assert_eq!(
+      lex_str(r#""\"\e""#),
+      &[Quote(b"\""), Esc(b"\\\""), Esc(b"\\e"), Quote(b"\"")]
+    );
+  }
+
+  #[test]
+  fn lex_quotes() {
+    let q = Quote(b"\"");
+    assert_eq!(lex_str(r#""""#), &[q, q]);
+    assert_eq!(
+      lex_str(r#""a" "b""#),
+      &[q, Quoted(b"a"), q, WhiteSpace(b" "), q, Quoted(b"b"), q]
+    );
+
+    // in unguarded strings a `#` before the closing quote is text
+    assert_eq!(
+      lex_str(r#""\n#" x"#),
+      &[q, Esc(b"\\n"), Quoted(b"#"), q, WhiteSpace(b" "), Bare(b"x")]
+    );
+  }
+
+  #[test]
+  fn lex_guards() {
+    let d = Quote(b"#(date)\"");
+    assert_eq!(
+      lex_str(r##"#(date)"a"b#(date)" "##),
+      &[d, Quoted(b"a"), Quoted(b"\""), Quoted(b"b"), d, WhiteSpace(b" ")]
+    );
+    assert_eq!(
+      lex_str(r##"#(date)"\n#(date)\n##(date)""##),
+      &[d, Esc(b"\\n"), Esc(b"#(date)\\n"), Quoted(b"#"), d]
+    );
+
+    // the longest guard closes as well
+    let g = Quote(b"#(12345678)\"");
+    assert_eq!(lex_str(r##"#(12345678)"#(12345678)""##), &[g, g]);
+  }
+
+  #[test]
+  fn lex_unmatched() {
+    // input no token matches is bad, not a panic
+    assert_eq!(lex_str("#("), &[Bad(b"#"), Open]);
+    let q = Quote(b"\"");
+    assert_eq!(lex_str(r#""\xZZ""#), &[q, Bad(b"\\"), Quoted(b"xZZ"), q]);
+    assert_eq!(lex_str(r#""\u{zz}""#), &[q, Bad(b"\\"), Quoted(b"u{zz}"), q]);
   }
 
   #[test]
@@ -370,8 +448,6 @@ mod tests {
 
     // test break up of comments
   }
-
-  // todo string guards
 }
 
 // Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -1,21 +1,100 @@
 #![forbid(unsafe_code)]
 
+mod anchor;
 mod atom;
+mod binary;
+mod canonical;
+#[cfg(feature = "cbor")]
+mod cbor;
+mod cmp;
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+mod codec;
+mod config;
+mod convert;
+mod diff;
+mod digest;
+mod edn;
 mod evaluate;
+mod include;
+mod interpolate;
 mod item;
+#[cfg(feature = "json")]
+mod json;
 mod lex;
 mod list;
 mod map;
+mod merge;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+mod number;
 mod parse;
+mod patch;
+mod path;
 mod pretty;
+mod profile;
+mod sexp;
+mod span;
+mod table;
+#[cfg(feature = "toml")]
+mod toml;
+#[cfg(feature = "watch")]
+mod watch;
+#[cfg(feature = "xml")]
+mod xml;
+#[cfg(feature = "yaml")]
+mod yaml;
 
+pub use anchor::{resolve_anchors, AnchorError};
 pub use atom::Atom;
-pub use evaluate::evaluate;
+pub use binary::{
+  from_binary, read_binary, to_binary, write_binary, BinaryError, BinaryLimits,
+  BinaryOptions,
+};
+pub use canonical::{canonical, canonical_with, CanonicalOptions};
+#[cfg(feature = "cbor")]
+pub use cbor::{from_cbor, to_cbor, CborError, CborOptions};
+pub use cmp::{Ordered, Unordered};
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+pub use codec::{CodecError, CodecOptions};
+pub use config::{Config, ConfigError, ConfigLoader, Location, Source};
+pub use convert::{FromItem, FromItemError};
+pub use diff::{diff, Change, Diff};
+pub use digest::{ContentHasher, Fnv1a};
+pub use edn::{from_edn, to_edn, EdnError};
+pub use evaluate::{
+  evaluate, evaluate_in, evaluate_spanned, Arity, Ctx, Env, EvalError,
+  EvalErrorKind, EvalLimits, Interpreter, Primitive,
+};
+pub use include::{
+  FsLoader, IncludeError, Loader, MemoryLoader, Resolved, Resolver,
+};
+pub use interpolate::{interpolate, InterpolateError, InterpolateOptions};
 pub use item::Item;
+#[cfg(feature = "json")]
+pub use json::{from_json, to_json, Infer, JsonOptions};
 pub use lex::{lex, AxpLexer, Token};
 pub use list::List;
 pub use map::Map;
-pub use parse::parse;
+pub use merge::{Layers, MergeOptions, Strategy};
+#[cfg(feature = "msgpack")]
+pub use msgpack::{from_msgpack, to_msgpack, MsgpackError, MsgpackOptions};
+pub use parse::{parse, parse_spanned, ParseError};
+pub use patch::{apply_patch, Op, Patch, PatchError};
+pub use path::Path;
 pub use pretty::{pretty, PrettyUtf8};
+pub use profile::{apply_profiles, ProfileError};
+pub use sexp::{from_sexp, to_sexp, SexpError};
+pub use span::{Span, Spans};
+pub use table::{from_csv, table, to_csv, TableError, TableOptions};
+#[cfg(feature = "toml")]
+pub use toml::{from_toml, to_toml, TomlError};
+#[cfg(feature = "watch")]
+pub use watch::{ConfigWatcher, Event, Watch};
+#[cfg(feature = "xml")]
+pub use xml::{
+  from_xml, read_xml, to_xml, write_xml, XmlError, XmlOptions, XmlStyle,
+};
+#[cfg(feature = "yaml")]
+pub use yaml::{from_yaml, to_yaml, YamlError};
 
 // Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -1,7 +1,8 @@
 use crate::Item;
 use std::fmt;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// A list is a sequence of items, ordered lexicographically.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct List(pub(crate) Vec<Item>);
 
 impl fmt::Display for List {
@@ -59,6 +60,14 @@ impl List {
     self.0.is_empty()
   }
 
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn get(&self, index: usize) -> Option<&Item> {
+    self.0.get(index)
+  }
+
   pub fn iter(&self) -> impl Iterator<Item = &Item> {
     self.0.iter()
   }
@@ -0,0 +1,112 @@
+use std::io::{IsTerminal, Write};
+use std::process::ExitCode;
+
+use axp::{parse, Item};
+
+const USAGE: &str = "\
+usage: axp <command> [args]
+
+commands:
+  diff [--axp] <old.axp> <new.axp>  show the structural difference
+  table <file.axp> [path]           show a list of maps as a table
+";
+
+#[cfg(feature = "json")]
+const JSON_USAGE: &str = "\
+  to-json [--strings] <file.axp>    convert to JSON, atoms are inferred as
+                                    numbers, booleans and null unless strings
+  from-json <file.json>             convert JSON to an axp document
+";
+
+// Exit codes like diff(1): 0 same, 1 different, 2 trouble
+fn main() -> ExitCode {
+  env_logger::init();
+
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+  let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+  let result = match args.as_slice() {
+    ["diff", "--axp", old, new] => diff(old, new, true),
+    ["diff", old, new] => diff(old, new, false),
+    ["table", path] => table(path, None),
+    ["table", path, at] => table(path, Some(at)),
+    #[cfg(feature = "json")]
+    ["to-json", "--strings", path] => to_json(path, axp::Infer::Strings),
+    #[cfg(feature = "json")]
+    ["to-json", path] => to_json(path, axp::Infer::Scalars),
+    #[cfg(feature = "json")]
+    ["from-json", path] => from_json(path),
+    _ => {
+      eprint!("{USAGE}");
+      #[cfg(feature = "json")]
+      eprint!("{JSON_USAGE}");
+      return ExitCode::from(2);
+    }
+  };
+
+  result.unwrap_or_else(|err| {
+    eprintln!("axp: {err}");
+    ExitCode::from(2)
+  })
+}
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn read(path: &str) -> Result<Item> {
+  let bytes = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+  Ok(parse(&bytes).map_err(|err| format!("{path}: {err}"))?)
+}
+
+fn diff(old: &str, new: &str, as_axp: bool) -> Result<ExitCode> {
+  let diff = axp::diff(&read(old)?, &read(new)?);
+
+  let mut stdout = std::io::stdout();
+  if as_axp {
+    stdout.write_all(&axp::canonical(&diff.to_item()))?;
+    stdout.write_all(b"\n")?;
+  } else {
+    stdout.write_all(diff.report(stdout.is_terminal()).as_bytes())?;
+  }
+
+  Ok(ExitCode::from(if diff.is_empty() { 0 } else { 1 }))
+}
+
+fn table(path: &str, at: Option<&str>) -> Result<ExitCode> {
+  let item = read(path)?;
+  let item = match at {
+    Some(at) => {
+      let at = at.parse::<axp::Path>().map_err(|err| format!("{at}: {err}"))?;
+      item.get(&at).cloned().ok_or_else(|| format!("{path}: no {at}"))?
+    }
+    None => item,
+  };
+
+  std::io::stdout().write_all(axp::table(&item)?.as_bytes())?;
+  Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(feature = "json")]
+fn to_json(path: &str, infer: axp::Infer) -> Result<ExitCode> {
+  let options = axp::JsonOptions { infer };
+  let json = axp::to_json(&read(path)?, &options);
+
+  let mut stdout = std::io::stdout();
+  serde_json::to_writer_pretty(&mut stdout, &json)?;
+  stdout.write_all(b"\n")?;
+  Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(feature = "json")]
+fn from_json(path: &str) -> Result<ExitCode> {
+  let bytes = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+  let json =
+    serde_json::from_slice(&bytes).map_err(|err| format!("{path}: {err}"))?;
+
+  // an object is written as the document, see axp::canonical() for scalars
+  let mut stdout = std::io::stdout();
+  stdout.write_all(&axp::canonical(&axp::from_json(&json)))?;
+  stdout.write_all(b"\n")?;
+  Ok(ExitCode::SUCCESS)
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -2,7 +2,12 @@ use std::fmt;
 
 use crate::Item;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// A map is a sequence of entries, ordered lexicographically by entry, each
+/// entry by key first and then by value.
+///
+/// Equality respects entry order. For order-insensitive equality see
+/// [`Unordered`](crate::Unordered).
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Map(pub(crate) Vec<(Item, Item)>);
 
 impl fmt::Display for Map {
@@ -31,6 +36,39 @@ impl Map {
   pub fn is_empty(&self) -> bool {
     self.0.is_empty()
   }
+
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &(Item, Item)> {
+    self.0.iter()
+  }
+
+  /// Get the value of the first entry with the key
+  pub fn get(&self, key: &Item) -> Option<&Item> {
+    self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+  }
+
+  /// Get the value of the first entry with the key mutably
+  pub fn get_mut(&mut self, key: &Item) -> Option<&mut Item> {
+    self.0.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v)
+  }
+
+  /// Set the value of the first entry with the key or push a new entry
+  pub fn set(&mut self, key: Item, value: Item) -> &mut Self {
+    match self.get_mut(&key) {
+      Some(old) => *old = value,
+      None => self.0.push((key, value)),
+    }
+    self
+  }
+
+  /// Remove the first entry with the key and return its value
+  pub fn remove(&mut self, key: &Item) -> Option<Item> {
+    let pos = self.0.iter().position(|(k, _)| k == key)?;
+    Some(self.0.remove(pos).1)
+  }
 }
 
 fn format_entry(key: &Item, value: &Item, width: usize) -> String {
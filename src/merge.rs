@@ -0,0 +1,477 @@
+use crate::{Item, Path, Resolved, Span, Spans};
+
+/// How to merge an overlay item into a base item
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Strategy {
+  /// The overlay replaces the base
+  Replace,
+  /// Merge maps entry by entry, recursively
+  Deep,
+  /// Append the overlay list to the base list
+  Append,
+  /// Prepend the overlay list to the base list
+  Prepend,
+  /// Merge lists of maps by the value of a key field: maps with the same
+  /// value are merged deeply, other items are appended unless already there
+  UnionBy(Item),
+}
+
+/// Options for merging, see [`Item::merge()`] and [`Layers`].
+///
+/// Strategies given for paths take precedence over the global strategies for
+/// maps and lists. The path step `*` matches any key or index. If a strategy
+/// does not fit, for example `Append` for maps, the overlay replaces the base.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MergeOptions {
+  pub maps: Strategy,
+  pub lists: Strategy,
+  pub paths: Vec<(Path, Strategy)>,
+}
+
+impl Default for MergeOptions {
+  fn default() -> Self {
+    MergeOptions {
+      maps: Strategy::Deep,
+      lists: Strategy::Replace,
+      paths: Vec::new(),
+    }
+  }
+}
+
+impl MergeOptions {
+  fn strategy(&self, path: &Path, item: &Item) -> &Strategy {
+    let wildcard = Item::new_atom(b"*");
+    let matches = |pattern: &Path| {
+      let steps = pattern.steps().iter().zip(path.steps());
+      pattern.steps().len() == path.steps().len()
+        && steps.into_iter().all(|(p, s)| *p == wildcard || p == s)
+    };
+
+    match self.paths.iter().find(|(pattern, _)| matches(pattern)) {
+      Some((_, strategy)) => strategy,
+      None if item.is_map() => &self.maps,
+      None => &self.lists,
+    }
+  }
+}
+
+// The value of the key field of a map for `Strategy::UnionBy`
+fn id<'i>(item: &'i Item, key: &Item) -> Option<&'i Item> {
+  match item {
+    Item::Map(map) => map.get(key),
+    _ => None,
+  }
+}
+
+// The span of the item at a path of a layer with the index of its document
+type Located<'s> = &'s dyn Fn(&Path) -> Option<(usize, Span)>;
+
+// The layers and spans where an item and its children come from, mirrors
+// the item
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct Origin {
+  layer: usize,
+  // The index of the document of the layer and the span in it
+  span: Option<(usize, Span)>,
+  children: Vec<Origin>,
+}
+
+/// Layered documents merged in order, with provenance.
+///
+/// Applications typically push defaults, then site config, then user config.
+/// Each layer is merged into the result of the previous ones and for each
+/// value of the result the layer which supplied it is recorded.
+///
+/// ```
+/// # use axp::{parse, Layers, MergeOptions, Path};
+/// let mut layers = Layers::new(MergeOptions::default());
+/// layers.push(&parse(b"server: (host: localhost port: 80)").unwrap());
+/// layers.push(&parse(b"server: (port: 8080)").unwrap());
+/// assert_eq!(layers.item(), &parse(b"server: (host: localhost port: 8080)").unwrap());
+///
+/// let layer = |path: &str| layers.layer(&path.parse::<Path>().unwrap());
+/// assert_eq!(layer("server/host"), Some(0));
+/// assert_eq!(layer("server/port"), Some(1));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Layers {
+  options: MergeOptions,
+  item: Item,
+  origin: Origin,
+  count: usize,
+}
+
+impl Layers {
+  pub fn new(options: MergeOptions) -> Self {
+    Layers { options, item: Item::nil(), origin: Origin::default(), count: 0 }
+  }
+
+  /// Merge a layer and return its index
+  pub fn push(&mut self, layer: &Item) -> usize {
+    self.push_layer(layer, &|_| None)
+  }
+
+  /// Merge a layer with the spans from [`parse_spanned()`], see
+  /// [`Layers::span()`]
+  ///
+  /// [`parse_spanned()`]: crate::parse_spanned
+  pub fn push_spanned(&mut self, layer: &Item, spans: &Spans) -> usize {
+    self.push_layer(layer, &|at| spans.get(at).map(|span| (0, *span)))
+  }
+
+  // Merge a document with expanded includes, see `Layers::located()`
+  pub(crate) fn push_resolved(&mut self, resolved: &Resolved) -> usize {
+    self.push_layer(resolved.item(), &|at| resolved.located(at))
+  }
+
+  fn push_layer(&mut self, layer: &Item, spans: Located<'_>) -> usize {
+    let index = self.count;
+    let merge = Merge { options: &self.options, layer: index, spans };
+    if index == 0 {
+      self.item = layer.clone();
+      self.origin = merge.origin(&Path::root(), layer);
+    } else {
+      let root = Path::root();
+      merge.item(&root, &mut self.item, &mut self.origin, &root, layer);
+    }
+    self.count += 1;
+    index
+  }
+
+  /// The number of layers pushed
+  pub fn len(&self) -> usize {
+    self.count
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.count == 0
+  }
+
+  /// The merged item
+  pub fn item(&self) -> &Item {
+    &self.item
+  }
+
+  pub fn into_item(self) -> Item {
+    self.item
+  }
+
+  /// The index of the layer which supplied the item at the path. For lists
+  /// and maps this is the last layer which changed them.
+  pub fn layer(&self, path: &Path) -> Option<usize> {
+    self.origin(path).map(|origin| origin.layer)
+  }
+
+  /// The span of the item at the path in its layer, if the layer was pushed
+  /// with spans
+  pub fn span(&self, path: &Path) -> Option<Span> {
+    self.located(path).map(|(_, span)| span)
+  }
+
+  // The span of the item at the path and the index of its document in the
+  // names of the `Resolved` layer, 0 for other layers
+  pub(crate) fn located(&self, path: &Path) -> Option<(usize, Span)> {
+    self.origin(path).and_then(|origin| origin.span)
+  }
+
+  fn origin(&self, path: &Path) -> Option<&Origin> {
+    let (mut item, mut origin) = (&self.item, &self.origin);
+    for step in path.steps() {
+      let i = match item {
+        Item::Atom(_) => None,
+        Item::List(list) => {
+          crate::path::index(step).filter(|&i| i < list.len())
+        }
+        Item::Map(map) => map.iter().position(|(k, _)| k == step),
+      }?;
+      item = match item {
+        Item::List(list) => &list.0[i],
+        Item::Map(map) => &map.0[i].1,
+        Item::Atom(_) => unreachable!("atoms have no children"),
+      };
+      origin = &origin.children[i];
+    }
+    Some(origin)
+  }
+
+  /// The paths of all atoms and empty lists or maps with their layers
+  pub fn provenance(&self) -> Vec<(Path, usize)> {
+    fn walk(p: Path, item: &Item, o: &Origin, out: &mut Vec<(Path, usize)>) {
+      match item {
+        _ if item.is_atom() || item.is_empty() => out.push((p, o.layer)),
+        Item::List(list) => {
+          for (i, (item, o)) in list.iter().zip(&o.children).enumerate() {
+            walk(p.join_index(i), item, o, out)
+          }
+        }
+        Item::Map(map) => {
+          for ((k, v), o) in map.iter().zip(&o.children) {
+            walk(p.join(k.clone()), v, o, out)
+          }
+        }
+        Item::Atom(_) => unreachable!("handled above"),
+      }
+    }
+
+    let mut provenance = Vec::new();
+    walk(Path::root(), &self.item, &self.origin, &mut provenance);
+    provenance
+  }
+}
+
+// Merges one layer, paths are in the merged item and in the overlay (`at`)
+struct Merge<'o> {
+  options: &'o MergeOptions,
+  layer: usize,
+  spans: Located<'o>,
+}
+
+impl<'o> Merge<'o> {
+  fn origin(&self, at: &Path, item: &Item) -> Origin {
+    let children = match item {
+      Item::Atom(_) => Vec::new(),
+      Item::List(list) => {
+        let origin = |(i, item)| self.origin(&at.join_index(i), item);
+        list.iter().enumerate().map(origin).collect()
+      }
+      Item::Map(map) => {
+        map.iter().map(|(k, v)| self.origin(&at.join(k.clone()), v)).collect()
+      }
+    };
+    let span = (self.spans)(at);
+    Origin { layer: self.layer, span, children }
+  }
+
+  fn item(
+    &self,
+    path: &Path,
+    base: &mut Item,
+    origin: &mut Origin,
+    at: &Path,
+    overlay: &Item,
+  ) {
+    let strategy = self.options.strategy(path, overlay);
+    match (base, overlay, strategy) {
+      (Item::Map(base), Item::Map(overlay), Strategy::Deep) => {
+        for (key, value) in overlay.iter() {
+          let found = base.iter().position(|(k, _)| k == key);
+          let (path, at) = (path.join(key.clone()), at.join(key.clone()));
+          match found {
+            Some(i) => {
+              let (base, origin) = (&mut base.0[i].1, &mut origin.children[i]);
+              self.item(&path, base, origin, &at, value)
+            }
+            None => {
+              base.push(key.clone(), value.clone());
+              origin.children.push(self.origin(&at, value));
+            }
+          }
+        }
+      }
+      (Item::List(base), Item::List(overlay), Strategy::Append) => {
+        base.0.extend(overlay.iter().cloned());
+        let children = overlay.iter().enumerate();
+        let children = children.map(|(i, v)| self.origin(&at.join_index(i), v));
+        origin.children.extend(children);
+      }
+      (Item::List(base), Item::List(overlay), Strategy::Prepend) => {
+        base.0.splice(0..0, overlay.iter().cloned());
+        let children = overlay.iter().enumerate();
+        let children = children.map(|(i, v)| self.origin(&at.join_index(i), v));
+        origin.children.splice(0..0, children);
+      }
+      (Item::List(base), Item::List(overlay), Strategy::UnionBy(key)) => {
+        for (j, value) in overlay.iter().enumerate() {
+          let at = at.join_index(j);
+          let found = match id(value, key) {
+            Some(id_value) => {
+              base.iter().position(|i| id(i, key) == Some(id_value))
+            }
+            None => base.iter().position(|i| i == value),
+          };
+          match found {
+            Some(i) => {
+              let path = path.join_index(i);
+              let (base, origin) = (&mut base.0[i], &mut origin.children[i]);
+              self.item(&path, base, origin, &at, value)
+            }
+            None => {
+              base.push(value.clone());
+              origin.children.push(self.origin(&at, value));
+            }
+          }
+        }
+      }
+      (base, overlay, _) => {
+        *base = overlay.clone();
+        *origin = self.origin(at, overlay);
+        return;
+      }
+    }
+    origin.layer = self.layer;
+    origin.span = (self.spans)(at);
+  }
+}
+
+/// Merge the maps of a directive like `@include`, `@extends` or a profile
+/// section in order with the default options. Empty layers are skipped, as an
+/// overlay `()` would replace the merged map. The error is the index of the
+/// first layer which is neither a map nor empty.
+pub(crate) fn merge_maps(layers: &[Item]) -> Result<Item, usize> {
+  let mut merged = Layers::new(MergeOptions::default());
+  for (index, layer) in layers.iter().enumerate() {
+    match layer {
+      _ if layer.is_empty() => (),
+      Item::Map(_) => _ = merged.push(layer),
+      _ => return Err(index),
+    }
+  }
+  Ok(merged.into_item())
+}
+
+impl Item {
+  /// Merge an overlay into a base item.
+  ///
+  /// ```
+  /// # use axp::{parse, Item, MergeOptions, Strategy};
+  /// let base = parse(b"a: (x: 1 y: 2) l: (1 2)").unwrap();
+  /// let overlay = parse(b"a: (y: 3) l: (3)").unwrap();
+  ///
+  /// let merged = Item::merge(&base, &overlay, &MergeOptions::default());
+  /// assert_eq!(merged, parse(b"a: (x: 1 y: 3) l: (3)").unwrap());
+  ///
+  /// let options = MergeOptions { lists: Strategy::Append, ..Default::default() };
+  /// let merged = Item::merge(&base, &overlay, &options);
+  /// assert_eq!(merged, parse(b"a: (x: 1 y: 3) l: (1 2 3)").unwrap());
+  /// ```
+  pub fn merge(base: &Item, overlay: &Item, options: &MergeOptions) -> Item {
+    let mut layers = Layers::new(options.clone());
+    layers.push(base);
+    layers.push(overlay);
+    layers.into_item()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Layers, MergeOptions, Strategy};
+  use crate::{canonical, parse, Item, Path};
+
+  fn doc(s: &str) -> Item {
+    parse(s.as_bytes()).unwrap()
+  }
+
+  fn merge(base: &str, overlay: &str, options: &MergeOptions) -> String {
+    let merged = Item::merge(&doc(base), &doc(overlay), options);
+    String::from_utf8(canonical(&merged)).unwrap()
+  }
+
+  #[test]
+  fn global_strategies() {
+    let deep = MergeOptions::default();
+    assert_eq!(
+      merge("a: (b: (c: 1 d: 2))", "a: (b: (d: 3))", &deep),
+      "a: (b: (c: 1 d: 3))"
+    );
+    assert_eq!(merge("a: 1", "a: (x y)", &deep), "a: (x y)");
+    assert_eq!(merge("a: (x: 1)", "a: 2", &deep), "a: 2");
+
+    let replace =
+      MergeOptions { maps: Strategy::Replace, ..Default::default() };
+    assert_eq!(merge("a: 1 b: 2", "b: 3", &replace), "b: 3");
+
+    let prepend =
+      MergeOptions { lists: Strategy::Prepend, ..Default::default() };
+    assert_eq!(merge("l: (1 2)", "l: (3 4)", &prepend), "l: (3 4 1 2)");
+  }
+
+  #[test]
+  fn union_by() {
+    let options = MergeOptions {
+      lists: Strategy::UnionBy(Item::new_atom(b"name")),
+      ..Default::default()
+    };
+    assert_eq!(
+      merge(
+        "members: ((name: a port: 1) (name: b port: 2) x)",
+        "members: ((name: b port: 3) (name: c port: 4) x y)",
+        &options
+      ),
+      "members: ((name: a port: 1) (name: b port: 3) x (name: c port: 4) y)"
+    );
+  }
+
+  #[test]
+  fn path_strategies() {
+    let path = |s: &str| s.parse::<Path>().unwrap();
+    let options = MergeOptions {
+      paths: vec![
+        (path("a/*/l"), Strategy::Append),
+        (path("b"), Strategy::Replace),
+      ],
+      ..Default::default()
+    };
+    assert_eq!(
+      merge(
+        "a: (x: (l: (1)) y: (l: (2))) b: (k: 1)",
+        "a: (y: (l: (3))) b: (j: 2)",
+        &options
+      ),
+      "a: (x: (l: (1)) y: (l: (2 3))) b: (j: 2)"
+    );
+  }
+
+  #[test]
+  fn provenance() {
+    let options =
+      MergeOptions { lists: Strategy::Prepend, ..Default::default() };
+    let mut layers = Layers::new(options);
+    layers.push(&doc("a: 1 b: (x: 1) l: (p)"));
+    layers.push(&doc("b: (y: 2) l: (q)"));
+    layers.push(&doc("c: 3 b: (x: 4)"));
+
+    let provenance = layers
+      .provenance()
+      .into_iter()
+      .map(|(path, layer)| format!("{path}:{layer}"))
+      .collect::<Vec<_>>();
+    assert_eq!(provenance, ["a:0", "b/x:2", "b/y:1", "l/0:1", "l/1:0", "c:2"]);
+
+    let layer = |s: &str| layers.layer(&s.parse().unwrap());
+    assert_eq!(layer("b"), Some(2));
+    assert_eq!(layer("l"), Some(1));
+    assert_eq!(layer(""), Some(2));
+    assert_eq!(layer("d"), None);
+    assert_eq!(layers.len(), 3);
+  }
+
+  #[test]
+  fn spans() {
+    let options =
+      MergeOptions { lists: Strategy::Append, ..Default::default() };
+    let mut layers = Layers::new(options);
+    for input in ["a: 1\nl: (p)", "l: (q r)\nb: 2"] {
+      let (item, spans) = crate::parse_spanned(input.as_bytes()).unwrap();
+      layers.push_spanned(&item, &spans);
+    }
+    layers.push(&doc("c: 3"));
+
+    let span = |s: &str| {
+      let (layer, span) =
+        (layers.layer(&s.parse().unwrap()), layers.span(&s.parse().unwrap()));
+      format!(
+        "{}@{}",
+        layer.unwrap(),
+        span.map(|s| s.to_string()).unwrap_or_default()
+      )
+    };
+    assert_eq!(span("a"), "0@1:4");
+    assert_eq!(span("l"), "1@1:4");
+    assert_eq!(span("l/0"), "0@2:5");
+    assert_eq!(span("l/2"), "1@1:7");
+    assert_eq!(span("b"), "1@2:4");
+    assert_eq!(span("c"), "2@");
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,259 @@
+use rmpv::{Integer, Value};
+
+use crate::atom::float_text;
+use crate::codec::{number, to_value, Codec, FLOAT, INT};
+use crate::{Atom, CodecError, CodecOptions, Item, Path};
+
+const EXT: &[u8] = b"ext";
+
+/// Options for [`from_msgpack()`] and [`to_msgpack()`]
+pub type MsgpackOptions = CodecOptions;
+
+/// An error of [`from_msgpack()`] and [`to_msgpack()`]
+pub type MsgpackError = CodecError;
+
+fn error(path: &Path, reason: impl Into<String>) -> MsgpackError {
+  Value::error(path, reason)
+}
+
+/// Decode a MessagePack value to an item.
+///
+/// Maps become maps and arrays become lists. Strings and binaries become
+/// atoms of their bytes, numbers and booleans of their text and nil is
+/// `null`. Extension types become atoms of their data tagged `ext` and the
+/// type, `ext-1` for timestamps.
+///
+/// ```
+/// # use axp::{from_msgpack, parse, MsgpackOptions};
+/// // {"port": 80, "tags": [true, nil]}
+/// let bytes = b"\x82\xa4port\x50\xa4tags\x92\xc3\xc0";
+/// let item = from_msgpack(bytes, &MsgpackOptions::default()).unwrap();
+/// assert_eq!(item, parse(b"port: 80 tags: (true null)").unwrap());
+///
+/// let options = MsgpackOptions { tag_numbers: true };
+/// let item = from_msgpack(bytes, &options).unwrap();
+/// assert_eq!(item, parse(br##"port: #(int)"80#(int)" tags: (true null)"##).unwrap());
+/// ```
+pub fn from_msgpack(
+  bytes: &[u8],
+  options: &MsgpackOptions,
+) -> Result<Item, MsgpackError> {
+  let mut reader = bytes;
+  let value = rmpv::decode::read_value(&mut reader)
+    .map_err(|err| error(&Path::root(), err.to_string()))?;
+  if reader.is_empty() {
+    Ok(from_value(&value, options))
+  } else {
+    Err(error(&Path::root(), "bytes after the value"))
+  }
+}
+
+fn from_value(value: &Value, options: &MsgpackOptions) -> Item {
+  match value {
+    Value::Nil => Item::new_atom(b"null"),
+    Value::Boolean(bool) => Item::new_atom(bool.to_string().as_bytes()),
+    Value::Integer(integer) => number(integer.to_string(), INT, options),
+    Value::F32(float) => number(float_text(*float), FLOAT, options),
+    Value::F64(float) => number(float_text(*float), FLOAT, options),
+    Value::String(string) => Item::new_atom(string.as_bytes()),
+    Value::Binary(bytes) => Item::new_atom(bytes),
+    Value::Array(array) => {
+      Item::new_list(array.iter().map(|value| from_value(value, options)))
+    }
+    Value::Map(map) => Item::new_map(map.iter().map(|(key, value)| {
+      (from_value(key, options), from_value(value, options))
+    })),
+    Value::Ext(kind, data) => {
+      let tag = [EXT, kind.to_string().as_bytes()].concat();
+      Item::Atom(Atom(data.clone(), Some(tag)))
+    }
+  }
+}
+
+/// Encode an item as a MessagePack value.
+///
+/// Maps become maps and lists become arrays. Atoms tagged `int` and `float`
+/// become numbers, atoms tagged `ext` and a type become extension types.
+/// Untagged atoms `true`, `false` and `null` become booleans and nil, unless
+/// [`MsgpackOptions::tag_numbers`] numbers which are written back the same
+/// become numbers, the rest become strings or, if not UTF-8, binaries. Other
+/// tags are errors.
+///
+/// ```
+/// # use axp::{from_msgpack, parse, to_msgpack, MsgpackOptions};
+/// let item = parse(b"port: 80 name: axp").unwrap();
+/// let bytes = to_msgpack(&item, &MsgpackOptions::default()).unwrap();
+/// assert_eq!(bytes, b"\x82\xa4port\x50\xa4name\xa3axp");
+/// assert_eq!(from_msgpack(&bytes, &MsgpackOptions::default()), Ok(item));
+/// ```
+pub fn to_msgpack(
+  item: &Item,
+  options: &MsgpackOptions,
+) -> Result<Vec<u8>, MsgpackError> {
+  let value = to_value(&Path::root(), item, options)?;
+  let mut out = Vec::new();
+  rmpv::encode::write_value(&mut out, &value).expect("writing to a vec");
+  Ok(out)
+}
+
+impl Codec for Value {
+  const FORMAT: &'static str = "Msgpack";
+
+  fn scalar(
+    path: &Path,
+    atom: &Atom,
+    options: &MsgpackOptions,
+  ) -> Result<Value, MsgpackError> {
+    let bytes = atom.as_bytes();
+    let text = std::str::from_utf8(bytes).ok();
+    let not = |kind| {
+      let text = String::from_utf8_lossy(bytes);
+      error(path, format!("{text} is not {kind}"))
+    };
+    match atom.tag() {
+      Some(INT) => {
+        text.and_then(integer).map(Value::Integer).ok_or(not("an integer"))
+      }
+      Some(FLOAT) => match text.map(str::parse::<f64>) {
+        Some(Ok(float)) => Ok(Value::F64(float)),
+        _ => Err(not("a float")),
+      },
+      Some(tag) => {
+        let kind = tag
+          .strip_prefix(EXT)
+          .and_then(|kind| std::str::from_utf8(kind).ok()?.parse::<i8>().ok());
+        match kind {
+          Some(kind) => Ok(Value::Ext(kind, bytes.to_vec())),
+          None => {
+            let tag = String::from_utf8_lossy(tag);
+            Err(error(path, format!("tag {tag} has no MessagePack type")))
+          }
+        }
+      }
+      None => Ok(match text {
+        None => Value::Binary(bytes.to_vec()),
+        Some("null") => Value::Nil,
+        Some("true") => Value::Boolean(true),
+        Some("false") => Value::Boolean(false),
+        Some(text) if options.tag_numbers => Value::from(text),
+        Some(text) => match (integer(text), text.parse::<f64>()) {
+          (Some(integer), _) => Value::Integer(integer),
+          (_, Ok(float)) if float_text(float) == text => Value::F64(float),
+          _ => Value::from(text),
+        },
+      }),
+    }
+  }
+
+  fn array(items: Vec<Value>) -> Value {
+    Value::Array(items)
+  }
+
+  fn map(entries: Vec<(Value, Value)>) -> Value {
+    Value::Map(entries)
+  }
+}
+
+// An integer, if its text is the same
+fn integer(text: &str) -> Option<Integer> {
+  let integer = match text.parse::<i64>() {
+    Ok(integer) => integer.into(),
+    Err(_) => text.parse::<u64>().ok().map(Integer::from)?,
+  };
+  Some(integer).filter(|integer| integer.to_string() == text)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_msgpack, to_msgpack, MsgpackOptions};
+  use crate::{canonical, parse, Item};
+
+  const TAGGED: MsgpackOptions = MsgpackOptions { tag_numbers: true };
+
+  fn decode(bytes: &[u8], options: &MsgpackOptions) -> String {
+    let item = from_msgpack(bytes, options).unwrap();
+    String::from_utf8(canonical(&item)).unwrap()
+  }
+
+  fn encode(input: &str, options: &MsgpackOptions) -> Vec<u8> {
+    to_msgpack(&parse(input.as_bytes()).unwrap(), options).unwrap()
+  }
+
+  #[test]
+  fn decodes() {
+    // [-1, 1.5f32, 0.1, "x", bin "\xff", {[1]: {}}, ext -1 "\x00\x00\x00\x01"]
+    let bytes =
+      b"\x97\xff\xca\x3f\xc0\x00\x00\xcb\x3f\xb9\x99\x99\x99\x99\x99\x9a\
+      \xa1x\xc4\x01\xff\x81\x91\x01\x80\xd6\xff\x00\x00\x00\x01";
+    assert_eq!(
+      decode(bytes, &MsgpackOptions::default()),
+      r##"-1 1.5 0.1 x "\xff" ((1): ()) #(ext-1)"#(ext-1)\0#(ext-1)\0#(ext-1)\0#(ext-1)\x01#(ext-1)""##
+    );
+    assert_eq!(
+      decode(bytes, &TAGGED),
+      r##"#(int)"-1#(int)" #(float)"1.5#(float)" #(float)"0.1#(float)" x "\xff" ((#(int)"1#(int)"): ()) #(ext-1)"#(ext-1)\0#(ext-1)\0#(ext-1)\0#(ext-1)\x01#(ext-1)""##
+    );
+  }
+
+  #[test]
+  fn encodes() {
+    let options = MsgpackOptions::default();
+    assert_eq!(
+      encode("1 -1 1.5 1e3 01 true null", &options),
+      b"\x97\x01\xff\xcb\x3f\xf8\x00\x00\x00\x00\x00\x00\xa31e3\xa201\xc3\xc0"
+    );
+    assert_eq!(
+      encode("18446744073709551615", &options),
+      b"\x91\xcf\xff\xff\xff\xff\xff\xff\xff\xff"
+    );
+    assert_eq!(encode(r##"1 #(int)"2#(int)""##, &TAGGED), b"\x92\xa11\x02");
+    let binary = Item::new_atom(b"\xff");
+    assert_eq!(to_msgpack(&binary, &options).unwrap(), b"\xc4\x01\xff");
+
+    let roundtrip = r##"a: (1 "01" #(float)"2.5#(float)" "" "\xff") (k l): (c: ()) e: #(ext5)"x#(ext5)""##;
+    let item = parse(roundtrip.as_bytes()).unwrap();
+    let bytes = to_msgpack(&item, &TAGGED).unwrap();
+    assert_eq!(from_msgpack(&bytes, &TAGGED), Ok(item));
+  }
+
+  #[test]
+  fn errors() {
+    let err = |input: &str| {
+      let item = parse(input.as_bytes()).unwrap();
+      to_msgpack(&item, &MsgpackOptions::default()).unwrap_err().to_string()
+    };
+    assert_eq!(
+      err(r##"a: #(int)"x#(int)""##),
+      "MsgpackError: a: x is not an integer"
+    );
+    assert_eq!(
+      err(r##"a: #(int)"01#(int)""##),
+      "MsgpackError: a: 01 is not an integer"
+    );
+    assert_eq!(
+      err(r##"a: #(int)"+5#(int)""##),
+      "MsgpackError: a: +5 is not an integer"
+    );
+    assert_eq!(
+      err(r##"a: (#(float)"#(float)")"##),
+      "MsgpackError: a/0:  is not a float"
+    );
+    assert_eq!(
+      err(r##"#(date)"x#(date)""##),
+      "MsgpackError: 0: tag date has no MessagePack type"
+    );
+    assert_eq!(
+      err(r##"#(ext999)"x#(ext999)""##),
+      "MsgpackError: 0: tag ext999 has no MessagePack type"
+    );
+
+    let options = MsgpackOptions::default();
+    assert!(from_msgpack(b"\x92\x01", &options).is_err());
+    assert_eq!(
+      from_msgpack(b"\x01\x02", &options).unwrap_err().to_string(),
+      "MsgpackError: bytes after the value"
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,471 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use num_rational::Ratio;
+use num_traits::{
+  CheckedAdd, CheckedDiv, CheckedEuclid, CheckedMul, CheckedSub,
+};
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use crate::atom::float_text;
+use crate::evaluate::{error, Ctx};
+use crate::{EvalError, EvalErrorKind, Item, List};
+
+// The integers of exact numbers, unbounded with the bigint feature
+#[cfg(not(feature = "bigint"))]
+type Int = i64;
+#[cfg(feature = "bigint")]
+type Int = num_bigint::BigInt;
+
+// A number read from an atom, an exact integer or rational or a float
+#[derive(Clone, Debug, PartialEq)]
+enum Number {
+  Exact(Ratio<Int>),
+  Float(f64),
+}
+
+impl fmt::Display for Number {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Number::Exact(exact) => write!(f, "{exact}"),
+      Number::Float(float) => f.write_str(&float_text(*float)),
+    }
+  }
+}
+
+impl Number {
+  fn into_item(self) -> Item {
+    Item::new_atom(self.to_string().as_bytes())
+  }
+
+  fn to_f64(&self) -> f64 {
+    match self {
+      Number::Exact(exact) => exact.to_f64().unwrap_or(f64::NAN),
+      Number::Float(float) => *float,
+    }
+  }
+
+  // Exact if both are exact, an exact operation gives none on overflow
+  fn apply(
+    self,
+    other: Number,
+    exact: fn(&Ratio<Int>, &Ratio<Int>) -> Option<Ratio<Int>>,
+    float: fn(f64, f64) -> f64,
+  ) -> Result<Number, EvalError> {
+    match (self, other) {
+      (Number::Exact(a), Number::Exact(b)) => match exact(&a, &b) {
+        Some(result) => Ok(Number::Exact(result)),
+        None => Err(overflow()),
+      },
+      (a, b) => Ok(Number::Float(float(a.to_f64(), b.to_f64()))),
+    }
+  }
+
+  fn add(self, other: Number) -> Result<Number, EvalError> {
+    self.apply(other, |a, b| a.checked_add(b), |a, b| a + b)
+  }
+
+  fn sub(self, other: Number) -> Result<Number, EvalError> {
+    self.apply(other, |a, b| a.checked_sub(b), |a, b| a - b)
+  }
+
+  fn mul(self, other: Number) -> Result<Number, EvalError> {
+    self.apply(other, |a, b| a.checked_mul(b), |a, b| a * b)
+  }
+
+  fn div(self, other: Number) -> Result<Number, EvalError> {
+    divisor(&self, &other)?;
+    self.apply(other, |a, b| a.checked_div(b), |a, b| a / b)
+  }
+
+  // The remainder of flooring division, with the sign of the divisor
+  fn modulo(self, other: Number) -> Result<Number, EvalError> {
+    divisor(&self, &other)?;
+    // The remainder of the numerators over a common denominator, unlike
+    // Ratio::floor this neither panics nor overflows if the result fits
+    let exact = |a: &Ratio<Int>, b: &Ratio<Int>| {
+      let dividend = a.numer().checked_mul(b.denom())?;
+      let modulus = b.numer().checked_mul(a.denom())?;
+      // only MIN % -1 overflows, which is 0
+      let rem = CheckedEuclid::checked_rem_euclid(&dividend, &modulus);
+      let rem = rem.unwrap_or_else(Int::zero);
+      let rem = if !rem.is_zero() && modulus.is_negative() {
+        rem + modulus
+      } else {
+        rem
+      };
+      Some(Ratio::new(rem, a.denom().checked_mul(b.denom())?))
+    };
+    let float = |a: f64, b: f64| match a % b {
+      rem if rem != 0.0 && (rem < 0.0) != (b < 0.0) => rem + b,
+      rem => rem,
+    };
+    self.apply(other, exact, float)
+  }
+
+  fn abs(self) -> Result<Number, EvalError> {
+    match self {
+      Number::Exact(exact) if exact.is_negative() => {
+        Number::Exact(Ratio::zero()).sub(Number::Exact(exact))
+      }
+      Number::Float(float) => Ok(Number::Float(float.abs())),
+      number => Ok(number),
+    }
+  }
+
+  fn compare(&self, other: &Number) -> Option<Ordering> {
+    match (self, other) {
+      (Number::Exact(a), Number::Exact(b)) => Some(a.cmp(b)),
+      (a, b) => a.to_f64().partial_cmp(&b.to_f64()),
+    }
+  }
+}
+
+fn overflow() -> EvalError {
+  let reason = "the result is out of the range of 64-bit integers";
+  error(EvalErrorKind::Overflow, reason)
+}
+
+// Exact division by zero is an error, float division gives inf or nan
+fn divisor(dividend: &Number, divisor: &Number) -> Result<(), EvalError> {
+  match (dividend, divisor) {
+    (Number::Exact(_), Number::Exact(zero)) if zero.is_zero() => {
+      Err(error(EvalErrorKind::DivisionByZero, "division by zero"))
+    }
+    _ => Ok(()),
+  }
+}
+
+// An optional sign and digits
+fn is_integer(text: &str) -> bool {
+  let digits = text.strip_prefix(['-', '+']).unwrap_or(text);
+  !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn integer(text: &str) -> Result<Int, EvalError> {
+  text.parse::<Int>().map_err(|_| {
+    let reason = format!("{text} is out of the range of 64-bit integers");
+    error(EvalErrorKind::Overflow, reason)
+  })
+}
+
+// Read an integer `-12`, a rational `3/4` or a float `1.5`, `1e3` or `inf`
+fn number(name: &str, item: &Item) -> Result<Number, EvalError> {
+  let not = || {
+    let reason = format!("{name} takes numbers, not {item}");
+    error(EvalErrorKind::TypeError, reason)
+  };
+  let Item::Atom(atom) = item else {
+    return Err(not());
+  };
+  let text = std::str::from_utf8(atom.as_bytes()).map_err(|_| not())?;
+  if is_integer(text) {
+    return Ok(Number::Exact(Ratio::from_integer(integer(text)?)));
+  }
+  if let Some((numer, denom)) = text.split_once('/') {
+    let digits = denom.bytes().all(|b| b.is_ascii_digit());
+    if !is_integer(numer) || !is_integer(denom) || !digits {
+      return Err(not());
+    }
+    let (numer, denom) = (integer(numer)?, integer(denom)?);
+    if denom.is_zero() {
+      return Err(error(EvalErrorKind::DivisionByZero, "division by zero"));
+    }
+    return Ok(Number::Exact(Ratio::new(numer, denom)));
+  }
+  let float = text.bytes().any(|b| b.is_ascii_digit())
+    && text.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+  match text.strip_prefix(['-', '+']).unwrap_or(text) {
+    _ if float => text.parse().map(Number::Float).map_err(|_| not()),
+    "inf" | "nan" => Ok(Number::Float(text.parse().map_err(|_| not())?)),
+    _ => Err(not()),
+  }
+}
+
+fn numbers(name: &str, args: &List) -> Result<Vec<Number>, EvalError> {
+  args.iter().map(|arg| number(name, arg)).collect()
+}
+
+fn fold(
+  name: &str,
+  args: &List,
+  op: fn(Number, Number) -> Result<Number, EvalError>,
+) -> Result<Item, EvalError> {
+  let mut numbers = numbers(name, args)?.into_iter();
+  let first = numbers.next().expect("the arity is at least one");
+  numbers.try_fold(first, op).map(Number::into_item)
+}
+
+fn truth(true_: bool) -> Item {
+  if true_ {
+    Item::new_atom(b"true")
+  } else {
+    Item::nil()
+  }
+}
+
+fn chain(
+  name: &str,
+  args: &List,
+  holds: fn(Ordering) -> bool,
+) -> Result<Item, EvalError> {
+  let numbers = numbers(name, args)?;
+  let pairs = numbers.windows(2);
+  Ok(truth(
+    pairs.into_iter().all(|pair| pair[0].compare(&pair[1]).is_some_and(holds)),
+  ))
+}
+
+// The first of the least or greatest numbers, nan is never chosen
+fn extreme(
+  name: &str,
+  args: &List,
+  wanted: Ordering,
+) -> Result<Item, EvalError> {
+  let numbers = numbers(name, args)?.into_iter();
+  let chosen = numbers.reduce(|chosen, number| {
+    if number.compare(&chosen) == Some(wanted) || chosen.to_f64().is_nan() {
+      number
+    } else {
+      chosen
+    }
+  });
+  Ok(chosen.expect("the arity is at least one").into_item())
+}
+
+/// The sum of numbers, `0` if there are none
+///
+/// ```
+/// # use axp::{parse, Interpreter, Item};
+/// let interpreter =
+///   Interpreter::new().define("base_port", Item::new_atom(b"8080"));
+/// let command = parse(b"list (+ base_port 1) (+ 1/2 1/3) (+ 1 0.5) (+)");
+/// let result = interpreter.evaluate(&command.unwrap()).unwrap();
+/// assert_eq!(format!("{result}"), "(8081 5/6 1.5 0)");
+/// ```
+pub(crate) fn prim_add(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  let zero = Number::Exact(Ratio::zero()).into_item();
+  let args = List::new(std::iter::once(zero).chain(args.iter().cloned()));
+  fold("+", &args, Number::add)
+}
+
+/// The first number minus the others, or the negated number
+pub(crate) fn prim_sub(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  match args.len() {
+    1 => {
+      let number = number("-", &args.first())?;
+      Number::Exact(Ratio::zero()).sub(number).map(Number::into_item)
+    }
+    _ => fold("-", args, Number::sub),
+  }
+}
+
+/// The product of numbers, `1` if there are none
+pub(crate) fn prim_mul(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  let one = Number::Exact(Ratio::one()).into_item();
+  let args = List::new(std::iter::once(one).chain(args.iter().cloned()));
+  fold("*", &args, Number::mul)
+}
+
+/// The first number divided by the others, or the reciprocal
+///
+/// ```
+/// # use axp::{evaluate, parse, EvalErrorKind};
+/// let command = parse(b"list (/ 6 4) (/ 6 3) (/ 3) (/ 1 4.0) (/ 1.0 0)");
+/// let result = evaluate(&command.unwrap()).unwrap();
+/// assert_eq!(format!("{result}"), "(3/2 2 1/3 0.25 inf)");
+/// let err = evaluate(&parse(b"/ 1 0").unwrap()).unwrap_err();
+/// assert_eq!(err.kind(), EvalErrorKind::DivisionByZero);
+/// ```
+pub(crate) fn prim_div(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  match args.len() {
+    1 => {
+      let number = number("/", &args.first())?;
+      Number::Exact(Ratio::one()).div(number).map(Number::into_item)
+    }
+    _ => fold("/", args, Number::div),
+  }
+}
+
+/// The remainder of flooring division, it has the sign of the divisor
+pub(crate) fn prim_mod(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  fold("mod", args, Number::modulo)
+}
+
+pub(crate) fn prim_abs(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  number("abs", &args.first())?.abs().map(Number::into_item)
+}
+
+pub(crate) fn prim_min(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  extreme("min", args, Ordering::Less)
+}
+
+pub(crate) fn prim_max(
+  _: &mut Ctx<'_>,
+  args: &List,
+) -> Result<Item, EvalError> {
+  extreme("max", args, Ordering::Greater)
+}
+
+/// Whether numbers are increasing, `true` or `()` as `if` takes `()` as false
+///
+/// ```
+/// # use axp::{evaluate, parse};
+/// let command = parse(b"list (< 1 2 3) (< 1 3 2) (<= 1 1.0 3/2) (= 2 4/2 2.0)");
+/// let result = evaluate(&command.unwrap()).unwrap();
+/// assert_eq!(format!("{result}"), "(true () true true)");
+/// ```
+pub(crate) fn prim_lt(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  chain("<", args, Ordering::is_lt)
+}
+
+pub(crate) fn prim_le(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  chain("<=", args, Ordering::is_le)
+}
+
+pub(crate) fn prim_eq(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  chain("=", args, Ordering::is_eq)
+}
+
+pub(crate) fn prim_ge(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  chain(">=", args, Ordering::is_ge)
+}
+
+pub(crate) fn prim_gt(_: &mut Ctx<'_>, args: &List) -> Result<Item, EvalError> {
+  chain(">", args, Ordering::is_gt)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{evaluate, parse, EvalErrorKind};
+
+  fn eval(input: &str) -> String {
+    evaluate(&parse(input.as_bytes()).unwrap()).unwrap().to_string()
+  }
+
+  fn err(input: &str) -> (EvalErrorKind, String) {
+    let err = evaluate(&parse(input.as_bytes()).unwrap()).unwrap_err();
+    (err.kind(), err.reason().to_string())
+  }
+
+  #[test]
+  fn arithmetic() {
+    assert_eq!(eval("+ 1 2 3"), "6");
+    assert_eq!(eval("+ +1 -1"), "0");
+    assert_eq!(eval("- 10 1 2"), "7");
+    assert_eq!(eval("- 3"), "-3");
+    assert_eq!(eval("- 1/2"), "-1/2");
+    assert_eq!(eval("* 2 3/4"), "3/2");
+    assert_eq!(eval("*"), "1");
+    assert_eq!(eval("/ 2 4 2"), "1/4");
+    assert_eq!(eval("/ -6 4"), "-3/2");
+    assert_eq!(eval("+ 1/2 1/2"), "1");
+    assert_eq!(eval("+ 1 1.5"), "2.5");
+    assert_eq!(eval("* 2 1e3"), "2000.0");
+    assert_eq!(eval("- 0.5 inf"), "-inf");
+    assert_eq!(eval("+ 007 1"), "8");
+    assert_eq!(eval("let ((x 2)) (* x (+ x 1))"), "6");
+  }
+
+  #[test]
+  fn modulo_and_abs() {
+    assert_eq!(eval("mod 7 3"), "1");
+    assert_eq!(eval("mod -7 3"), "2");
+    assert_eq!(eval("mod 7 -3"), "-2");
+    assert_eq!(eval("mod 7/2 1"), "1/2");
+    assert_eq!(eval("mod -7.5 2"), "0.5");
+    assert_eq!(eval("mod 1.0 0"), "nan");
+    assert_eq!(eval("abs -3/4"), "3/4");
+    assert_eq!(eval("abs -2.5"), "2.5");
+    assert_eq!(eval("abs 3"), "3");
+  }
+
+  #[test]
+  fn comparisons() {
+    assert_eq!(eval("< 1"), "true");
+    assert_eq!(eval("> 3 2 1"), "true");
+    assert_eq!(eval(">= 3 3 4"), "()");
+    assert_eq!(eval("= 1/3 2/6"), "true");
+    assert_eq!(eval("= nan nan"), "()");
+    assert_eq!(eval("if (< 1 2) a b"), "a");
+    assert_eq!(eval("if (> 1 2) a b"), "b");
+    assert_eq!(eval("min 3 1/2 2.0"), "1/2");
+    assert_eq!(eval("max 3 1/2 4.0 4"), "4.0");
+    assert_eq!(eval("max nan 1 2"), "2");
+  }
+
+  #[test]
+  fn errors() {
+    use EvalErrorKind::*;
+    assert_eq!(err("+ 1 x"), (TypeError, "+ takes numbers, not x".into()));
+    assert_eq!(
+      err("+ 1 (quote (2))"),
+      (TypeError, "+ takes numbers, not (2)".into())
+    );
+    for input in ["+ 1/-2", "+ 1/", "+ e3", "+ 1.2.3", "+ -", "+ infinity"] {
+      assert_eq!(err(input).0, TypeError, "{input}");
+    }
+    assert_eq!(err("/ 1 0"), (DivisionByZero, "division by zero".into()));
+    assert_eq!(err("mod 1 0").0, DivisionByZero);
+    assert_eq!(err("+ 1/0").0, DivisionByZero);
+    assert_eq!(err("abs").0, ArityMismatch);
+    assert_eq!(err("mod 1").0, ArityMismatch);
+  }
+
+  #[cfg(not(feature = "bigint"))]
+  #[test]
+  fn overflow() {
+    use EvalErrorKind::Overflow;
+    let max = "9223372036854775807";
+    assert_eq!(eval(&format!("- {max}")), "-9223372036854775807");
+    assert_eq!(err(&format!("+ {max} 1")).0, Overflow);
+    assert_eq!(err(&format!("* {max} 2")).0, Overflow);
+    assert_eq!(err("abs -9223372036854775808").0, Overflow);
+    assert_eq!(eval("mod -9223372036854775808 3"), "1");
+    assert_eq!(eval("mod -9223372036854775808 -1"), "0");
+    assert_eq!(eval("mod -9223372036854775807/2 3"), "5/2");
+    assert_eq!(
+      err("+ 9223372036854775808"),
+      (
+        Overflow,
+        "9223372036854775808 is out of the range of 64-bit integers".into()
+      )
+    );
+  }
+
+  #[cfg(feature = "bigint")]
+  #[test]
+  fn bigint() {
+    let max = "9223372036854775807";
+    assert_eq!(eval(&format!("+ {max} 1")), "9223372036854775808");
+    assert_eq!(
+      eval(&format!("* {max} {max}")),
+      "85070591730234615847396907784232501249"
+    );
+    assert_eq!(eval("abs -9223372036854775808"), "9223372036854775808");
+    assert_eq!(eval(&format!("/ 1 (* {max} 2)")), "1/18446744073709551614");
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -6,7 +6,7 @@ use std::f32::consts::LOG10_2;
 
 use crate::lex::AxpLexer;
 use crate::pretty::PrettyUtf8;
-use crate::{lex, Atom, Item, List, Map, Token};
+use crate::{lex, Atom, Item, List, Map, Path, Span, Spans, Token};
 use crate::{map, Token::*};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -51,10 +51,38 @@ struct Parser<'b> {
   lexer: AxpLexer<'b>,
   token: Option<Token<'b>>,
   mode: Mode,
+  input: &'b [u8],
+  spans: Option<(Spans, Vec<usize>)>,
 }
 
-// todo handle col, line
 impl<'b> Parser<'b> {
+  fn new(input: &'b [u8], spanned: bool) -> Self {
+    let mut lexer = lex(input);
+    let token = lexer.next();
+    let spans = spanned.then(|| (Spans::default(), Span::lines(input)));
+    Parser { lexer, token, mode: Mode::Top, input, spans }
+  }
+
+  // The start of the current token
+  fn start(&self) -> usize {
+    self.lexer.span().start
+  }
+
+  // Record the span from start to the end of the current token
+  fn record(&mut self, path: Path, start: usize) {
+    if let Some((spans, lines)) = &mut self.spans {
+      let span = Span::new(self.input, lines, start, self.lexer.span().end);
+      spans.0.entry(path).or_insert(span);
+    }
+  }
+
+  // Forget the spans of a path and below
+  fn forget(&mut self, path: &Path) {
+    if let Some((spans, _)) = &mut self.spans {
+      spans.0.retain(|p, _| !p.starts_with(path));
+    }
+  }
+
   fn next(&mut self) -> Option<Token<'b>> {
     let old_token = self.token;
     self.token = self.lexer.next();
@@ -66,6 +94,10 @@ impl<'b> Parser<'b> {
     self
   }
 
+  fn peek(&self) -> Option<Token<'b>> {
+    self.lexer.clone().next()
+  }
+
   fn skip_ws(&mut self) -> Option<Token<'b>> {
     while let Some(WhiteSpace(_) | Comment(_)) = self.token {
       self.token = self.lexer.next()
@@ -81,12 +113,27 @@ enum Mode {
 }
 
 pub fn parse(input: &[u8]) -> Parse<Item> {
-  let mut lexer = lex(input);
-  let token = lexer.next();
-  parse_compound(&mut Parser { lexer, token, mode: Mode::Top })
+  parse_compound(&mut Parser::new(input, false), Some(&Path::root()))
+}
+
+/// Parse and record the spans of all list items and map values by path.
+///
+/// ```
+/// # use axp::{parse_spanned, Path};
+/// let (item, spans) = parse_spanned(b"name: x\nserver: (port: 80)").unwrap();
+/// let span = spans.get(&"server/port".parse().unwrap()).unwrap();
+/// assert_eq!((span.line, span.col), (2, 16));
+/// assert_eq!(format!("{span}"), "2:16");
+/// ```
+pub fn parse_spanned(input: &[u8]) -> Parse<(Item, Spans)> {
+  let mut parser = Parser::new(input, true);
+  let item = parse_compound(&mut parser, Some(&Path::root()))?;
+  let (spans, _) = parser.spans.unwrap_or_default();
+  Ok((item, spans))
 }
 
-fn parse_compound(parser: &mut Parser<'_>) -> Parse<Item> {
+// The path is where the compound is in the document, keys have no path
+fn parse_compound(parser: &mut Parser<'_>, path: Option<&Path>) -> Parse<Item> {
   use Item::*;
 
   macro_rules! push_item_and_continue {
@@ -109,12 +156,24 @@ fn parse_compound(parser: &mut Parser<'_>) -> Parse<Item> {
     let token = parser.skip_ws();
     log::trace!("parse_compound key: token={token:?} item={item:?}");
 
+    // the path of the element if this is a list, the first element might be
+    // a key, then the spans get forgotten below
+    let start = parser.start();
+    let element_path = match (&item, path) {
+      (List(list), Some(path)) => Some(path.join_index(list.len())),
+      _ => None,
+    };
+
     // get key, get element or close compound
     let key = match token {
       Some(Bare(s)) => parse_bare(parser)?,
-      Some(Open) => parse_compound(parser.next_fluent())?,
-      Some(Quoted(s)) => parse_quoted(parser)?,
-      x @ Some(Esc(_) | WhiteSpace(_) | Comment(_)) => unreachable!("{x:?}"),
+      Some(Open) => {
+        parse_compound(parser.next_fluent(), element_path.as_ref())?
+      }
+      Some(Quote(s)) => parse_quoted(parser)?,
+      x @ Some(Quoted(_) | Esc(_) | WhiteSpace(_) | Comment(_)) => {
+        unreachable!("{x:?}")
+      }
 
       Some(Bad(s)) => throw!("bad: {}", s.pretty()),
       Some(Colon) => throw!("unexpected :"),
@@ -126,6 +185,10 @@ fn parse_compound(parser: &mut Parser<'_>) -> Parse<Item> {
       None => throw!("unexpected end"),
     };
 
+    if let Some(element_path) = &element_path {
+      parser.record(element_path.clone(), start);
+    }
+
     parser.next();
     let token = parser.skip_ws();
     log::trace!("parse_compound colon: token={token:?}");
@@ -133,7 +196,12 @@ fn parse_compound(parser: &mut Parser<'_>) -> Parse<Item> {
     // for lists push and continue or for maps handle colon
     match (token, &mut item) {
       // on first iteration item is an empty list, mutate to map
-      (Some(Colon), List(list)) if list.is_empty() => item = Item::new_map([]),
+      (Some(Colon), List(list)) if list.is_empty() => {
+        if let Some(element_path) = &element_path {
+          parser.forget(element_path);
+        }
+        item = Item::new_map([]);
+      }
 
       // token will be handled in the next loop iteration
       (_, List(ref mut list)) => push_item_and_continue!(parser, list, key),
@@ -150,12 +218,16 @@ fn parse_compound(parser: &mut Parser<'_>) -> Parse<Item> {
     log::trace!("parse_compound value: token={token:?}");
 
     //  maps only: get value
+    let start = parser.start();
+    let value_path = path.map(|path| path.join(key.clone()));
     let value = match token {
       Some(Bare(s)) => parse_bare(parser)?,
-      Some(Open) => parse_compound(parser)?,
-      Some(Quoted(s)) => parse_quoted(parser)?,
+      Some(Open) => parse_compound(parser.next_fluent(), value_path.as_ref())?,
+      Some(Quote(s)) => parse_quoted(parser)?,
 
-      x @ Some(Esc(_) | WhiteSpace(_) | Comment(_)) => unreachable!("{x:?}"),
+      x @ Some(Quoted(_) | Esc(_) | WhiteSpace(_) | Comment(_)) => {
+        unreachable!("{x:?}")
+      }
 
       Some(Bad(s)) => throw!("bad: {}", s.pretty()),
       Some(Colon) => throw!("unexpected :"),
@@ -163,6 +235,10 @@ fn parse_compound(parser: &mut Parser<'_>) -> Parse<Item> {
       None => throw!("unexpected end"),
     };
 
+    if let Some(value_path) = value_path {
+      parser.record(value_path, start);
+    }
+
     // push entry
     match &mut item {
       Item::Map(ref mut map) => {
@@ -176,23 +252,109 @@ fn parse_compound(parser: &mut Parser<'_>) -> Parse<Item> {
   unreachable!("loop ended without returning");
 }
 
-// todo concatenate bares
+// The lexer breaks up long bares, concatenate them again
 fn parse_bare(parser: &mut Parser<'_>) -> Parse<Item> {
-  if let Some(Bare(s)) = parser.token {
-    Ok(Item::new_atom(s))
-  } else {
-    throw!("not a bare")
+  let mut atom = match parser.token {
+    Some(Bare(s)) => s.to_vec(),
+    _ => throw!("not a bare"),
+  };
+
+  while let Some(Bare(s)) = parser.peek() {
+    atom.extend_from_slice(s);
+    parser.next();
   }
+
+  Ok(Item::new_atom(&atom))
 }
 
+// On entry the token is the opening quote, on exit the closing quote
 fn parse_quoted(parser: &mut Parser<'_>) -> Parse<Item> {
-  throw!("todo")
+  let guard = match parser.token {
+    Some(Quote(s)) => &s[..s.len() - 1],
+    _ => throw!("not a quote"),
+  };
+
+  // a guard like `#(date)` is the tag of the atom
+  let tag = match guard.strip_prefix(b"#(") {
+    Some(tag) => match tag.strip_suffix(b")") {
+      Some(tag) if crate::atom::is_tag(tag) => Some(tag.to_vec()),
+      _ => throw!("bad tag: {}", guard.pretty()),
+    },
+    None => None,
+  };
+
+  let mut atom = Vec::new();
+  loop {
+    parser.next();
+    match parser.token {
+      Some(Quoted(s)) => atom.extend_from_slice(s),
+      Some(Esc(s)) => unescape(s, guard, &mut atom)?,
+      Some(Bad(b"\"")) | None => throw!("unterminated string"),
+      Some(Bad(s)) => match split_guard(s, guard) {
+        Some(_) => throw!("bad escape: {}", s.pretty()),
+        None => atom.extend_from_slice(s),
+      },
+      Some(Quote(_)) => return Ok(Item::Atom(Atom(atom, tag))),
+      Some(token) => unreachable!("{token:?}"),
+    }
+  }
+}
+
+// Split an escape token into text before the guard and the escape after the
+// guard. Returns None if the guard does not match, then it's just text.
+fn split_guard<'b>(
+  esc: &'b [u8],
+  guard: &[u8],
+) -> Option<(&'b [u8], &'b [u8])> {
+  let pos = esc.iter().position(|&b| b == b'\\')?;
+  let (text, esc) = (&esc[..pos], &esc[pos..]);
+  let text = text.strip_suffix(guard)?;
+  Some((text, esc))
+}
+
+fn unescape(esc: &[u8], guard: &[u8], atom: &mut Vec<u8>) -> Parse<()> {
+  let Some((text, esc)) = split_guard(esc, guard) else {
+    atom.extend_from_slice(esc);
+    return Ok(());
+  };
+  atom.extend_from_slice(text);
+
+  let hex = |hex: &[u8]| {
+    let hex = std::str::from_utf8(hex).ok()?;
+    u32::from_str_radix(hex, 16).ok()
+  };
+
+  match esc {
+    b"\\\"" => atom.push(b'"'),
+    b"\\e" => atom.push(0x1b),
+    b"\\n" => atom.push(b'\n'),
+    b"\\r" => atom.push(b'\r'),
+    b"\\t" => atom.push(b'\t'),
+    b"\\0" => atom.push(b'\0'),
+    [b'\\', b'x', h @ ..] => match hex(h) {
+      Some(byte) => atom.push(byte as u8),
+      None => throw!("bad escape: {}", esc.pretty()),
+    },
+    [b'\\', b'u', b'{', h @ .., b'}'] => {
+      match hex(h).and_then(char::from_u32) {
+        Some(c) => {
+          atom.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+        }
+        None => throw!("bad unicode escape: {}", esc.pretty()),
+      }
+    }
+    // line continuation: `\` white space `\` is skipped
+    [b'\\', .., b'\\'] => (),
+    _ => throw!("bad escape: {}", esc.pretty()),
+  }
+
+  Ok(())
 }
 
 #[cfg(test)]
 mod tests {
   use super::parse;
-  use crate::Item;
+  use crate::{Atom, Item};
 
   #[test]
   fn run() {
@@ -222,6 +384,152 @@ mod tests {
       ]))
     );
   }
+
+  #[test]
+  fn parse_nested() {
+    let a = || Item::new_atom(b"a");
+    assert_eq!(
+      parse(b"k: (a) l: (x: a)"),
+      Ok(Item::new_map([
+        (Item::new_atom(b"k"), Item::new_list([a()])),
+        (Item::new_atom(b"l"), Item::new_map([(Item::new_atom(b"x"), a())])),
+      ]))
+    );
+  }
+
+  #[test]
+  fn parse_long_bare() {
+    let long = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    assert_eq!(parse(long), Ok(Item::new_list([Item::new_atom(long)])));
+  }
+
+  #[test]
+  fn spans() {
+    let input = "# comment\na: (x \"y z\")\n(k l): (m: n)\nb: ((c: ä) d)";
+    let (item, spans) = super::parse_spanned(input.as_bytes()).unwrap();
+    assert_eq!(Ok(item), parse(input.as_bytes()));
+
+    let spans = spans
+      .iter()
+      .map(|(path, span)| {
+        let text = &input.as_bytes()[span.start..span.end];
+        format!("{path} {span} {}", String::from_utf8_lossy(text))
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      spans,
+      [
+        "a 2:4 (x \"y z\")",
+        "a/0 2:5 x",
+        "a/1 2:7 \"y z\"",
+        "b 4:4 ((c: ä) d)",
+        "b/0 4:5 (c: ä)",
+        "b/0/c 4:9 ä",
+        "b/1 4:12 d",
+        "(k l) 3:8 (m: n)",
+        "(k l)/m 3:12 n",
+      ]
+    );
+  }
+
+  #[test]
+  fn parse_strings() {
+    let atom = |s: &[u8]| Ok(Item::new_list([Item::new_atom(s)]));
+    assert_eq!(parse(br#""""#), atom(b""));
+    assert_eq!(parse(br#""a b: (c)""#), atom(b"a b: (c)"));
+    assert_eq!(parse(br#""\"\e\n\r\t\0""#), atom(b"\"\x1b\n\r\t\0"));
+    assert_eq!(parse(br#""\x5c\xff\u{e4}""#), atom(b"\\\xff\xc3\xa4"));
+    assert_eq!(parse(b"\"a\\ \n  \\b\""), atom(b"ab"));
+    assert_eq!(parse(br#""a#""#), atom(b"a#"));
+    assert_eq!(parse(br##""#\n""##), atom(b"#\n"));
+    assert_eq!(
+      parse(br#""the quick brown fox jumps over the lazy dog""#),
+      atom(b"the quick brown fox jumps over the lazy dog")
+    );
+    assert_eq!(
+      parse(br#""a" b"#),
+      Ok(Item::new_list([Item::new_atom(b"a"), Item::new_atom(b"b")]))
+    );
+
+    let tagged = |tag: &[u8], s: &[u8]| {
+      Ok(Item::new_list([Item::Atom(Atom::tagged(tag, s).unwrap())]))
+    };
+    assert_eq!(
+      parse(br##"#(date)"2023-10-14#(date)""##),
+      tagged(b"date", b"2023-10-14")
+    );
+    // in a guarded string only guarded escapes are escapes
+    assert_eq!(
+      parse(br##"#(b64)"a"\n#(b64)\n##(b64)""##),
+      tagged(b"b64", b"a\"\\n\n#")
+    );
+    assert!(parse(br##"#(date)"2023-10-14""##).is_err());
+    assert!(parse(br##"#()"x#()""##).is_err());
+
+    assert!(parse(br#""open"#).is_err());
+    assert!(parse(br#""\q""#).is_err());
+    assert!(parse(br#""\u{d800}""#).is_err());
+    // input the lexer has no token for
+    let err = |input: &[u8]| parse(input).unwrap_err().to_string();
+    assert!(err(b"port: 80 host: #(").starts_with("ParseError: bad: #"));
+    assert!(err(br#""\xZZ""#).starts_with("ParseError: bad"));
+    assert!(err(br#"("\u{zz}")"#).starts_with("ParseError: bad"));
+  }
+
+  #[test]
+  fn nested_map_values() {
+    let atom = |s: &[u8]| Item::new_atom(s);
+    assert_eq!(
+      parse(b"a: (b: (c: d)) e: ((f))"),
+      Ok(Item::new_map([
+        (
+          atom(b"a"),
+          Item::new_map([(
+            atom(b"b"),
+            Item::new_map([(atom(b"c"), atom(b"d"))])
+          )])
+        ),
+        (atom(b"e"), Item::new_list([Item::new_list([atom(b"f")])])),
+      ]))
+    );
+    assert_eq!(
+      parse(br#"a: "x" b: ("y")"#),
+      Ok(Item::new_map([
+        (atom(b"a"), atom(b"x")),
+        (atom(b"b"), Item::new_list([atom(b"y")])),
+      ]))
+    );
+  }
+
+  #[test]
+  fn split_guard() {
+    use super::split_guard;
+    assert_eq!(split_guard(b"\\n", b""), Some((&b""[..], &b"\\n"[..])));
+    assert_eq!(split_guard(b"#\\n", b""), Some((&b"#"[..], &b"\\n"[..])));
+    assert_eq!(split_guard(b"#\\n", b"#"), Some((&b""[..], &b"\\n"[..])));
+    assert_eq!(split_guard(b"\\n", b"#"), None);
+    assert_eq!(split_guard(b"#", b""), None);
+  }
+
+  #[test]
+  fn unescape() {
+    let unescape = |esc: &[u8], guard: &[u8]| {
+      let mut atom = b"<".to_vec();
+      super::unescape(esc, guard, &mut atom).map(|()| atom)
+    };
+    assert_eq!(unescape(b"\\n", b""), Ok(b"<\n".to_vec()));
+    assert_eq!(unescape(b"\\x41", b""), Ok(b"<A".to_vec()));
+    assert_eq!(unescape(b"\\u{1F4A9}", b""), Ok("<💩".into()));
+    assert_eq!(unescape(b"\\ \n \\", b""), Ok(b"<".to_vec()));
+    assert_eq!(unescape(b"#\\t", b""), Ok(b"<#\t".to_vec()));
+
+    // with a guard only guarded escapes are escapes, the rest is text
+    assert_eq!(unescape(b"#\\t", b"#"), Ok(b"<\t".to_vec()));
+    assert_eq!(unescape(b"\\t", b"#"), Ok(b"<\\t".to_vec()));
+
+    assert!(unescape(b"\\u{110000}", b"").is_err());
+    assert!(unescape(b"\\q", b"").is_err());
+  }
 }
 
 // Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
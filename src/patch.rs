@@ -0,0 +1,415 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::path::index;
+use crate::{canonical, Atom, Item, Path};
+
+/// A patch operation, the axp equivalent of a JSON Patch operation.
+///
+/// As axp document an operation is a map like `op: replace path: server/port
+/// value: 8080`. The keys are `op`, `path`, `from` for `move` and `copy`, and
+/// `value` for `add`, `replace` and `test`.
+///
+/// `add` sets an entry of a map, replacing an existing one, or inserts an
+/// item into a list, the index `-` appends. `test` fails unless the item at
+/// the path equals the value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Op {
+  Add { path: Path, value: Item },
+  Remove { path: Path },
+  Replace { path: Path, value: Item },
+  Move { from: Path, path: Path },
+  Copy { from: Path, path: Path },
+  Test { path: Path, value: Item },
+}
+
+impl Op {
+  fn from_item(item: &Item) -> Result<Op, String> {
+    let Item::Map(map) = item else { Err("op is not a map")? };
+
+    let get = |key: &str| map.get(&Item::new_atom(key.as_bytes()));
+    let value = || get("value").cloned().ok_or("missing value");
+    let path = |key: &str| match get(key) {
+      Some(Item::Atom(atom)) => std::str::from_utf8(atom.as_bytes())
+        .map_err(|_| format!("bad {key}"))?
+        .parse::<Path>()
+        .map_err(|err| format!("bad {key}: {err}")),
+      Some(_) => Err(format!("bad {key}")),
+      None => Err(format!("missing {key}")),
+    };
+
+    let op = match get("op") {
+      Some(Item::Atom(Atom(op, None))) => Some(op.as_slice()),
+      Some(_) => Err("bad op")?,
+      None => None,
+    };
+
+    Ok(match op {
+      Some(b"add") => Op::Add { path: path("path")?, value: value()? },
+      Some(b"remove") => Op::Remove { path: path("path")? },
+      Some(b"replace") => Op::Replace { path: path("path")?, value: value()? },
+      Some(b"move") => Op::Move { from: path("from")?, path: path("path")? },
+      Some(b"copy") => Op::Copy { from: path("from")?, path: path("path")? },
+      Some(b"test") => Op::Test { path: path("path")?, value: value()? },
+      Some(_) => Err("unknown op")?,
+      None => Err("missing op")?,
+    })
+  }
+
+  pub fn to_item(&self) -> Item {
+    let atom = |s: &str| Item::new_atom(s.as_bytes());
+    let path = |path: &Path| atom(&path.to_string());
+    let entries = match self {
+      Op::Add { path: p, value } => {
+        vec![("add", "path", path(p)), ("", "value", value.clone())]
+      }
+      Op::Remove { path: p } => vec![("remove", "path", path(p))],
+      Op::Replace { path: p, value } => {
+        vec![("replace", "path", path(p)), ("", "value", value.clone())]
+      }
+      Op::Move { from, path: p } => {
+        vec![("move", "from", path(from)), ("", "path", path(p))]
+      }
+      Op::Copy { from, path: p } => {
+        vec![("copy", "from", path(from)), ("", "path", path(p))]
+      }
+      Op::Test { path: p, value } => {
+        vec![("test", "path", path(p)), ("", "value", value.clone())]
+      }
+    };
+
+    let op = (atom("op"), atom(entries[0].0));
+    let entries = entries.into_iter().map(|(_, k, v)| (atom(k), v));
+    Item::new_map(std::iter::once(op).chain(entries))
+  }
+}
+
+impl fmt::Display for Op {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&String::from_utf8_lossy(&canonical(&self.to_item())))
+  }
+}
+
+/// A patch, either a sequence of operations or a merge patch.
+///
+/// A merge patch is the axp equivalent of a JSON Merge Patch: a map overlays
+/// the target recursively, and an entry with the value `()` deletes the entry
+/// of the target. Everything else replaces the target. So a merge patch
+/// can't set a value to the empty list.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Patch {
+  Ops(Vec<Op>),
+  Merge(Item),
+}
+
+impl Patch {
+  /// Read a patch document with operations: a list of maps, or a single map
+  /// for a single operation.
+  ///
+  /// ```
+  /// # use axp::{apply_patch, parse, Patch};
+  /// let patch = parse(b"op: replace path: server/port value: 8080").unwrap();
+  /// let patch = Patch::from_item(&patch).unwrap();
+  /// let mut doc = parse(b"server: (host: localhost port: 80)").unwrap();
+  /// apply_patch(&mut doc, &patch).unwrap();
+  /// assert_eq!(doc, parse(b"server: (host: localhost port: 8080)").unwrap());
+  /// ```
+  pub fn from_item(item: &Item) -> Result<Patch, PatchError> {
+    let op = |(index, item): (usize, &Item)| {
+      Op::from_item(item).map_err(|reason| PatchError {
+        index,
+        op: String::from_utf8_lossy(&canonical(item)).into_owned(),
+        reason,
+      })
+    };
+
+    match item {
+      Item::Map(_) => Ok(Patch::Ops(vec![op((0, item))?])),
+      Item::List(list) => Ok(Patch::Ops(
+        list.iter().enumerate().map(op).collect::<Result<_, _>>()?,
+      )),
+      Item::Atom(_) => Err(PatchError {
+        index: 0,
+        op: String::from_utf8_lossy(&canonical(item)).into_owned(),
+        reason: "not a patch".to_owned(),
+      }),
+    }
+  }
+
+  pub fn to_item(&self) -> Item {
+    match self {
+      Patch::Ops(ops) => Item::new_list(ops.iter().map(Op::to_item)),
+      Patch::Merge(item) => item.clone(),
+    }
+  }
+}
+
+/// A failed patch operation
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatchError {
+  index: usize,
+  op: String,
+  reason: String,
+}
+
+impl Error for PatchError {}
+
+impl PatchError {
+  /// The index of the failing operation
+  pub fn index(&self) -> usize {
+    self.index
+  }
+
+  /// The failing operation as text
+  pub fn op(&self) -> &str {
+    &self.op
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for PatchError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let PatchError { index, op, reason } = self;
+    write!(f, "PatchError: op {index} `{op}`: {reason}")
+  }
+}
+
+/// Apply a patch to an item.
+///
+/// Either the whole patch is applied or, if an operation fails, the item is
+/// left unchanged and the error names the failing operation.
+///
+/// ```
+/// # use axp::{apply_patch, parse, Patch};
+/// let mut doc = parse(b"server: (port: 80 debug: true)").unwrap();
+/// let patch = parse(b"server: (port: 8080 debug: ())").unwrap();
+/// apply_patch(&mut doc, &Patch::Merge(patch)).unwrap();
+/// assert_eq!(doc, parse(b"server: (port: 8080)").unwrap());
+///
+/// let patch = parse(b"(op: remove path: server/port) (op: remove path: x)");
+/// let patch = Patch::from_item(&patch.unwrap()).unwrap();
+/// let err = apply_patch(&mut doc, &patch).unwrap_err();
+/// assert_eq!(err.to_string(), "PatchError: op 1 `op: remove path: x`: no such key");
+/// assert_eq!(doc, parse(b"server: (port: 8080)").unwrap());
+/// ```
+pub fn apply_patch(target: &mut Item, patch: &Patch) -> Result<(), PatchError> {
+  let mut result = target.clone();
+
+  match patch {
+    Patch::Ops(ops) => {
+      for (index, op) in ops.iter().enumerate() {
+        apply_op(&mut result, op).map_err(|reason| PatchError {
+          index,
+          op: op.to_string(),
+          reason,
+        })?;
+      }
+    }
+    Patch::Merge(patch) => merge_patch(&mut result, patch),
+  }
+
+  *target = result;
+  Ok(())
+}
+
+fn apply_op(target: &mut Item, op: &Op) -> Result<(), String> {
+  match op {
+    Op::Add { path, value } => add(target, path, value.clone()),
+    Op::Remove { path } => remove(target, path).map(drop),
+    Op::Replace { path, value } => {
+      *target.get_mut(path).ok_or("no such item")? = value.clone();
+      Ok(())
+    }
+    Op::Move { from, path } => {
+      if path.starts_with(from) && path != from {
+        Err("can't move an item into itself")?
+      }
+      let value = remove(target, from)?;
+      add(target, path, value)
+    }
+    Op::Copy { from, path } => {
+      let value = target.get(from).ok_or("no such item")?.clone();
+      add(target, path, value)
+    }
+    Op::Test { path, value } => match target.get(path) {
+      Some(item) if item == value => Ok(()),
+      Some(item) => Err(format!("test failed, found {item}")),
+      None => Err("no such item".to_owned()),
+    },
+  }
+}
+
+fn add(target: &mut Item, path: &Path, value: Item) -> Result<(), String> {
+  let Some((parent, last)) = path.split_last() else {
+    *target = value;
+    return Ok(());
+  };
+
+  match target.get_mut(&parent).ok_or("no such parent")? {
+    Item::Map(map) => {
+      map.set(last.clone(), value);
+    }
+    Item::List(list) if *last == Item::new_atom(b"-") => {
+      list.push(value);
+    }
+    Item::List(list) => match index(last) {
+      Some(i) if i <= list.len() => list.0.insert(i, value),
+      Some(_) => Err("index out of range")?,
+      None => Err("bad index")?,
+    },
+    Item::Atom(_) => Err("parent is an atom")?,
+  }
+  Ok(())
+}
+
+fn remove(target: &mut Item, path: &Path) -> Result<Item, String> {
+  let (parent, last) = path.split_last().ok_or("can't remove the root")?;
+
+  match target.get_mut(&parent).ok_or("no such parent")? {
+    Item::Map(map) => Ok(map.remove(last).ok_or("no such key")?),
+    Item::List(list) => match index(last) {
+      Some(i) if i < list.len() => Ok(list.0.remove(i)),
+      Some(_) => Err("index out of range")?,
+      None => Err("bad index")?,
+    },
+    Item::Atom(_) => Err("parent is an atom")?,
+  }
+}
+
+fn merge_patch(target: &mut Item, patch: &Item) {
+  let Item::Map(patch) = patch else {
+    *target = patch.clone();
+    return;
+  };
+
+  if !target.is_map() {
+    *target = Item::new_map([]);
+  }
+  let Item::Map(map) = target else { unreachable!("not a map") };
+
+  for (key, value) in patch.iter() {
+    if *value == Item::nil() {
+      while map.remove(key).is_some() {}
+    } else if let Some(old) = map.get_mut(key) {
+      merge_patch(old, value);
+    } else {
+      let mut new = Item::nil();
+      merge_patch(&mut new, value);
+      map.push(key.clone(), new);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{apply_patch, Patch};
+  use crate::{canonical, parse, Item};
+
+  fn doc(s: &str) -> Item {
+    parse(s.as_bytes()).unwrap()
+  }
+
+  fn patch(target: &str, patch: &str) -> Result<String, String> {
+    let mut target = doc(target);
+    let patch = Patch::from_item(&doc(patch)).map_err(|e| e.to_string())?;
+    apply_patch(&mut target, &patch).map_err(|e| e.to_string())?;
+    Ok(String::from_utf8(canonical(&target)).unwrap())
+  }
+
+  fn merge(target: &str, patch: &str) -> String {
+    let mut target = doc(target);
+    apply_patch(&mut target, &Patch::Merge(doc(patch))).unwrap();
+    String::from_utf8(canonical(&target)).unwrap()
+  }
+
+  #[test]
+  fn ops() {
+    let ok = |s: &str| Ok(s.to_owned());
+    assert_eq!(patch("a: 1", "op: add path: b value: 2"), ok("a: 1 b: 2"));
+    assert_eq!(patch("a: 1", "op: add path: a value: 2"), ok("a: 2"));
+    assert_eq!(
+      patch("l: (x z)", "op: add path: l/1 value: y"),
+      ok("l: (x y z)")
+    );
+    assert_eq!(patch("l: (x)", "op: add path: l/- value: y"), ok("l: (x y)"));
+    assert_eq!(patch("a: 1 b: 2", "op: remove path: a"), ok("b: 2"));
+    assert_eq!(patch("l: (x y)", "op: remove path: l/0"), ok("l: (y)"));
+    assert_eq!(
+      patch("a: (b: 1)", "op: replace path: a/b value: (2 3)"),
+      ok("a: (b: (2 3))")
+    );
+    assert_eq!(patch("a: 1 b: 2", "op: move from: a path: c"), ok("b: 2 c: 1"));
+    assert_eq!(patch("a: 1", "op: copy from: a path: b"), ok("a: 1 b: 1"));
+    assert_eq!(patch("a: 1", "op: test path: a value: 1"), ok("a: 1"));
+    assert_eq!(patch("a: 1", "op: replace path: \"\" value: x"), ok("x"));
+    assert_eq!(
+      patch("a: 1", "(op: test path: a value: 1) (op: add path: b value: 2)"),
+      ok("a: 1 b: 2")
+    );
+  }
+
+  #[test]
+  fn errors() {
+    let err = |s: &str| Err(s.to_owned());
+    assert_eq!(
+      patch("a: 1", "(op: add path: b value: 2) (op: test path: a value: 2)"),
+      err("PatchError: op 1 `op: test path: a value: 2`: test failed, found 1")
+    );
+    assert_eq!(
+      patch("l: (x)", "op: add path: l/2 value: y"),
+      err("PatchError: op 0 `op: add path: l/2 value: y`: index out of range")
+    );
+    assert_eq!(
+      patch("a: 1", "op: frobnicate path: a"),
+      err("PatchError: op 0 `op: frobnicate path: a`: unknown op")
+    );
+    assert_eq!(
+      patch("a: 1", "op: (add) path: b value: 2"),
+      err("PatchError: op 0 `op: (add) path: b value: 2`: bad op")
+    );
+    assert_eq!(
+      patch("a: 1", "op: #(x)\"add#(x)\" path: b value: 2"),
+      err("PatchError: op 0 `op: #(x)\"add#(x)\" path: b value: 2`: bad op")
+    );
+    assert_eq!(
+      patch("a: 1", "(op: remove path: a) (op: add)"),
+      err("PatchError: op 1 `op: add`: missing path")
+    );
+    assert_eq!(
+      patch("a: (b: 1)", "op: move from: a path: a/b/c"),
+      err("PatchError: op 0 `op: move from: a path: a/b/c`: can't move an item into itself")
+    );
+  }
+
+  #[test]
+  fn atomic() {
+    let mut target = doc("a: 1");
+    let ops = doc("(op: add path: b value: 2) (op: remove path: c)");
+    let result = apply_patch(&mut target, &Patch::from_item(&ops).unwrap());
+    assert_eq!(result.unwrap_err().index(), 1);
+    assert_eq!(target, doc("a: 1"));
+  }
+
+  #[test]
+  fn merge_patches() {
+    assert_eq!(merge("a: 1 b: 2", "b: 3 c: 4"), "a: 1 b: 3 c: 4");
+    assert_eq!(merge("a: (x: 1 y: 2)", "a: (y: ())"), "a: (x: 1)");
+    assert_eq!(merge("a: 1", "b: (c: 1 d: ())"), "a: 1 b: (c: 1)");
+    assert_eq!(merge("a: (1 2)", "a: (3)"), "a: (3)");
+    assert_eq!(merge("a b", "k: v"), "k: v");
+    assert_eq!(merge("k: v", "a b"), "a b");
+  }
+
+  #[test]
+  fn roundtrip() {
+    let ops =
+      doc("(op: move from: a path: b) (op: add path: l/- value: (x: 1))");
+    let patch = Patch::from_item(&ops).unwrap();
+    assert_eq!(patch.to_item(), ops);
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,211 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::canonical::{is_bare, write_item, write_quoted};
+use crate::parse::ParseError;
+use crate::pretty::PrettyUtf8;
+use crate::{parse, Atom, Item};
+
+/// A path addresses an item inside of another item.
+///
+/// Each step is a key for maps or an index for lists. Since axp is untyped an
+/// index is just an atom with decimal digits like `0` or `42`.
+///
+/// The text form separates the steps with `/`, for example `server/port` or
+/// `members/0/name`. Steps are written like items in axp, so keys with special
+/// characters are quoted, and lists and maps in parentheses.
+///
+/// ```
+/// # use axp::{parse, Path};
+/// let doc = parse(b"server: (host: localhost port: 8080)").unwrap();
+/// let path: Path = "server/port".parse().unwrap();
+/// assert_eq!(format!("{}", doc.get(&path).unwrap()), "8080");
+/// ```
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Path(Vec<Item>);
+
+impl Path {
+  pub fn new<I: IntoIterator<Item = Item>>(steps: I) -> Self {
+    Path(steps.into_iter().collect())
+  }
+
+  pub fn root() -> Self {
+    Path(Vec::new())
+  }
+
+  pub fn is_root(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  pub fn steps(&self) -> &[Item] {
+    &self.0
+  }
+
+  pub fn push(&mut self, step: Item) -> &mut Self {
+    self.0.push(step);
+    self
+  }
+
+  /// A new path with one more step
+  pub fn join(&self, step: Item) -> Path {
+    let mut path = self.clone();
+    path.0.push(step);
+    path
+  }
+
+  /// A new path with a list index as last step
+  pub fn join_index(&self, index: usize) -> Path {
+    self.join(Item::new_atom(index.to_string().as_bytes()))
+  }
+
+  /// The path without the last step and the last step, None for the root
+  pub fn split_last(&self) -> Option<(Path, &Item)> {
+    let (last, parent) = self.0.split_last()?;
+    Some((Path(parent.to_vec()), last))
+  }
+
+  pub fn starts_with(&self, other: &Path) -> bool {
+    self.0.starts_with(&other.0)
+  }
+}
+
+impl fmt::Display for Path {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut out = Vec::new();
+    for (i, step) in self.0.iter().enumerate() {
+      if i > 0 {
+        out.push(b'/');
+      }
+      match step {
+        Item::Atom(Atom(atom, None))
+          if is_bare(atom) && !atom.contains(&b'/') =>
+        {
+          out.extend_from_slice(atom)
+        }
+        Item::Atom(atom) => write_quoted(atom, &mut out),
+        item => write_item(item, &mut out),
+      }
+    }
+    f.write_str(&String::from_utf8_lossy(&out))
+  }
+}
+
+impl FromStr for Path {
+  type Err = ParseError;
+
+  /// Parse a path, a leading `/` is optional
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.strip_prefix('/').unwrap_or(s);
+    if s.is_empty() {
+      return Ok(Path::root());
+    }
+
+    let mut path = Path::root();
+    for step in split_steps(s.as_bytes()) {
+      match parse(step)? {
+        Item::List(list) if list.0.len() == 1 => path.push(list.first()),
+        _ => {
+          Err(ParseError::new(format!("bad path step `{}`", step.pretty())))?
+        }
+      };
+    }
+    Ok(path)
+  }
+}
+
+// Split at slashes which are not quoted or in parentheses
+fn split_steps(s: &[u8]) -> Vec<&[u8]> {
+  let mut steps = Vec::new();
+  let (mut start, mut depth, mut quoted, mut escaped) = (0, 0, false, false);
+  for (i, &byte) in s.iter().enumerate() {
+    match byte {
+      _ if escaped => escaped = false,
+      b'\\' if quoted => escaped = true,
+      b'"' => quoted = !quoted,
+      b'(' if !quoted => depth += 1,
+      b')' if !quoted => depth -= 1,
+      b'/' if !quoted && depth == 0 => {
+        steps.push(&s[start..i]);
+        start = i + 1;
+      }
+      _ => (),
+    }
+  }
+  steps.push(&s[start..]);
+  steps
+}
+
+/// Interpret a step as a list index
+pub(crate) fn index(step: &Item) -> Option<usize> {
+  match step {
+    Item::Atom(atom) if atom.0.iter().all(u8::is_ascii_digit) => {
+      std::str::from_utf8(&atom.0).ok()?.parse().ok()
+    }
+    _ => None,
+  }
+}
+
+impl Item {
+  /// Get the item at a path, None if there is no such item
+  pub fn get(&self, path: &Path) -> Option<&Item> {
+    let mut item = self;
+    for step in path.steps() {
+      item = match item {
+        Item::Atom(_) => return None,
+        Item::List(list) => list.0.get(index(step)?)?,
+        Item::Map(map) => map.get(step)?,
+      };
+    }
+    Some(item)
+  }
+
+  /// Get the item at a path mutably, None if there is no such item
+  pub fn get_mut(&mut self, path: &Path) -> Option<&mut Item> {
+    let mut item = self;
+    for step in path.steps() {
+      item = match item {
+        Item::Atom(_) => return None,
+        Item::List(list) => list.0.get_mut(index(step)?)?,
+        Item::Map(map) => map.get_mut(step)?,
+      };
+    }
+    Some(item)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Path;
+  use crate::{parse, Item};
+
+  fn path(s: &str) -> Path {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn text_form() {
+    for s in ["", "a", "a/b", "a/0/b", r#""a/b"/c"#, "(a b)/c", r#""""#] {
+      assert_eq!(format!("{}", path(s)), s);
+    }
+    assert_eq!(path("/a/b"), path("a/b"));
+    assert_eq!(path(r#""x y"/z"#).steps()[0], Item::new_atom(b"x y"));
+    assert!("a//b".parse::<Path>().is_err());
+    let err = "a/b c/d".parse::<Path>().unwrap_err();
+    assert!(err.to_string().ends_with("bad path step `b c`"), "{err}");
+  }
+
+  #[test]
+  fn get() {
+    let doc = parse(b"a: (x: 1 l: (p q)) (k l): v").unwrap();
+    let get = |s| doc.get(&path(s)).map(|i| format!("{i}"));
+    assert_eq!(get("a/x").as_deref(), Some("1"));
+    assert_eq!(get("a/l/1").as_deref(), Some("q"));
+    assert_eq!(get("(k l)").as_deref(), Some("v"));
+    assert_eq!(get("").as_deref(), Some("(a: (x: 1 l: (p q)) (k l): v)"));
+    assert_eq!(get("a/l/2"), None);
+    assert_eq!(get("a/x/y"), None);
+    assert_eq!(get("b"), None);
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
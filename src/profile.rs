@@ -0,0 +1,202 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+
+use crate::merge::merge_maps;
+use crate::{Item, Path};
+
+const PROFILE: &[u8] = b"@profile";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProfileError {
+  path: Path,
+  reason: String,
+}
+
+impl Error for ProfileError {}
+
+impl ProfileError {
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for ProfileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "ProfileError: {}: {}", self.path, self.reason)
+  }
+}
+
+/// Apply the sections of the active profiles.
+///
+/// A map entry with the key `(@profile name ...)` is a section. If one of
+/// the names is active the section is merged deeply over the map, else it is
+/// dropped. Sections can be nested.
+///
+/// Returns the effective document and the active profiles which the document
+/// does not know, probably typos.
+///
+/// ```
+/// # use axp::{apply_profiles, parse};
+/// let item = parse(b"
+///   replicas: 1
+///   db: (host: localhost)
+///   (@profile prod): (replicas: 5 db: (host: db.internal))
+///   (@profile dev test): (debug: true)
+/// ").unwrap();
+/// let (effective, unknown) = apply_profiles(&item, &["prod", "prd"]).unwrap();
+/// let expected = parse(b"replicas: 5 db: (host: db.internal)").unwrap();
+/// assert_eq!(effective, expected);
+/// assert_eq!(unknown, ["prd"]);
+/// ```
+pub fn apply_profiles(
+  item: &Item,
+  active: &[&str],
+) -> Result<(Item, Vec<String>), ProfileError> {
+  let mut profiles = Profiles { active, known: BTreeSet::new() };
+  let item = profiles.item(&Path::root(), item)?;
+  let unknown = active.iter().filter(|name| !profiles.known.contains(**name));
+  Ok((item, unknown.map(|name| name.to_string()).collect()))
+}
+
+struct Profiles<'a> {
+  active: &'a [&'a str],
+  // the profile names in the document
+  known: BTreeSet<String>,
+}
+
+impl<'a> Profiles<'a> {
+  fn item(&mut self, path: &Path, item: &Item) -> Result<Item, ProfileError> {
+    match item {
+      Item::Atom(_) => Ok(item.clone()),
+      Item::List(list) => {
+        let items = list.iter().enumerate();
+        let items = items.map(|(i, item)| self.item(&path.join_index(i), item));
+        items.collect::<Result<Vec<_>, _>>().map(Item::new_list)
+      }
+      Item::Map(map) => {
+        let (mut entries, mut sections, mut paths) =
+          (Vec::new(), Vec::new(), Vec::new());
+        for (key, value) in map.iter() {
+          let path = path.join(key.clone());
+          match self.section(&path, key)? {
+            Some(true) => {
+              sections.push(self.item(&path, value)?);
+              paths.push(path);
+            }
+            Some(false) => self.collect(value),
+            None => entries.push((key.clone(), self.item(&path, value)?)),
+          }
+        }
+
+        if !entries.is_empty() {
+          sections.insert(0, Item::new_map(entries));
+        }
+        let offset = sections.len() - paths.len();
+        merge_maps(&sections).map_err(|index| ProfileError {
+          path: paths[index - offset].clone(),
+          reason: "profile section is not a map".to_string(),
+        })
+      }
+    }
+  }
+
+  // Collect the profile names of an inactive section
+  fn collect(&mut self, item: &Item) {
+    match item {
+      Item::Atom(_) => (),
+      Item::List(list) => list.iter().for_each(|item| self.collect(item)),
+      Item::Map(map) => {
+        for (key, value) in map.iter() {
+          _ = self.section(&Path::root(), key);
+          self.collect(value);
+        }
+      }
+    }
+  }
+
+  // Whether a key is a section and if so whether it is active
+  fn section(
+    &mut self,
+    path: &Path,
+    key: &Item,
+  ) -> Result<Option<bool>, ProfileError> {
+    let Item::List(list) = key else { return Ok(None) };
+    if list.get(0) != Some(&Item::new_atom(PROFILE)) {
+      return Ok(None);
+    }
+
+    let mut active = false;
+    for name in list.iter().skip(1) {
+      let name = match name {
+        Item::Atom(name) => std::str::from_utf8(name.as_bytes()).ok(),
+        _ => None,
+      };
+      let Some(name) = name else {
+        let reason = "profile names must be text".to_string();
+        return Err(ProfileError { path: path.clone(), reason });
+      };
+      active |= self.active.contains(&name);
+      self.known.insert(name.to_string());
+    }
+    Ok(Some(active))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::apply_profiles;
+  use crate::{canonical, parse};
+
+  fn apply(input: &str, active: &[&str]) -> (String, Vec<String>) {
+    let item = parse(input.as_bytes()).unwrap();
+    let (item, unknown) = apply_profiles(&item, active).unwrap();
+    (String::from_utf8(canonical(&item)).unwrap(), unknown)
+  }
+
+  #[test]
+  fn sections() {
+    let input = "a: 1
+      (@profile x): (a: 2 b: (c: 1))
+      (@profile y): (b: (d: 2))
+      l: (((@profile x): (e: 3) f: 4))";
+    assert_eq!(apply(input, &[]).0, "a: 1 l: ((f: 4))");
+    assert_eq!(apply(input, &["x"]).0, "a: 2 l: ((f: 4 e: 3)) b: (c: 1)");
+    assert_eq!(
+      apply(input, &["y", "x"]).0,
+      "a: 2 l: ((f: 4 e: 3)) b: (c: 1 d: 2)"
+    );
+    assert_eq!(apply(input, &["z", "y"]).1, ["z"]);
+
+    // nested and only a section
+    let input = "(@profile x): (a: 1 (@profile y): (a: 2))";
+    assert_eq!(apply(input, &["x"]).0, "a: 1");
+    assert_eq!(apply(input, &["x", "y"]).0, "a: 2");
+    assert_eq!(apply(input, &["y"]), (String::new(), vec![]));
+
+    // an empty section changes nothing
+    let input = "a: 1 (@profile x): () (@profile y): (b: 2)";
+    assert_eq!(apply(input, &["x"]).0, "a: 1");
+    assert_eq!(apply(input, &["y", "x"]).0, "a: 1 b: 2");
+  }
+
+  #[test]
+  fn errors() {
+    let item = parse(b"a: ((@profile x): 1)").unwrap();
+    let err = apply_profiles(&item, &["x"]).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "ProfileError: a/(@profile x): profile section is not a map"
+    );
+
+    let item = parse(b"(@profile (x)): ()").unwrap();
+    let err = apply_profiles(&item, &[]).unwrap_err();
+    assert_eq!(err.reason(), "profile names must be text");
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
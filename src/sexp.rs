@@ -0,0 +1,578 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::{Atom, Item, Path, Span};
+
+const DOT: &[u8] = b".";
+const STRING: &[u8] = b"string";
+
+// Expressions nested deeper are an error, as in the default BinaryLimits
+const MAX_DEPTH: usize = 128;
+
+// The reader abbreviations and their forms
+const QUOTES: [(&str, &str); 5] = [
+  ("'", "quote"),
+  ("`", "quasiquote"),
+  (",@", "unquote-splicing"),
+  (",", "unquote"),
+  ("#'", "function"),
+];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SexpError {
+  /// The input is not a valid S-expression
+  Read { span: Span, reason: String },
+  /// The item at the path has no S-expression form
+  Write { path: Path, reason: String },
+}
+
+impl Error for SexpError {}
+
+impl SexpError {
+  pub fn reason(&self) -> &str {
+    match self {
+      SexpError::Read { reason, .. } => reason,
+      SexpError::Write { reason, .. } => reason,
+    }
+  }
+}
+
+impl fmt::Display for SexpError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SexpError::Read { span, reason } => {
+        write!(f, "SexpError: {span}: {reason}")
+      }
+      SexpError::Write { path, reason } if path.is_root() => {
+        write!(f, "SexpError: {reason}")
+      }
+      SexpError::Write { path, reason } => {
+        write!(f, "SexpError: {path}: {reason}")
+      }
+    }
+  }
+}
+
+/// Read S-expressions as in Scheme or Common Lisp, one item per top level
+/// expression.
+///
+/// - Lists and vectors `#(...)` become lists
+/// - Symbols and numbers become atoms of their text, `|a b|` is a symbol
+///   with a space
+/// - Strings become atoms tagged `string`, so that they stay apart from
+///   symbols
+/// - `'x`, `` `x ``, `,x`, `,@x` and `#'x` become `(quote x)`,
+///   `(quasiquote x)`, `(unquote x)`, `(unquote-splicing x)` and
+///   `(function x)`
+/// - A dotted pair `(a . b)` becomes the list `(a #(dot)".#(dot)" b)`, the
+///   tag keeps it apart from the symbol `|.|`, a list after the dot is
+///   spliced: `(a . (b))` is `(a b)`
+/// - Comments `;`, `#| |#` and `#;` are dropped
+/// - Expressions nested deeper than 128 are an error
+///
+/// ```
+/// # use axp::{from_sexp, parse, Item};
+/// let items = from_sexp(r#"(define (f x) '(x . "y")) ; comment"#).unwrap();
+/// let expected = parse(
+///   br##"(define (f x) (quote (x #(dot)".#(dot)" #(string)"y#(string)")))"##,
+/// );
+/// let expected = expected.unwrap();
+/// assert_eq!(Item::new_list(items), expected);
+/// ```
+pub fn from_sexp(input: &str) -> Result<Vec<Item>, SexpError> {
+  let mut reader = Reader {
+    input: input.as_bytes(),
+    pos: 0,
+    lines: Span::lines(input.as_bytes()),
+    depth: 0,
+  };
+  let mut items = Vec::new();
+  while reader.skip()? {
+    items.push(reader.expression()?);
+  }
+  Ok(items)
+}
+
+fn is_delimiter(byte: u8) -> bool {
+  byte.is_ascii_whitespace() || b"()[]\";'`,".contains(&byte)
+}
+
+struct Reader<'a> {
+  input: &'a [u8],
+  pos: usize,
+  lines: Vec<usize>,
+  depth: usize,
+}
+
+// The marker of a dotted pair
+fn dot_atom() -> Item {
+  Item::Atom(Atom(DOT.to_vec(), Some(b"dot".to_vec())))
+}
+
+impl<'a> Reader<'a> {
+  fn error(&self, start: usize, reason: impl Into<String>) -> SexpError {
+    let span = Span::new(self.input, &self.lines, start, self.pos);
+    SexpError::Read { span, reason: reason.into() }
+  }
+
+  // Read one level deeper
+  fn nested<T>(
+    &mut self,
+    read: impl FnOnce(&mut Self) -> Result<T, SexpError>,
+  ) -> Result<T, SexpError> {
+    if self.depth == MAX_DEPTH {
+      let reason = format!("nested deeper than {MAX_DEPTH}");
+      return Err(self.error(self.pos, reason));
+    }
+    self.depth += 1;
+    let result = read(self);
+    self.depth -= 1;
+    result
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.input.get(self.pos).copied()
+  }
+
+  fn rest(&self) -> &'a [u8] {
+    &self.input[self.pos..]
+  }
+
+  // Skip white space and comments, false at the end of the input
+  fn skip(&mut self) -> Result<bool, SexpError> {
+    loop {
+      let start = self.pos;
+      match self.peek() {
+        None => return Ok(false),
+        Some(byte) if byte.is_ascii_whitespace() => self.pos += 1,
+        Some(b';') => {
+          while self.peek().is_some_and(|byte| byte != b'\n') {
+            self.pos += 1;
+          }
+        }
+        Some(b'#') if self.rest().starts_with(b"#|") => {
+          // block comments nest
+          let mut depth = 0;
+          loop {
+            match self.rest() {
+              [] => return Err(self.error(start, "#| not closed")),
+              [b'#', b'|', ..] => {
+                depth += 1;
+                self.pos += 2;
+              }
+              [b'|', b'#', ..] => {
+                depth -= 1;
+                self.pos += 2;
+                if depth == 0 {
+                  break;
+                }
+              }
+              _ => self.pos += 1,
+            }
+          }
+        }
+        Some(b'#') if self.rest().starts_with(b"#;") => {
+          self.pos += 2;
+          self.nested(|reader| {
+            if reader.skip()? {
+              reader.expression()
+            } else {
+              Err(reader.error(start, "nothing to comment out"))
+            }
+          })?;
+        }
+        Some(_) => return Ok(true),
+      }
+    }
+  }
+
+  fn token(&mut self) -> &'a str {
+    let start = self.pos;
+    while self.peek().is_some_and(|byte| !is_delimiter(byte)) {
+      self.pos += 1;
+    }
+    // the input is a str and delimiters are ascii
+    std::str::from_utf8(&self.input[start..self.pos]).unwrap()
+  }
+
+  fn expression(&mut self) -> Result<Item, SexpError> {
+    self.nested(Self::read_expression)
+  }
+
+  fn read_expression(&mut self) -> Result<Item, SexpError> {
+    let start = self.pos;
+    let quote = QUOTES.iter().find(|(abbreviation, _)| {
+      self.rest().starts_with(abbreviation.as_bytes())
+    });
+    if let Some((abbreviation, form)) = quote {
+      self.pos += abbreviation.len();
+      if !self.skip()? {
+        return Err(
+          self.error(start, format!("{abbreviation} needs an expression")),
+        );
+      }
+      let quoted = self.expression()?;
+      return Ok(Item::new_list([Item::new_atom(form.as_bytes()), quoted]));
+    }
+
+    match self.peek() {
+      Some(b'(') => self.list(b')'),
+      Some(b'[') => self.list(b']'),
+      Some(b'#') if self.rest().starts_with(b"#(") => {
+        self.pos += 1;
+        self.list(b')')
+      }
+      Some(b'"') => self.string(),
+      Some(b'|') => {
+        self.pos += 1;
+        let end = self.rest().iter().position(|&byte| byte == b'|');
+        let Some(end) = end else {
+          self.pos = self.input.len();
+          return Err(self.error(start, "| not closed"));
+        };
+        let symbol = Item::new_atom(&self.rest()[..end]);
+        self.pos += end + 1;
+        Ok(symbol)
+      }
+      Some(b'#') if self.rest().starts_with(b"#\\") => {
+        // the character after `#\` can be a delimiter
+        let rest = std::str::from_utf8(&self.rest()[2..]).unwrap();
+        let Some(first) = rest.chars().next() else {
+          self.pos = self.input.len();
+          return Err(self.error(start, "character expected"));
+        };
+        self.pos += 2 + first.len_utf8();
+        self.token();
+        Ok(Item::new_atom(&self.input[start..self.pos]))
+      }
+      Some(byte @ (b')' | b']')) => {
+        self.pos += 1;
+        Err(self.error(start, format!("unexpected {}", byte as char)))
+      }
+      _ => match self.token() {
+        "." => Err(self.error(start, "unexpected .")),
+        token => Ok(Item::new_atom(token.as_bytes())),
+      },
+    }
+  }
+
+  // The expressions up to the closing delimiter, with a dotted tail
+  fn list(&mut self, close: u8) -> Result<Item, SexpError> {
+    let start = self.pos;
+    self.pos += 1;
+    let mut items = Vec::new();
+    loop {
+      if !self.skip()? {
+        let reason = format!("{} not closed", self.input[start] as char);
+        return Err(self.error(start, reason));
+      }
+      if self.peek() == Some(close) {
+        self.pos += 1;
+        return Ok(Item::new_list(items));
+      }
+
+      let dot = self.pos;
+      if self.rest().starts_with(DOT) && self.token() == "." {
+        let tail = match self.skip()? {
+          true if !items.is_empty() && self.peek() != Some(close) => {
+            self.expression()?
+          }
+          _ => return Err(self.error(dot, "unexpected .")),
+        };
+        if !self.skip()? || self.peek() != Some(close) {
+          return Err(self.error(dot, "one expression after . expected"));
+        }
+        self.pos += 1;
+        match tail {
+          Item::List(list) => items.extend(list.0),
+          tail => items.extend([dot_atom(), tail]),
+        }
+        return Ok(Item::new_list(items));
+      }
+      self.pos = dot;
+      items.push(self.expression()?);
+    }
+  }
+
+  fn string(&mut self) -> Result<Item, SexpError> {
+    let start = self.pos;
+    self.pos += 1;
+    let mut out = Vec::new();
+    loop {
+      let byte = match self.rest() {
+        [] => return Err(self.error(start, "string not closed")),
+        [b'"', ..] => {
+          self.pos += 1;
+          return Ok(Item::Atom(Atom(out, Some(STRING.to_vec()))));
+        }
+        [b'\\', b'n', ..] => b'\n',
+        [b'\\', b't', ..] => b'\t',
+        [b'\\', b'r', ..] => b'\r',
+        [b'\\', byte @ (b'"' | b'\\'), ..] => *byte,
+        [b'\\', b'x', ..] => {
+          // `\x41;` as in R7RS
+          let escape = self.rest().iter().position(|&byte| byte == b';');
+          let char = escape
+            .and_then(|end| std::str::from_utf8(&self.rest()[2..end]).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32);
+          match (escape, char) {
+            (Some(end), Some(char)) => {
+              out.extend_from_slice(char.to_string().as_bytes());
+              self.pos += end + 1;
+              continue;
+            }
+            _ => return Err(self.error(self.pos, "bad \\x escape")),
+          }
+        }
+        [b'\\', ..] => return Err(self.error(self.pos, "unknown escape")),
+        [byte, ..] => {
+          out.push(*byte);
+          self.pos += 1;
+          continue;
+        }
+      };
+      out.push(byte);
+      self.pos += 2;
+    }
+  }
+}
+
+// A character like `#\a` or `#\space`, read back the same
+fn is_char(text: &str) -> bool {
+  let mut chars = text.strip_prefix("#\\").unwrap_or("").chars();
+  chars.next().is_some() && chars.as_str().bytes().all(|b| !is_delimiter(b))
+}
+
+// An atom which reads back the same as a symbol or number
+fn is_symbol(text: &str) -> bool {
+  let special = |char: char| {
+    char.is_whitespace() || char.is_control() || "()[]\";'`,|\\".contains(char)
+  };
+  !text.is_empty()
+    && text != "."
+    && !text.contains(special)
+    && (!text.starts_with('#')
+      || matches!(text, "#t" | "#f" | "#true" | "#false"))
+}
+
+/// Write an item as an S-expression.
+///
+/// Lists are written as lists, a list `(a #(dot)".#(dot)" b)` as the dotted
+/// pair `(a . b)`. Maps are written as association lists `((key . value) ...)`.
+/// Atoms tagged `string` are written as strings. Untagged atoms are written
+/// bare if they are symbols, numbers or characters, else as symbols like
+/// `|a b|`. Other tags, untagged atoms with `|` and atoms which are not UTF-8
+/// are errors.
+///
+/// ```
+/// # use axp::{parse, to_sexp};
+/// let item = parse(
+///   br##"(define x (quote (#(string)"a b#(string)" #(dot)".#(dot)" c))) (k: "v w")"##,
+/// );
+/// let item = item.unwrap();
+/// assert_eq!(
+///   to_sexp(&item).unwrap(),
+///   r#"((define x (quote ("a b" . c))) ((k . |v w|)))"#
+/// );
+/// ```
+pub fn to_sexp(item: &Item) -> Result<String, SexpError> {
+  let mut out = String::new();
+  write_expression(&Path::root(), item, &mut out)?;
+  Ok(out)
+}
+
+fn write_expression(
+  path: &Path,
+  item: &Item,
+  out: &mut String,
+) -> Result<(), SexpError> {
+  let error = |reason: &str| {
+    Err(SexpError::Write { path: path.clone(), reason: reason.to_string() })
+  };
+  match item {
+    Item::Atom(atom) => {
+      let Ok(text) = std::str::from_utf8(atom.as_bytes()) else {
+        return error("atom is not UTF-8");
+      };
+      match atom.tag() {
+        Some(STRING) => write_string(text, out),
+        Some(_) => return error("tagged atoms have no S-expression form"),
+        None if is_symbol(text) || is_char(text) => out.push_str(text),
+        None if text.contains('|') => {
+          return error("symbols with | have no S-expression form");
+        }
+        None => out.push_str(&format!("|{text}|")),
+      }
+    }
+    Item::List(list) => {
+      let dotted = list.len() > 2 && list.0[list.len() - 2] == dot_atom();
+      out.push('(');
+      for (i, item) in list.iter().enumerate() {
+        if i > 0 {
+          out.push(' ');
+        }
+        if dotted && i == list.len() - 2 {
+          out.push('.');
+        } else {
+          write_expression(&path.join_index(i), item, out)?;
+        }
+      }
+      out.push(')');
+    }
+    Item::Map(map) => {
+      out.push('(');
+      for (i, (key, value)) in map.iter().enumerate() {
+        if i > 0 {
+          out.push(' ');
+        }
+        let path = path.join(key.clone());
+        out.push('(');
+        write_expression(&path, key, out)?;
+        out.push_str(" . ");
+        write_expression(&path, value, out)?;
+        out.push(')');
+      }
+      out.push(')');
+    }
+  }
+  Ok(())
+}
+
+fn write_string(text: &str, out: &mut String) {
+  out.push('"');
+  for char in text.chars() {
+    match char {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\t' => out.push_str("\\t"),
+      '\r' => out.push_str("\\r"),
+      char if char.is_control() => {
+        out.push_str(&format!("\\x{:x};", char as u32))
+      }
+      char => out.push(char),
+    }
+  }
+  out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_sexp, to_sexp};
+  use crate::{canonical, parse, Atom, Item};
+
+  fn read(input: &str) -> Result<String, String> {
+    let items = from_sexp(input).map_err(|err| err.to_string())?;
+    let item = Item::new_list(items);
+    Ok(String::from_utf8(canonical(&item)).unwrap())
+  }
+
+  fn write(input: &str) -> Result<String, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    to_sexp(&item).map_err(|err| err.to_string())
+  }
+
+  #[test]
+  fn reads() {
+    assert_eq!(
+      read(
+        "(a . b) (a b . (c . (d))) (a . ()) [x #(1 2)] #t |a b| #\\( #\\space"
+      )
+      .unwrap(),
+      r##"(a #(dot)".#(dot)" b) (a b c d) (a) (x (1 2)) "#t" "a b" "#\x5c(" "##
+        .to_string()
+        + r##""#\x5cspace""##
+    );
+    assert_eq!(read("(a |.| b)").unwrap(), "(a . b)");
+    assert_eq!(
+      read("'a `(b ,c ,@d) #'f").unwrap(),
+      "(quote a) (quasiquote (b (unquote c) (unquote-splicing d))) (function f)"
+    );
+    assert_eq!(
+      read(
+        "x ; comment\n #| block #| nested |# |# #;(dropped) \"a\\\"\\x41;\\n\""
+      )
+      .unwrap(),
+      r##"x #(string)"a#(string)\"A#(string)\n#(string)""##
+    );
+    // strings stay apart from symbols
+    assert_eq!(
+      read(r#"("define" define)"#).unwrap(),
+      r##"(#(string)"define#(string)" define)"##
+    );
+    assert_eq!(read("").unwrap(), "");
+  }
+
+  #[test]
+  fn read_errors() {
+    let err = |input: &str| read(input).unwrap_err();
+    assert_eq!(err("(a"), "SexpError: 1:1: ( not closed");
+    assert_eq!(err("\n a)"), "SexpError: 2:3: unexpected )");
+    assert_eq!(err("(. a)"), "SexpError: 1:2: unexpected .");
+    assert_eq!(
+      err("(a . b c)"),
+      "SexpError: 1:4: one expression after . expected"
+    );
+    assert_eq!(err("(a .)"), "SexpError: 1:4: unexpected .");
+    assert_eq!(err("."), "SexpError: 1:1: unexpected .");
+    assert_eq!(err("\"\\q\""), "SexpError: 1:2: unknown escape");
+    assert_eq!(err("\"\\xzz;\""), "SexpError: 1:2: bad \\x escape");
+    assert_eq!(err("#| a"), "SexpError: 1:1: #| not closed");
+    assert_eq!(err("'"), "SexpError: 1:1: ' needs an expression");
+
+    let deep = "(".repeat(128) + &")".repeat(128);
+    assert!(read(&deep).is_ok());
+    assert_eq!(
+      err(&format!("({deep})")),
+      "SexpError: 1:129: nested deeper than 128"
+    );
+    assert!(err(&"'".repeat(200)).ends_with("nested deeper than 128"));
+    assert!(err(&"#;".repeat(200)).ends_with("nested deeper than 128"));
+  }
+
+  #[test]
+  fn writes() {
+    assert_eq!(
+      write(r##"a "a b" "" "#t" "#x" "." "(" ("." a) (a "." b c) (1 "." 2)"##)
+        .unwrap(),
+      r##"(a |a b| || #t |#x| |.| |(| (|.| a) (a |.| b c) (1 |.| 2))"##
+    );
+    assert_eq!(
+      write(r##"#(string)"a b#(string)" #(string)"x#(string)" "#\x5c(""##)
+        .unwrap(),
+      r##"("a b" "x" #\()"##
+    );
+    assert_eq!(write(r##"(1 #(dot)".#(dot)" 2)"##).unwrap(), "((1 . 2))");
+    assert_eq!(write("k: (1 2) l: m").unwrap(), "((k . (1 2)) (l . m))");
+    let string = Atom::tagged(b"string", b"\\\"\t\x01").unwrap();
+    let item = Item::new_list([Item::new_atom(b"a"), Item::Atom(string)]);
+    assert_eq!(to_sexp(&item).unwrap(), r#"(a "\\\"\t\x1;")"#);
+
+    let input = r#"(define (f . args) '(1 "a b" |c d| "e" e . #t)) (x . y)"#;
+    let items = Item::new_list(from_sexp(input).unwrap());
+    let output = to_sexp(&items).unwrap();
+    assert_eq!(
+      output,
+      r#"((define (f . args) (quote (1 "a b" |c d| "e" e . #t))) (x . y))"#
+    );
+    assert_eq!(from_sexp(&output).unwrap(), vec![items]);
+  }
+
+  #[test]
+  fn write_errors() {
+    let item = Item::new_list([Item::Atom(Atom::tagged(b"t", b"x").unwrap())]);
+    assert_eq!(
+      to_sexp(&item).unwrap_err().to_string(),
+      "SexpError: 0: tagged atoms have no S-expression form"
+    );
+    let item = Item::new_map([(Item::new_atom(b"k"), Item::new_atom(b"\xff"))]);
+    assert_eq!(to_sexp(&item).unwrap_err().reason(), "atom is not UTF-8");
+    assert_eq!(
+      to_sexp(&Item::new_list([Item::new_atom(b"a|b")])).unwrap_err().reason(),
+      "symbols with | have no S-expression form"
+    );
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::Path;
+
+/// The position of an item in the input, see [`parse_spanned()`].
+///
+/// `start` and `end` are byte offsets, `line` and `col` count from one, `col`
+/// in characters.
+///
+/// [`parse_spanned()`]: crate::parse_spanned
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+  pub line: usize,
+  pub col: usize,
+}
+
+impl Span {
+  pub(crate) fn new(
+    input: &[u8],
+    lines: &[usize],
+    start: usize,
+    end: usize,
+  ) -> Span {
+    let line = lines.partition_point(|&line_start| line_start <= start);
+    let line_start = lines[line - 1];
+    let col =
+      String::from_utf8_lossy(&input[line_start..start]).chars().count();
+    Span { start, end, line, col: col + 1 }
+  }
+
+  // The offsets where lines start, the first line starts at zero
+  pub(crate) fn lines(input: &[u8]) -> Vec<usize> {
+    let ends = input.iter().enumerate().filter(|(_, &b)| b == b'\n');
+    std::iter::once(0).chain(ends.map(|(i, _)| i + 1)).collect()
+  }
+}
+
+impl fmt::Display for Span {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.line, self.col)
+  }
+}
+
+/// The spans of the items of a document by path
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Spans(pub(crate) BTreeMap<Path, Span>);
+
+impl Spans {
+  pub fn get(&self, path: &Path) -> Option<&Span> {
+    self.0.get(path)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&Path, &Span)> {
+    self.0.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,510 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+
+use crate::canonical::write_item;
+use crate::{parse, Item, Map, Path, PrettyUtf8};
+
+/// Options for [`to_csv()`] and [`from_csv()`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TableOptions {
+  /// The field delimiter, `,` for CSV and `\t` for TSV
+  pub delimiter: u8,
+  /// Read cells which start with `(`, `#(` or `"` as axp text, and write
+  /// atoms which start with them quoted
+  pub nested: bool,
+}
+
+impl Default for TableOptions {
+  fn default() -> Self {
+    TableOptions { delimiter: b',', nested: true }
+  }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TableError {
+  path: Path,
+  reason: String,
+}
+
+impl Error for TableError {}
+
+impl TableError {
+  /// The path of the row, the root for the table and the CSV syntax
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for TableError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.path.is_root() {
+      write!(f, "TableError: {}", self.reason)
+    } else {
+      write!(f, "TableError: {}: {}", self.path, self.reason)
+    }
+  }
+}
+
+fn error(path: &Path, reason: impl Into<String>) -> TableError {
+  TableError { path: path.clone(), reason: reason.into() }
+}
+
+// The union of the keys in the order they first occur and the rows, `None`
+// for an empty row
+type Columns<'i> = (Vec<&'i Item>, Vec<Option<&'i Map>>);
+
+fn columns(item: &Item) -> Result<Columns<'_>, TableError> {
+  let list = match item {
+    Item::List(list) => list,
+    item if item.is_empty() => return Ok((Vec::new(), Vec::new())),
+    _ => return Err(error(&Path::root(), "a table must be a list of maps")),
+  };
+
+  let (mut keys, mut seen, mut rows) = (Vec::new(), HashSet::new(), Vec::new());
+  for (i, row) in list.iter().enumerate() {
+    match row {
+      Item::Map(map) => {
+        for (key, _) in map.iter() {
+          if seen.insert(key) {
+            keys.push(key);
+          }
+        }
+        rows.push(Some(map));
+      }
+      row if row.is_empty() => rows.push(None),
+      _ => return Err(error(&Path::root().join_index(i), "row must be a map")),
+    }
+  }
+  Ok((keys, rows))
+}
+
+// Whether a cell is read as axp text
+fn is_nested(cell: &[u8]) -> bool {
+  cell.starts_with(b"(") || cell.starts_with(b"#(") || cell.starts_with(b"\"")
+}
+
+// Untagged atoms are their bytes, other items their axp text, and so are
+// atoms which would be read as axp text if nested
+fn cell(item: &Item, nested: bool) -> Vec<u8> {
+  match item {
+    Item::Atom(atom)
+      if atom.tag().is_none() && !(nested && is_nested(atom.as_bytes())) =>
+    {
+      atom.as_bytes().to_vec()
+    }
+    item => {
+      let mut out = Vec::new();
+      write_item(item, &mut out);
+      out
+    }
+  }
+}
+
+/// Write a list of maps as CSV, or TSV with [`TableOptions::delimiter`].
+///
+/// The header is the union of the keys in the order they first occur, a
+/// row without a key has an empty cell. Untagged atoms are written as they
+/// are, other keys and values inline as axp text, as are atoms which
+/// [`from_csv()`] would read as axp text with [`TableOptions::nested`].
+/// Cells with the delimiter, a quote or a line break are quoted, as is the
+/// only cell of a record when it is empty.
+///
+/// ```
+/// # use axp::{parse, to_csv, TableOptions};
+/// let item = parse(br#"
+///   (name: Sandman age: 53 powers: (Sandstorm "Magic carpet"))
+///   (name: "Molecule Man, Jr." age: 29)
+/// "#).unwrap();
+/// assert_eq!(
+///   to_csv(&item, &TableOptions::default()).unwrap(),
+///   b"name,age,powers\n\
+///     Sandman,53,\"(Sandstorm \"\"Magic carpet\"\")\"\n\
+///     \"Molecule Man, Jr.\",29,\n"
+/// );
+/// ```
+pub fn to_csv(
+  item: &Item,
+  options: &TableOptions,
+) -> Result<Vec<u8>, TableError> {
+  let (keys, rows) = columns(item)?;
+  let mut out = Vec::new();
+  if keys.is_empty() {
+    return Ok(out);
+  }
+
+  let record = |cells: &mut dyn Iterator<Item = Vec<u8>>, out: &mut Vec<u8>| {
+    let start = out.len();
+    for (i, cell) in cells.enumerate() {
+      if i > 0 {
+        out.push(options.delimiter);
+      }
+      write_field(&cell, options.delimiter, out);
+    }
+    // a lone empty field is quoted, a blank line would be skipped
+    if out.len() == start {
+      out.extend_from_slice(b"\"\"");
+    }
+    out.push(b'\n');
+  };
+
+  let cell = |item| cell(item, options.nested);
+  record(&mut keys.iter().map(|key| cell(key)), &mut out);
+  for row in rows {
+    let mut cells =
+      keys.iter().map(|key| match row.and_then(|row| row.get(key)) {
+        Some(value) => cell(value),
+        None => Vec::new(),
+      });
+    record(&mut cells, &mut out);
+  }
+  Ok(out)
+}
+
+fn write_field(field: &[u8], delimiter: u8, out: &mut Vec<u8>) {
+  let quote =
+    field.iter().any(|&b| matches!(b, b'"' | b'\r' | b'\n') || b == delimiter);
+  if !quote {
+    return out.extend_from_slice(field);
+  }
+  out.push(b'"');
+  for &byte in field {
+    if byte == b'"' {
+      out.push(b'"');
+    }
+    out.push(byte);
+  }
+  out.push(b'"');
+}
+
+/// Read CSV, or TSV with [`TableOptions::delimiter`], as a list of maps.
+///
+/// The first record is the header with the keys. Cells become atoms, with
+/// [`TableOptions::nested`] cells which start with `(`, `#(` or `"` and are
+/// one item of axp text become that item, also for the header. Empty cells
+/// are empty atoms and empty lines are skipped.
+///
+/// ```
+/// # use axp::{canonical, from_csv, TableOptions};
+/// let csv = b"name\tpowers\nSandman\t(Sandstorm \"Magic carpet\")\n";
+/// let options = TableOptions { delimiter: b'\t', ..Default::default() };
+/// let item = from_csv(csv, &options).unwrap();
+/// assert_eq!(
+///   canonical(&item),
+///   br#"(name: Sandman powers: (Sandstorm "Magic carpet"))"#
+/// );
+/// ```
+pub fn from_csv(
+  input: &[u8],
+  options: &TableOptions,
+) -> Result<Item, TableError> {
+  let mut records = records(input, options.delimiter)?.into_iter();
+  let Some(header) = records.next() else {
+    return Ok(Item::nil());
+  };
+
+  let keys = header.iter().map(|key| from_cell(key, options));
+  let keys = keys.collect::<Vec<_>>();
+  let mut seen = HashSet::new();
+  if let Some(key) = keys.iter().find(|key| !seen.insert(*key)) {
+    let key = key.format(0);
+    return Err(error(&Path::root(), format!("duplicate key {key}")));
+  }
+
+  let mut rows = Vec::new();
+  for (i, record) in records.enumerate() {
+    if record.len() != keys.len() {
+      let (len, keys) = (record.len(), keys.len());
+      let reason = format!("row has {len} cells, the header {keys}");
+      return Err(error(&Path::root().join_index(i), reason));
+    }
+    let cells = record.iter().map(|cell| from_cell(cell, options));
+    rows.push(Item::new_map(keys.iter().cloned().zip(cells)));
+  }
+  Ok(Item::new_list(rows))
+}
+
+fn from_cell(cell: &[u8], options: &TableOptions) -> Item {
+  if options.nested && is_nested(cell) {
+    if let Ok(Item::List(list)) = parse(cell) {
+      if list.len() == 1 {
+        return list.first();
+      }
+    }
+  }
+  Item::new_atom(cell)
+}
+
+type Record = Vec<Vec<u8>>;
+
+fn records(input: &[u8], delimiter: u8) -> Result<Vec<Record>, TableError> {
+  let (mut records, mut at, mut line) = (Vec::new(), 0, 1);
+  while at < input.len() {
+    match &input[at..] {
+      [b'\n', ..] => at += 1,
+      [b'\r', b'\n', ..] => at += 2,
+      _ => {
+        let mut record = Vec::new();
+        loop {
+          let (field, end) = read_field(input, at, delimiter, &mut line)?;
+          record.push(field);
+          at = end;
+          match &input[at..] {
+            [b, ..] if *b == delimiter => at += 1,
+            [b'\n', ..] => {
+              at += 1;
+              break;
+            }
+            [b'\r', b'\n', ..] => {
+              at += 2;
+              break;
+            }
+            [] => break,
+            _ => {
+              let reason = format!("line {line}: text after a quoted cell");
+              return Err(error(&Path::root(), reason));
+            }
+          }
+        }
+        records.push(record);
+      }
+    }
+    line += 1;
+  }
+  Ok(records)
+}
+
+// A field and the position after it
+fn read_field(
+  input: &[u8],
+  mut at: usize,
+  delimiter: u8,
+  line: &mut usize,
+) -> Result<(Vec<u8>, usize), TableError> {
+  let mut field = Vec::new();
+  if input.get(at) != Some(&b'"') {
+    while let Some(&byte) = input.get(at) {
+      if byte == delimiter || byte == b'\n' || input[at..].starts_with(b"\r\n")
+      {
+        break;
+      }
+      field.push(byte);
+      at += 1;
+    }
+    return Ok((field, at));
+  }
+
+  let start = *line;
+  at += 1;
+  loop {
+    match &input[at..] {
+      [b'"', b'"', ..] => {
+        field.push(b'"');
+        at += 2;
+      }
+      [b'"', ..] => return Ok((field, at + 1)),
+      [byte, ..] => {
+        *line += usize::from(*byte == b'\n');
+        field.push(*byte);
+        at += 1;
+      }
+      [] => {
+        let reason = format!("line {start}: unterminated quote");
+        return Err(error(&Path::root(), reason));
+      }
+    }
+  }
+}
+
+/// Render a list of maps as an aligned table for the terminal.
+///
+/// The columns are those of [`to_csv()`], cells are pretty printed with
+/// line breaks escaped.
+///
+/// ```
+/// # use axp::{parse, table};
+/// let item = parse(br#"
+///   (name: Sandman age: 53 powers: (Sandstorm "Magic carpet"))
+///   (name: "Molecule Man" age: 29)
+/// "#).unwrap();
+/// assert_eq!(
+///   table(&item).unwrap(),
+///   "\
+/// name          age  powers
+/// Sandman       53   (Sandstorm \"Magic carpet\")
+/// Molecule Man  29
+/// "
+/// );
+/// ```
+pub fn table(item: &Item) -> Result<String, TableError> {
+  let (keys, rows) = columns(item)?;
+  let text = |item: &Item| cell(item, false).pretty().replace('\n', "\\n");
+
+  let mut lines = vec![keys.iter().map(|key| text(key)).collect::<Vec<_>>()];
+  for row in rows {
+    let cells = keys.iter().map(|key| match row.and_then(|row| row.get(key)) {
+      Some(value) => text(value),
+      None => String::new(),
+    });
+    lines.push(cells.collect());
+  }
+
+  let mut widths = vec![0; keys.len()];
+  for line in &lines {
+    for (width, cell) in widths.iter_mut().zip(line) {
+      *width = (*width).max(cell.chars().count());
+    }
+  }
+
+  let mut out = String::new();
+  for line in lines.iter().filter(|_| !keys.is_empty()) {
+    let mut text = String::new();
+    for (cell, width) in line.iter().zip(&widths) {
+      text.push_str(&format!("{cell:width$}  "));
+    }
+    out.push_str(text.trim_end());
+    out.push('\n');
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_csv, table, to_csv, TableOptions};
+  use crate::{canonical, parse};
+
+  fn export(input: &str, options: &TableOptions) -> Result<String, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    let csv = to_csv(&item, options).map_err(|err| err.to_string())?;
+    Ok(String::from_utf8(csv).unwrap())
+  }
+
+  fn import(input: &str, options: &TableOptions) -> Result<String, String> {
+    let item = from_csv(input.as_bytes(), options);
+    let item = item.map_err(|err| err.to_string())?;
+    Ok(String::from_utf8(canonical(&item)).unwrap())
+  }
+
+  #[test]
+  fn exports() {
+    let options = TableOptions::default();
+    assert_eq!(
+      export(
+        r##"(a: 1 b: "x\"y") () (c: (d: e) a: #(t)"2#(t)" (k): "l\nm")"##,
+        &options
+      )
+      .unwrap(),
+      "a,b,c,(k)\n1,\"x\"\"y\",,\n,,,\n\"#(t)\"\"2#(t)\"\"\",,(d: e),\"l\nm\"\n"
+    );
+    let tsv = TableOptions { delimiter: b'\t', ..Default::default() };
+    assert_eq!(
+      export("(a: \"x,y\" b: \"x\ty\")", &tsv).unwrap(),
+      "a\tb\nx,y\t\"x\ty\"\n"
+    );
+    assert_eq!(export("", &options).unwrap(), "");
+  }
+
+  #[test]
+  fn imports() {
+    let options = TableOptions::default();
+    assert_eq!(
+      import(
+        "a,b,(c)\r\n1,\"x \"\"y\"\",z\",(d: e)\n\n,\"l\nm\",#(t)\"2#(t)\"\n",
+        &options
+      )
+      .unwrap(),
+      r##"(a: 1 b: "x \"y\",z" (c): (d: e)) (a: "" b: "l\nm" (c): #(t)"2#(t)")"##
+    );
+    let plain = TableOptions { nested: false, ..Default::default() };
+    assert_eq!(
+      import("a\n(b c)\n(d) e\n", &plain).unwrap(),
+      r#"(a: "(b c)") (a: "(d) e")"#
+    );
+    assert_eq!(
+      import("a\n(b c)\n(d) e\n", &options).unwrap(),
+      r#"(a: (b c)) (a: "(d) e")"#
+    );
+    // cells which don't parse stay atoms
+    let csv = "a,b,c\n#(,\"\"\"\\xZZ\"\"\",\"(\"\"\\u{zz}\"\")\"\n";
+    assert_eq!(
+      import(csv, &options).unwrap(),
+      r##"(a: "#(" b: "\"\x5cxZZ\"" c: "(\"\x5cu{zz}\")")"##
+    );
+    assert_eq!(
+      import("a,b,\n1,2,\n", &options).unwrap(),
+      r#"(a: 1 b: 2 "": "")"#
+    );
+    assert_eq!(import("", &options).unwrap(), "");
+
+    let roundtrip = r#"(name: Sandman age: 53 powers: (Sandstorm "Magic carpet"))
+      (name: "Molecule Man" age: 29 powers: ("Radiation resistance"))"#;
+    let item = parse(roundtrip.as_bytes()).unwrap();
+    let csv = to_csv(&item, &options).unwrap();
+    assert_eq!(from_csv(&csv, &options), Ok(item));
+
+    // atoms which look like axp text
+    let roundtrip = r##"("(a)": "(b c)" "#(t)": "\"d\"" e: "(")"##;
+    let item = parse(roundtrip.as_bytes()).unwrap();
+    let csv = to_csv(&item, &options).unwrap();
+    assert_eq!(
+      String::from_utf8(csv.clone()).unwrap(),
+      r##""""(a)""","""#(t)""",e"##.to_string()
+        + "\n"
+        + r##""""(b c)""","""\""d\""""","""(""""##
+        + "\n"
+    );
+    assert_eq!(from_csv(&csv, &options), Ok(item.clone()));
+    let plain = TableOptions { nested: false, ..Default::default() };
+    let csv = to_csv(&item, &plain).unwrap();
+    assert_eq!(from_csv(&csv, &plain), Ok(item));
+
+    // one column with empty cells
+    let item = parse(br#"(a: x) (a: "") (a: y)"#).unwrap();
+    let csv = to_csv(&item, &options).unwrap();
+    assert_eq!(csv, b"a\nx\n\"\"\ny\n");
+    assert_eq!(from_csv(&csv, &options), Ok(item.clone()));
+    let csv = to_csv(&parse(b"(a: x) () (a: y)").unwrap(), &options);
+    assert_eq!(from_csv(&csv.unwrap(), &options), Ok(item.clone()));
+    let item = parse(br#"("": x)"#).unwrap();
+    let csv = to_csv(&item, &options).unwrap();
+    assert_eq!(from_csv(&csv, &options), Ok(item));
+  }
+
+  #[test]
+  fn errors() {
+    let options = TableOptions::default();
+    let export = |input| export(input, &options).unwrap_err();
+    assert_eq!(export("a: 1"), "TableError: a table must be a list of maps");
+    assert_eq!(export("(a: 1) (b c)"), "TableError: 1: row must be a map");
+
+    let import = |input| import(input, &options).unwrap_err();
+    assert_eq!(
+      import("a,b\n1\n"),
+      "TableError: 0: row has 1 cells, the header 2"
+    );
+    assert_eq!(import("a,a\n"), "TableError: duplicate key a");
+    assert_eq!(
+      import("a\n\"x\ny\"z\n"),
+      "TableError: line 3: text after a quoted cell"
+    );
+    assert_eq!(import("a\n\n\"x\n"), "TableError: line 3: unterminated quote");
+  }
+
+  #[test]
+  fn render() {
+    let item =
+      parse("(a: 1 b: \"x\ny\") (a: 10 c: (d)) ()".as_bytes()).unwrap();
+    assert_eq!(
+      table(&item).unwrap(),
+      "a   b     c\n1   x\\ny\n10        (d)\n\n"
+    );
+    assert_eq!(table(&parse(b"").unwrap()).unwrap(), "");
+    assert!(table(&parse(b"a b").unwrap()).is_err());
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,252 @@
+use std::error::Error;
+use std::fmt;
+
+use toml::value::Datetime;
+use toml::{Table, Value};
+
+use crate::atom::float_text;
+use crate::{Atom, Item, Path};
+
+const DATETIME: &[u8] = b"datetime";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TomlError {
+  path: Path,
+  reason: String,
+}
+
+impl Error for TomlError {}
+
+impl TomlError {
+  /// The path of the item which can't be written, the root for parse errors
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for TomlError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.path.is_root() {
+      write!(f, "TomlError: {}", self.reason)
+    } else {
+      write!(f, "TomlError: {}: {}", self.path, self.reason)
+    }
+  }
+}
+
+fn error(path: &Path, reason: impl Into<String>) -> TomlError {
+  TomlError { path: path.clone(), reason: reason.into() }
+}
+
+/// Convert a TOML document to an item.
+///
+/// Tables become maps and arrays become lists. Strings, numbers and
+/// booleans become atoms of their text, datetimes become atoms tagged
+/// `datetime`. Comments are dropped, items have none.
+///
+/// ```
+/// # use axp::{canonical, from_toml};
+/// let item = from_toml("
+///   title = 'example'
+///   [owner]
+///   dob = 1979-05-27
+///   [[servers]]
+///   ports = [80, 443]
+/// ").unwrap();
+/// assert_eq!(
+///   canonical(&item),
+///   br##"title: example owner: (dob: #(datetime)"1979-05-27#(datetime)") servers: ((ports: (80 443)))"##
+/// );
+/// ```
+pub fn from_toml(input: &str) -> Result<Item, TomlError> {
+  let table = input.parse::<Table>();
+  let table = table.map_err(|err| error(&Path::root(), err.to_string()))?;
+  Ok(from_table(&table))
+}
+
+fn from_table(table: &Table) -> Item {
+  Item::new_map(
+    table
+      .iter()
+      .map(|(key, value)| (Item::new_atom(key.as_bytes()), from_value(value))),
+  )
+}
+
+fn from_value(value: &Value) -> Item {
+  match value {
+    Value::String(string) => Item::new_atom(string.as_bytes()),
+    Value::Integer(integer) => Item::new_atom(integer.to_string().as_bytes()),
+    Value::Float(float) => Item::new_atom(float_text(*float).as_bytes()),
+    Value::Boolean(bool) => Item::new_atom(bool.to_string().as_bytes()),
+    Value::Datetime(datetime) => {
+      let datetime = datetime.to_string();
+      Item::Atom(Atom(datetime.into_bytes(), Some(DATETIME.to_vec())))
+    }
+    Value::Array(array) => Item::new_list(array.iter().map(from_value)),
+    Value::Table(table) => from_table(table),
+  }
+}
+
+/// Write an item as a TOML document.
+///
+/// The item must be a map, its keys atoms. Atoms tagged `datetime` become
+/// datetimes. Other atoms which are written back the same become integers,
+/// floats and booleans, the rest become strings. Other tags, atoms which
+/// are not UTF-8, keys which are not atoms and duplicate keys are errors.
+///
+/// ```
+/// # use axp::{parse, to_toml};
+/// let item = parse(b"name: axp server: (port: 80 debug: false)").unwrap();
+/// let toml = to_toml(&item).unwrap();
+/// assert_eq!(toml, "name = \"axp\"\n\n[server]\nport = 80\ndebug = false\n");
+/// ```
+pub fn to_toml(item: &Item) -> Result<String, TomlError> {
+  let root = Path::root();
+  match item {
+    Item::Map(_) => match to_value(&root, item)? {
+      Value::Table(table) => Ok(table.to_string()),
+      _ => unreachable!("maps are tables"),
+    },
+    Item::List(list) if list.is_empty() => Ok(String::new()),
+    _ => Err(error(&root, "a TOML document must be a map")),
+  }
+}
+
+fn to_value(path: &Path, item: &Item) -> Result<Value, TomlError> {
+  match item {
+    Item::Atom(atom) => to_scalar(path, atom),
+    Item::List(list) => {
+      let items = list.iter().enumerate();
+      let items = items.map(|(i, item)| to_value(&path.join_index(i), item));
+      items.collect::<Result<_, _>>().map(Value::Array)
+    }
+    Item::Map(map) => {
+      let mut table = Table::new();
+      for (key, value) in map.iter() {
+        let path = path.join(key.clone());
+        let key = match key {
+          Item::Atom(key) if key.tag().is_none() => text(&path, key)?,
+          _ => return Err(error(&path, "key must be an atom")),
+        };
+        if table.insert(key.to_string(), to_value(&path, value)?).is_some() {
+          return Err(error(&path, format!("duplicate key {key}")));
+        }
+      }
+      Ok(Value::Table(table))
+    }
+  }
+}
+
+fn to_scalar(path: &Path, atom: &Atom) -> Result<Value, TomlError> {
+  let text = text(path, atom)?;
+  match atom.tag() {
+    Some(DATETIME) => match text.parse::<Datetime>() {
+      Ok(datetime) => Ok(Value::Datetime(datetime)),
+      Err(_) => Err(error(path, format!("{text} is not a datetime"))),
+    },
+    Some(tag) => {
+      let tag = String::from_utf8_lossy(tag);
+      Err(error(path, format!("tag {tag} has no TOML type")))
+    }
+    None => Ok(match text {
+      "true" => Value::Boolean(true),
+      "false" => Value::Boolean(false),
+      text => match (text.parse::<i64>(), text.parse::<f64>()) {
+        (Ok(integer), _) if integer.to_string() == text => {
+          Value::Integer(integer)
+        }
+        (_, Ok(float)) if float_text(float) == text => Value::Float(float),
+        _ => Value::String(text.to_string()),
+      },
+    }),
+  }
+}
+
+fn text<'a>(path: &Path, atom: &'a Atom) -> Result<&'a str, TomlError> {
+  std::str::from_utf8(atom.as_bytes())
+    .map_err(|_| error(path, "atom is not UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_toml, to_toml};
+  use crate::{canonical, parse, Atom, Item};
+
+  fn toml(input: &str) -> Result<String, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    to_toml(&item).map_err(|err| err.to_string())
+  }
+
+  #[test]
+  fn import() {
+    let item = from_toml(
+      "# comment
+      a = 1
+      b = 1.0
+      c = inf
+      d = \"x y\"
+      e = 1979-05-27
+      [t.u]
+      v = [true, { w = 2 }]
+      [[l]]
+      [[l]]
+      x = 3",
+    )
+    .unwrap();
+    assert_eq!(
+      String::from_utf8(canonical(&item)).unwrap(),
+      r##"a: 1 b: 1.0 c: inf d: "x y" e: #(datetime)"1979-05-27#(datetime)" "##
+        .to_string()
+        + "t: (u: (v: (true (w: 2)))) l: (() (x: 3))"
+    );
+
+    let err = from_toml("a = ").unwrap_err();
+    assert!(err.reason().contains("string values must be quoted"), "{err}");
+  }
+
+  #[test]
+  fn export() {
+    assert_eq!(
+      toml(r##"a: (1 1.5 1e3 01 x "") b: (c: nan d: #(datetime)"07:32:00#(datetime)")"##)
+        .unwrap(),
+      "a = [1, 1.5, \"1e3\", \"01\", \"x\", \"\"]\n\n[b]\nc = nan\nd = 07:32:00\n"
+    );
+    assert_eq!(toml("").unwrap(), "");
+
+    let roundtrip =
+      "a: 1 b: (c: #(datetime)\"1979-05-27T07:32:00Z#(datetime)\" d: (x y))";
+    let item = parse(roundtrip.as_bytes()).unwrap();
+    assert_eq!(from_toml(&to_toml(&item).unwrap()), Ok(item));
+  }
+
+  #[test]
+  fn errors() {
+    let err = |input| toml(input).unwrap_err();
+    assert_eq!(err("a b"), "TomlError: a TOML document must be a map");
+    assert_eq!(
+      to_toml(&Item::new_atom(b"")).unwrap_err().reason(),
+      "a TOML document must be a map"
+    );
+    assert_eq!(err("a: ((b): 1)"), "TomlError: a/(b): key must be an atom");
+    assert_eq!(err("a: (k: 1 k: 2)"), "TomlError: a/k: duplicate key k");
+    assert_eq!(
+      err(r##"a: (#(date)"x#(date)")"##),
+      "TomlError: a/0: tag date has no TOML type"
+    );
+    assert_eq!(
+      err(r##"a: #(datetime)"x#(datetime)""##),
+      "TomlError: a: x is not a datetime"
+    );
+    let item = Item::new_map([(Item::new_atom(b"a"), Item::new_atom(b"\xff"))]);
+    assert_eq!(to_toml(&item).unwrap_err().reason(), "atom is not UTF-8");
+    let tagged = Atom::tagged(b"datetime", b"1979-05-27").unwrap();
+    let item = Item::new_map([(Item::Atom(tagged), Item::new_atom(b"x"))]);
+    assert!(to_toml(&item).is_err());
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
@@ -0,0 +1,413 @@
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{diff, Config, ConfigError, ConfigLoader, Diff};
+
+type Validate = dyn Fn(&Config) -> Result<(), ConfigError> + Send + Sync;
+
+/// An update of a watched configuration, see [`Watch::subscribe()`]
+#[derive(Clone, Debug)]
+pub enum Event {
+  /// A new valid configuration and how it differs from the previous one
+  Updated { config: Box<Config>, diff: Diff },
+  /// The files could not be loaded or the configuration is not valid, the
+  /// previous one stays active
+  Failed(String),
+}
+
+/// Watches the files of a configuration and reloads it when they change.
+///
+/// The files are those of the [`ConfigLoader`], the ones they include and the
+/// ones added with [`watch()`](Self::watch). The included files are updated
+/// after each load. Changes are debounced, the configuration is reloaded and
+/// validated, and subscribers get only valid configurations which differ from
+/// the active one.
+///
+/// ```no_run
+/// # use axp::{ConfigError, ConfigLoader, ConfigWatcher, Event};
+/// let watch = ConfigWatcher::new(ConfigLoader::new("app"))
+///   .validate(|config| config.get::<u16>(&"port".parse().unwrap()).map(drop))
+///   .start()?;
+/// for event in watch.subscribe() {
+///   match event {
+///     Event::Updated { diff, .. } => print!("{}", diff.report(false)),
+///     Event::Failed(error) => eprintln!("{error}"),
+///   }
+/// }
+/// # Ok::<(), ConfigError>(())
+/// ```
+pub struct ConfigWatcher {
+  loader: ConfigLoader,
+  paths: Vec<PathBuf>,
+  debounce: Duration,
+  validate: Option<Arc<Validate>>,
+}
+
+impl ConfigWatcher {
+  pub fn new(loader: ConfigLoader) -> Self {
+    ConfigWatcher {
+      loader,
+      paths: Vec::new(),
+      debounce: Duration::from_millis(100),
+      validate: None,
+    }
+  }
+
+  /// Also reload if this file changes
+  pub fn watch(mut self, path: impl Into<PathBuf>) -> Self {
+    self.paths.push(path.into());
+    self
+  }
+
+  /// How long the files have to be quiet before reloading, 100ms by default
+  pub fn debounce(mut self, debounce: Duration) -> Self {
+    self.debounce = debounce;
+    self
+  }
+
+  /// Check a configuration before it becomes active
+  pub fn validate<F>(mut self, validate: F) -> Self
+  where
+    F: Fn(&Config) -> Result<(), ConfigError> + Send + Sync + 'static,
+  {
+    self.validate = Some(Arc::new(validate));
+    self
+  }
+
+  /// Load and validate the configuration and start watching its files
+  pub fn start(self) -> Result<Watch, ConfigError> {
+    let config = load(&self.loader, self.validate.as_deref())?;
+    let paths = self.files(&config)?;
+
+    let (tx, rx) = mpsc::channel();
+    let events = tx.clone();
+    let files = Arc::new(Mutex::new(Vec::new()));
+    let watched = files.clone();
+    let watcher = notify::recommended_watcher(
+      move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        let watched = watched.lock().unwrap();
+        let relevant = !matches!(event.kind, EventKind::Access(_))
+          && event.paths.iter().any(|path| watched.contains(path));
+        if relevant {
+          let _ = events.send(Message::Changed);
+        }
+      },
+    );
+    let first = paths.first().map_or(FsPath::new(""), |path| path);
+    let watcher = watcher.map_err(|err| watch_error(first, err))?;
+    let mut files = Files { watcher, files, dirs: Vec::new() };
+    files.sync(paths)?;
+
+    let reload = Arc::new(Reload {
+      current: Mutex::new(config),
+      subscribers: Mutex::new(Vec::new()),
+      files: Mutex::new(files),
+      watcher: self,
+    });
+
+    let thread = {
+      let reload = reload.clone();
+      thread::spawn(move || run(&reload, &rx))
+    };
+
+    Ok(Watch { reload, tx, thread: Some(thread) })
+  }
+
+  // The files to watch for a configuration, events have absolute paths
+  fn files(&self, config: &Config) -> Result<Vec<PathBuf>, ConfigError> {
+    let paths = self.loader.paths().into_iter().chain(self.paths.clone());
+    let mut files = Vec::new();
+    for path in paths.chain(config.files().iter().cloned()) {
+      let path = absolute(path)?;
+      if !files.contains(&path) {
+        files.push(path);
+      }
+    }
+    Ok(files)
+  }
+}
+
+/// A running [`ConfigWatcher`], stops watching when dropped
+pub struct Watch {
+  reload: Arc<Reload>,
+  tx: Sender<Message>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+  /// The active configuration
+  pub fn config(&self) -> Config {
+    self.reload.current.lock().unwrap().clone()
+  }
+
+  /// The events of all later reloads
+  pub fn subscribe(&self) -> Receiver<Event> {
+    let (tx, rx) = mpsc::channel();
+    self.reload.subscribers.lock().unwrap().push(tx);
+    rx
+  }
+
+  /// Reload now, as if the files changed, and return the event if any
+  pub fn reload(&self) -> Option<Event> {
+    self.reload.reload()
+  }
+}
+
+impl Drop for Watch {
+  fn drop(&mut self) {
+    let _ = self.tx.send(Message::Stop);
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+enum Message {
+  Changed,
+  Stop,
+}
+
+struct Reload {
+  current: Mutex<Config>,
+  subscribers: Mutex<Vec<Sender<Event>>>,
+  files: Mutex<Files>,
+  watcher: ConfigWatcher,
+}
+
+impl Reload {
+  fn reload(&self) -> Option<Event> {
+    // the lock is held so that concurrent reloads are ordered
+    let mut current = self.current.lock().unwrap();
+    let loaded = load(&self.watcher.loader, self.watcher.validate.as_deref())
+      .and_then(|config| {
+        let files = self.watcher.files(&config)?;
+        self.files.lock().unwrap().sync(files)?;
+        Ok(config)
+      });
+    let event = match loaded {
+      Ok(config) => {
+        let diff = diff(current.item(), config.item());
+        if diff.is_empty() {
+          return None;
+        }
+        *current = config.clone();
+        Event::Updated { config: Box::new(config), diff }
+      }
+      Err(err) => Event::Failed(err.to_string()),
+    };
+
+    let mut subscribers = self.subscribers.lock().unwrap();
+    subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    Some(event)
+  }
+}
+
+// The watched files, their directories are watched so that files can be
+// created and replaced
+struct Files {
+  watcher: RecommendedWatcher,
+  files: Arc<Mutex<Vec<PathBuf>>>,
+  dirs: Vec<PathBuf>,
+}
+
+impl Files {
+  fn sync(&mut self, files: Vec<PathBuf>) -> Result<(), ConfigError> {
+    let dirs = files.iter().filter_map(|path| path.parent());
+    let dirs = dirs.filter(|dir| dir.is_dir()).map(FsPath::to_path_buf);
+    let mut dirs = dirs.collect::<Vec<_>>();
+    dirs.sort();
+    dirs.dedup();
+
+    for dir in dirs.iter().filter(|dir| !self.dirs.contains(dir)) {
+      self
+        .watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|err| watch_error(dir, err))?;
+    }
+    for dir in self.dirs.iter().filter(|dir| !dirs.contains(dir)) {
+      let _ = self.watcher.unwatch(dir);
+    }
+    self.dirs = dirs;
+    *self.files.lock().unwrap() = files;
+    Ok(())
+  }
+}
+
+fn run(reload: &Reload, rx: &Receiver<Message>) {
+  while let Ok(Message::Changed) = rx.recv() {
+    loop {
+      match rx.recv_timeout(reload.watcher.debounce) {
+        Ok(Message::Changed) => continue,
+        Err(RecvTimeoutError::Timeout) => break,
+        Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+      }
+    }
+    reload.reload();
+  }
+}
+
+fn load(
+  loader: &ConfigLoader,
+  validate: Option<&Validate>,
+) -> Result<Config, ConfigError> {
+  let config = loader.load()?;
+  if let Some(validate) = validate {
+    validate(&config)?;
+  }
+  Ok(config)
+}
+
+// Events have absolute paths
+fn absolute(path: PathBuf) -> Result<PathBuf, ConfigError> {
+  std::path::absolute(&path).map_err(|error| ConfigError::Io { path, error })
+}
+
+fn watch_error(path: &FsPath, err: notify::Error) -> ConfigError {
+  ConfigError::Io { path: path.to_path_buf(), error: io::Error::other(err) }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::time::Duration;
+
+  use super::{ConfigWatcher, Event};
+  use crate::{ConfigError, ConfigLoader, Path};
+
+  // A directory which is removed when dropped, also if a test fails
+  struct TempDir(PathBuf);
+
+  impl std::ops::Deref for TempDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+      &self.0
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.0);
+    }
+  }
+
+  fn dir(name: &str) -> TempDir {
+    let dir = std::env::temp_dir()
+      .join(format!("axp-watch-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    TempDir(dir)
+  }
+
+  fn watcher(file: &std::path::Path) -> ConfigWatcher {
+    let loader = ConfigLoader::new("app").locations([file]).env([("A", "")]);
+    ConfigWatcher::new(loader).debounce(Duration::from_millis(20)).validate(
+      |config| config.get::<u16>(&"port".parse::<Path>().unwrap()).map(drop),
+    )
+  }
+
+  #[test]
+  fn reload() {
+    let dir = dir("reload");
+    let file = dir.join("app.axp");
+    std::fs::write(&file, "port: 80").unwrap();
+    let watch = watcher(&file).start().unwrap();
+    let events = watch.subscribe();
+
+    assert!(watch.reload().is_none());
+
+    std::fs::write(&file, "port: 8080 host: x").unwrap();
+    let Some(Event::Updated { config, diff }) = watch.reload() else {
+      panic!()
+    };
+    assert_eq!(diff.report(false), "~ port: 80 -> 8080\n+ host: x\n");
+    assert_eq!(config.item(), watch.config().item());
+
+    // invalid, the last good configuration stays
+    std::fs::write(&file, "port: http").unwrap();
+    let Some(Event::Failed(error)) = watch.reload() else { panic!() };
+    assert_eq!(
+      error,
+      format!("ConfigError: port from {}:1:7 must be a number", file.display())
+    );
+    std::fs::write(&file, "port: (").unwrap();
+    assert!(matches!(watch.reload(), Some(Event::Failed(_))));
+    assert_eq!(watch.config().item(), config.item());
+
+    assert!(matches!(events.try_recv(), Ok(Event::Updated { .. })));
+    assert!(matches!(events.try_recv(), Ok(Event::Failed(_))));
+    assert!(matches!(events.try_recv(), Ok(Event::Failed(_))));
+    assert!(events.try_recv().is_err());
+  }
+
+  #[test]
+  fn changes() {
+    let dir = dir("changes");
+    let file = dir.join("app.axp");
+    std::fs::write(&file, "port: 80").unwrap();
+    let watch = watcher(&file).start().unwrap();
+    let events = watch.subscribe();
+
+    std::fs::write(&file, "port: 81").unwrap();
+    let event = events.recv_timeout(Duration::from_secs(10)).unwrap();
+    let Event::Updated { diff, .. } = event else { panic!("{event:?}") };
+    assert_eq!(diff.report(false), "~ port: 80 -> 81\n");
+
+    // a half saved file fails and the watcher keeps going
+    for text in ["port: 82 host: #(", r#"port: 82 host: "\xZZ""#] {
+      std::fs::write(&file, text).unwrap();
+      let event = events.recv_timeout(Duration::from_secs(10)).unwrap();
+      let Event::Failed(error) = event else { panic!("{event:?}") };
+      assert!(error.contains(": ParseError: bad"), "{error}");
+    }
+    std::fs::write(&file, "port: 82").unwrap();
+    let event = events.recv_timeout(Duration::from_secs(10)).unwrap();
+    let Event::Updated { diff, .. } = event else { panic!("{event:?}") };
+    assert_eq!(diff.report(false), "~ port: 81 -> 82\n");
+  }
+
+  #[test]
+  fn includes() {
+    let dir = dir("includes");
+    let file = dir.join("app.axp");
+    for sub in ["a", "b"] {
+      std::fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    std::fs::write(&file, "port: 80 @include: a/a.axp").unwrap();
+    std::fs::write(dir.join("a/a.axp"), "a: 1").unwrap();
+    std::fs::write(dir.join("b/b.axp"), "b: 1").unwrap();
+    let watch = watcher(&file).start().unwrap();
+    let events = watch.subscribe();
+    let next = || events.recv_timeout(Duration::from_secs(10)).unwrap();
+
+    std::fs::write(dir.join("a/a.axp"), "a: 2").unwrap();
+    let Event::Updated { diff, .. } = next() else { panic!() };
+    assert_eq!(diff.report(false), "~ a: 1 -> 2\n");
+
+    // a new include is watched after the reload
+    std::fs::write(&file, "port: 80 @include: b/b.axp").unwrap();
+    let Event::Updated { diff, .. } = next() else { panic!() };
+    assert_eq!(diff.report(false), "- a: 2\n+ b: 1\n");
+    std::fs::write(dir.join("b/b.axp"), "b: 2").unwrap();
+    let Event::Updated { diff, .. } = next() else { panic!() };
+    assert_eq!(diff.report(false), "~ b: 1 -> 2\n");
+  }
+
+  #[test]
+  fn start() {
+    let dir = dir("start");
+    let file = dir.join("app.axp");
+    std::fs::write(&file, "port: x").unwrap();
+    let err = watcher(&file).start().err().unwrap();
+    assert!(matches!(err, ConfigError::Value { .. }), "{err}");
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
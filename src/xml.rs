@@ -0,0 +1,721 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::{Atom, Item, Path};
+
+const TAG: &[u8] = b"tag";
+const ATTRS: &[u8] = b"attrs";
+const CHILDREN: &[u8] = b"children";
+const TEXT: &[u8] = b"$";
+
+/// How elements are mapped to items
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum XmlStyle {
+  /// An element is a map `(tag: name attrs: (name: value) children: (..))`,
+  /// `attrs` and `children` only if there are any, and the children are
+  /// elements and text atoms
+  #[default]
+  Elements,
+  /// An element is a map entry `name: content`, the document a map with the
+  /// root element. The content is `()` if the element is empty, the text if
+  /// it only has text, or else a map with `@name: value` for attributes,
+  /// `name: content` for child elements and `$: text` for text
+  Compact,
+}
+
+/// Options for [`from_xml()`] and [`to_xml()`]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct XmlOptions {
+  pub style: XmlStyle,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XmlError {
+  path: Path,
+  reason: String,
+}
+
+impl Error for XmlError {}
+
+impl XmlError {
+  /// The path of the item which can't be written, the root for read errors
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for XmlError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.path.is_root() {
+      write!(f, "XmlError: {}", self.reason)
+    } else {
+      write!(f, "XmlError: {}: {}", self.path, self.reason)
+    }
+  }
+}
+
+fn error(path: &Path, reason: impl Into<String>) -> XmlError {
+  XmlError { path: path.clone(), reason: reason.into() }
+}
+
+/// Convert an XML document to an item, see [`read_xml()`].
+///
+/// ```
+/// # use axp::{canonical, from_xml, XmlOptions, XmlStyle};
+/// let xml = br#"<?xml version="1.0"?>
+/// <squad town="Metro City">
+///   <member age="53">Sandman</member>
+///   <member age="29">Molecule Man</member>
+/// </squad>"#;
+///
+/// let item = from_xml(xml, &XmlOptions::default()).unwrap();
+/// assert_eq!(
+///   canonical(&item),
+///   br#"tag: squad attrs: (town: "Metro City") children: ((tag: member attrs: (age: 53) children: (Sandman)) (tag: member attrs: (age: 29) children: ("Molecule Man")))"#
+/// );
+///
+/// let options = XmlOptions { style: XmlStyle::Compact };
+/// let item = from_xml(xml, &options).unwrap();
+/// assert_eq!(
+///   canonical(&item),
+///   br#"squad: (@town: "Metro City" member: (@age: 53 $: Sandman) member: (@age: 29 $: "Molecule Man"))"#
+/// );
+/// ```
+pub fn from_xml(input: &[u8], options: &XmlOptions) -> Result<Item, XmlError> {
+  read_xml(input, options)
+}
+
+/// Read an XML document as an item, mapping elements as in
+/// [`XmlOptions::style`].
+///
+/// The document is read event by event, only the item is kept in memory.
+/// Text which is only white space is dropped, other text is kept as it is,
+/// CDATA sections are text. The declaration, comments, processing
+/// instructions and the doctype are dropped.
+pub fn read_xml<R: BufRead>(
+  reader: R,
+  options: &XmlOptions,
+) -> Result<Item, XmlError> {
+  let mut reader = Reader::from_reader(reader);
+  let read_error = |at: u64, reason: &dyn fmt::Display| {
+    error(&Path::root(), format!("at byte {at}: {reason}"))
+  };
+
+  let (mut buf, mut stack, mut root) = (Vec::new(), Vec::new(), None);
+  loop {
+    let event = reader.read_event_into(&mut buf);
+    let event =
+      event.map_err(|err| read_error(reader.error_position(), &err))?;
+    let at = reader.buffer_position();
+    let element = match event {
+      Event::Start(start) => {
+        stack.push(Element::new(&start).map_err(|r| read_error(at, &r))?);
+        None
+      }
+      Event::Empty(start) => {
+        Some(Element::new(&start).map_err(|r| read_error(at, &r))?)
+      }
+      Event::End(_) => stack.pop(),
+      Event::Text(text) => {
+        let text = text.unescape().map_err(|err| read_error(at, &err))?;
+        if !is_space(&text) {
+          let Some(element) = stack.last_mut() else {
+            return Err(read_error(
+              reader.buffer_position(),
+              &"text outside the root element",
+            ));
+          };
+          element.text(&text);
+        }
+        None
+      }
+      Event::CData(data) => {
+        let data = data.into_inner();
+        let Ok(text) = std::str::from_utf8(&data) else {
+          return Err(read_error(
+            reader.buffer_position(),
+            &"CDATA is not UTF-8",
+          ));
+        };
+        let Some(element) = stack.last_mut() else {
+          return Err(read_error(
+            reader.buffer_position(),
+            &"text outside the root element",
+          ));
+        };
+        element.text(text);
+        None
+      }
+      Event::Eof => break,
+      _ => None,
+    };
+
+    if let Some(element) = element {
+      let (name, item) = element.into_item(options.style);
+      match (stack.last_mut(), &root) {
+        (Some(parent), _) => parent.children.push(Child::Element(name, item)),
+        (None, None) => root = Some((name, item)),
+        (None, Some(_)) => {
+          return Err(read_error(
+            reader.buffer_position(),
+            &"a second root element",
+          ));
+        }
+      }
+    }
+    buf.clear();
+  }
+
+  if let Some(element) = stack.last() {
+    let reason = format!("unclosed element {}", element.name);
+    return Err(error(&Path::root(), reason));
+  }
+  match (root, options.style) {
+    (Some((_, item)), XmlStyle::Elements) => Ok(item),
+    (Some((name, item)), XmlStyle::Compact) => {
+      Ok(Item::new_map([(Item::new_atom(name.as_bytes()), item)]))
+    }
+    (None, _) => Err(error(&Path::root(), "no root element")),
+  }
+}
+
+// XML white space, not Unicode
+fn is_space(text: &str) -> bool {
+  text.bytes().all(|b| matches!(b, b' ' | b'\t' | b'\r' | b'\n'))
+}
+
+struct Element {
+  name: String,
+  attrs: Vec<(String, String)>,
+  children: Vec<Child>,
+}
+
+enum Child {
+  Element(String, Item),
+  Text(String),
+}
+
+impl Element {
+  fn new(start: &BytesStart) -> Result<Self, String> {
+    let name = std::str::from_utf8(start.name().as_ref())
+      .map_err(|_| "name is not UTF-8".to_string())?
+      .to_string();
+    let mut attrs = Vec::new();
+    for attr in start.attributes() {
+      let attr = attr.map_err(|err| err.to_string())?;
+      let key = std::str::from_utf8(attr.key.as_ref())
+        .map_err(|_| "name is not UTF-8".to_string())?;
+      let value = attr.unescape_value().map_err(|err| err.to_string())?;
+      attrs.push((key.to_string(), value.into_owned()));
+    }
+    Ok(Element { name, attrs, children: Vec::new() })
+  }
+
+  // Adjacent text, like text and CDATA, is one text
+  fn text(&mut self, text: &str) {
+    match self.children.last_mut() {
+      Some(Child::Text(last)) => last.push_str(text),
+      _ => self.children.push(Child::Text(text.to_string())),
+    }
+  }
+
+  fn into_item(self, style: XmlStyle) -> (String, Item) {
+    let atom = |text: &str| Item::new_atom(text.as_bytes());
+    let attrs = self.attrs.iter();
+    let item = match style {
+      XmlStyle::Elements => {
+        let mut entries = vec![(Item::new_atom(TAG), atom(&self.name))];
+        if !self.attrs.is_empty() {
+          let attrs = attrs.map(|(key, value)| (atom(key), atom(value)));
+          entries.push((Item::new_atom(ATTRS), Item::new_map(attrs)));
+        }
+        if !self.children.is_empty() {
+          let children = self.children.into_iter().map(|child| match child {
+            Child::Element(_, item) => item,
+            Child::Text(text) => atom(&text),
+          });
+          entries.push((Item::new_atom(CHILDREN), Item::new_list(children)));
+        }
+        Item::new_map(entries)
+      }
+      XmlStyle::Compact => match self.children.as_slice() {
+        [] if self.attrs.is_empty() => Item::nil(),
+        [Child::Text(text)] if self.attrs.is_empty() => atom(text),
+        _ => {
+          let attrs =
+            attrs.map(|(key, value)| (atom(&format!("@{key}")), atom(value)));
+          let children = self.children.into_iter().map(|child| match child {
+            Child::Element(name, item) => (atom(&name), item),
+            Child::Text(text) => (Item::new_atom(TEXT), atom(&text)),
+          });
+          Item::new_map(attrs.collect::<Vec<_>>().into_iter().chain(children))
+        }
+      },
+    };
+    (self.name, item)
+  }
+}
+
+/// Convert an item to an XML document, see [`write_xml()`].
+///
+/// ```
+/// # use axp::{parse, to_xml, XmlOptions, XmlStyle};
+/// let item = parse(br#"
+///   squad: (@town: "Metro & City" member: Sandman member: (@age: 29))
+/// "#).unwrap();
+/// let options = XmlOptions { style: XmlStyle::Compact };
+/// assert_eq!(
+///   String::from_utf8(to_xml(&item, &options).unwrap()).unwrap(),
+///   "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+///    <squad town=\"Metro &amp; City\"><member>Sandman</member><member age=\"29\"/></squad>\n"
+/// );
+/// ```
+pub fn to_xml(item: &Item, options: &XmlOptions) -> Result<Vec<u8>, XmlError> {
+  let mut out = Vec::new();
+  write_xml(&mut out, item, options)?;
+  Ok(out)
+}
+
+/// Write an item as an XML document, mapping elements as in
+/// [`XmlOptions::style`].
+///
+/// The document is written element by element without indentation, which
+/// would be text. `<`, `>` and `&` are escaped, in attributes also `"` and
+/// line breaks, and carriage returns in text. Atoms are UTF-8 text: invalid
+/// UTF-8 and the characters which XML does not allow, control characters
+/// other than tab and line breaks, are replaced by U+FFFD. Text which is
+/// only white space is written as CDATA so that it is read back. Tagged
+/// atoms, empty text, text next to text and empty `attrs` or `children`,
+/// which would not be read back as they are, duplicate attributes, names
+/// which are not XML names and items which don't fit the style are errors.
+pub fn write_xml<W: Write>(
+  writer: W,
+  item: &Item,
+  options: &XmlOptions,
+) -> Result<(), XmlError> {
+  let mut writer = Writer(writer);
+  let root = Path::root();
+  writer.put(&root, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")?;
+  match (options.style, item) {
+    (XmlStyle::Elements, item) => writer.element(&root, item)?,
+    (XmlStyle::Compact, Item::Map(map)) if map.len() == 1 => {
+      let (name, content) = map.iter().next().expect("one entry");
+      let path = root.join(name.clone());
+      writer.content(&path, name, content)?;
+    }
+    (XmlStyle::Compact, _) => {
+      let reason = "a document must be a map with one root element";
+      return Err(error(&root, reason));
+    }
+  }
+  writer.put(&root, "\n")
+}
+
+struct Writer<W>(W);
+
+impl<W: Write> Writer<W> {
+  fn put(&mut self, path: &Path, text: &str) -> Result<(), XmlError> {
+    self
+      .0
+      .write_all(text.as_bytes())
+      .map_err(|err| error(path, err.to_string()))
+  }
+
+  // The name without the prefix, `@` for attributes in the compact style
+  fn name(
+    &self,
+    path: &Path,
+    item: &Item,
+    prefix: &str,
+  ) -> Result<String, XmlError> {
+    let text = match item {
+      Item::Atom(atom) => String::from_utf8_lossy(untagged(path, atom)?),
+      _ => return Err(error(path, "name must be an atom")),
+    };
+    let name = text.strip_prefix(prefix).unwrap_or(&text);
+    let start = |c: char| c.is_alphabetic() || c == '_' || c == ':';
+    let rest = |c: char| start(c) || c.is_numeric() || c == '-' || c == '.';
+    let mut chars = name.chars();
+    if chars.next().is_some_and(start) && chars.all(rest) {
+      Ok(name.to_string())
+    } else {
+      Err(error(path, format!("{text} is not an XML name")))
+    }
+  }
+
+  fn start<'i>(
+    &mut self,
+    path: &Path,
+    name: &str,
+    prefix: &str,
+    attrs: impl Iterator<Item = (&'i Item, &'i Item)>,
+  ) -> Result<(), XmlError> {
+    self.put(path, &format!("<{name}"))?;
+    let mut seen = HashSet::new();
+    for (key, value) in attrs {
+      let path = path.join(key.clone());
+      let key = self.name(&path, key, prefix)?;
+      if !seen.insert(key.clone()) {
+        return Err(error(&path, format!("duplicate attribute {key}")));
+      }
+      let Item::Atom(value) = value else {
+        return Err(error(&path, "attribute must be an atom"));
+      };
+      let value = escape(untagged(&path, value)?, true);
+      self.put(&path, &format!(" {key}=\"{value}\""))?;
+    }
+    Ok(())
+  }
+
+  // Text is read back as it is unless it is empty or next to other text
+  fn text(
+    &mut self,
+    path: &Path,
+    atom: &Atom,
+    after_text: bool,
+  ) -> Result<(), XmlError> {
+    let text = untagged(path, atom)?;
+    if text.is_empty() {
+      return Err(error(path, "text must not be empty"));
+    }
+    if after_text {
+      return Err(error(path, "text must not follow text"));
+    }
+    let raw = String::from_utf8_lossy(text);
+    if is_space(&raw) {
+      self.put(path, &format!("<![CDATA[{raw}]]>"))
+    } else {
+      self.put(path, &escape(text, false))
+    }
+  }
+
+  fn element(&mut self, path: &Path, item: &Item) -> Result<(), XmlError> {
+    let Item::Map(map) = item else {
+      return Err(error(path, "element must be a map"));
+    };
+    let (mut name, mut attrs, mut children) = (None, None, None);
+    for (key, value) in map.iter() {
+      let path = path.join(key.clone());
+      match key {
+        Item::Atom(key) if key.as_bytes() == TAG => {
+          name = Some(self.name(&path, value, "")?)
+        }
+        Item::Atom(key) if key.as_bytes() == ATTRS => attrs = Some(value),
+        Item::Atom(key) if key.as_bytes() == CHILDREN => children = Some(value),
+        _ => return Err(error(&path, "unknown key")),
+      }
+    }
+    let Some(name) = name else {
+      return Err(error(path, "element must have a tag"));
+    };
+
+    let attrs_path = path.join(Item::new_atom(ATTRS));
+    match attrs {
+      Some(Item::Map(map)) if !map.is_empty() => {
+        self.start(&attrs_path, &name, "", map.iter().map(|(k, v)| (k, v)))?
+      }
+      Some(attrs) if !attrs.is_atom() && attrs.is_empty() => {
+        return Err(error(&attrs_path, "attrs must not be empty"));
+      }
+      Some(_) => return Err(error(&attrs_path, "attrs must be a map")),
+      None => self.start(path, &name, "", std::iter::empty())?,
+    }
+
+    let children_path = path.join(Item::new_atom(CHILDREN));
+    let children = match children {
+      Some(Item::List(list)) if !list.is_empty() => {
+        list.iter().collect::<Vec<_>>()
+      }
+      Some(children) if !children.is_atom() && children.is_empty() => {
+        return Err(error(&children_path, "children must not be empty"));
+      }
+      Some(_) => {
+        return Err(error(&children_path, "children must be a list"));
+      }
+      None => Vec::new(),
+    };
+    if children.is_empty() {
+      return self.put(path, "/>");
+    }
+    self.put(path, ">")?;
+    let mut after_text = false;
+    for (i, child) in children.into_iter().enumerate() {
+      let path = children_path.join_index(i);
+      match child {
+        Item::Atom(atom) => self.text(&path, atom, after_text)?,
+        child => self.element(&path, child)?,
+      }
+      after_text = child.is_atom();
+    }
+    self.put(path, &format!("</{name}>"))
+  }
+
+  fn content(
+    &mut self,
+    path: &Path,
+    name: &Item,
+    content: &Item,
+  ) -> Result<(), XmlError> {
+    let name = self.name(path, name, "")?;
+    match content {
+      Item::Atom(atom) => {
+        self.start(path, &name, "", std::iter::empty())?;
+        self.put(path, ">")?;
+        self.text(path, atom, false)?;
+        self.put(path, &format!("</{name}>"))
+      }
+      content if content.is_empty() => {
+        self.start(path, &name, "", std::iter::empty())?;
+        self.put(path, "/>")
+      }
+      Item::Map(map) => {
+        let is_attr = |key: &Item| match key {
+          Item::Atom(key) => key.as_bytes().starts_with(b"@"),
+          _ => false,
+        };
+        let attrs = map.iter().filter(|(key, _)| is_attr(key));
+        self.start(path, &name, "@", attrs.map(|(k, v)| (k, v)))?;
+        let mut children =
+          map.iter().filter(|(key, _)| !is_attr(key)).peekable();
+        if children.peek().is_none() {
+          return self.put(path, "/>");
+        }
+        self.put(path, ">")?;
+        let mut after_text = false;
+        for (key, value) in children {
+          let path = path.join(key.clone());
+          let is_text = match key {
+            Item::Atom(key) => key.as_bytes() == TEXT,
+            _ => false,
+          };
+          match (is_text, value) {
+            (true, Item::Atom(text)) => self.text(&path, text, after_text)?,
+            (true, _) => return Err(error(&path, "text must be an atom")),
+            (false, value) => self.content(&path, key, value)?,
+          }
+          after_text = is_text;
+        }
+        self.put(path, &format!("</{name}>"))
+      }
+      _ => Err(error(path, "content must be an atom or a map")),
+    }
+  }
+}
+
+fn untagged<'a>(path: &Path, atom: &'a Atom) -> Result<&'a [u8], XmlError> {
+  match atom.tag() {
+    Some(_) => Err(error(path, "tagged atoms have no XML form")),
+    None => Ok(atom.as_bytes()),
+  }
+}
+
+fn escape(bytes: &[u8], attr: bool) -> String {
+  let text = String::from_utf8_lossy(bytes);
+  let mut out = String::with_capacity(text.len());
+  for char in text.chars() {
+    match char {
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '&' => out.push_str("&amp;"),
+      '"' if attr => out.push_str("&quot;"),
+      '\t' if attr => out.push_str("&#9;"),
+      '\n' if attr => out.push_str("&#10;"),
+      '\r' => out.push_str("&#13;"),
+      '\t' | '\n' | ' '..='\u{d7ff}' | '\u{e000}'..='\u{fffd}' => {
+        out.push(char)
+      }
+      '\u{10000}'.. => out.push(char),
+      _ => out.push('\u{fffd}'),
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_xml, read_xml, to_xml, XmlOptions, XmlStyle};
+  use crate::{canonical, parse, Item};
+
+  const COMPACT: XmlOptions = XmlOptions { style: XmlStyle::Compact };
+
+  fn import(input: &str, options: &XmlOptions) -> Result<String, String> {
+    let item = from_xml(input.as_bytes(), options);
+    let item = item.map_err(|err| err.to_string())?;
+    Ok(String::from_utf8(canonical(&item)).unwrap())
+  }
+
+  fn export(input: &str, options: &XmlOptions) -> Result<String, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    let xml = to_xml(&item, options).map_err(|err| err.to_string())?;
+    let xml = String::from_utf8(xml).unwrap();
+    Ok(xml.trim_end().lines().nth(1).unwrap_or("").to_string())
+  }
+
+  #[test]
+  fn imports() {
+    let xml = r#"<!DOCTYPE a><!-- c --><a x='1 &amp; 2'>
+      <b/> t&lt;u <![CDATA[<v>]]><?pi?>
+      <c><d>e</d><![CDATA[ ]]></c>
+    </a>"#;
+    assert_eq!(
+      import(xml, &XmlOptions::default()).unwrap(),
+      r#"tag: a attrs: (x: "1 & 2") children: ((tag: b) " t<u <v>" (tag: c children: ((tag: d children: (e)) " ")))"#
+    );
+    assert_eq!(
+      import(xml, &COMPACT).unwrap(),
+      r#"a: (@x: "1 & 2" b: () $: " t<u <v>" c: (d: e $: " "))"#
+    );
+    assert_eq!(import("<a>x&#13;\ny</a>", &COMPACT).unwrap(), r#"a: "x\r\ny""#);
+  }
+
+  #[test]
+  fn exports() {
+    let item = r#"tag: a attrs: (x: "\"<1\n2>\"") children: ((tag: b) "t&u" (tag: c) " ")"#;
+    assert_eq!(
+      export(item, &XmlOptions::default()).unwrap(),
+      "<a x=\"&quot;&lt;1&#10;2&gt;&quot;\"><b/>t&amp;u<c/><![CDATA[ ]]></a>"
+    );
+    assert_eq!(
+      export(r#"a: (c: () d: (@e: 1) $: "x\ry" f: "\x01\xff")"#, &COMPACT)
+        .unwrap(),
+      "<a><c/><d e=\"1\"/>x&#13;y<f>\u{fffd}\u{fffd}</f></a>"
+    );
+
+    for (input, options) in [
+      (
+        r#"tag: a attrs: (x: "1 & 2") children: ((tag: b) " t<u" (tag: c children: ("\r\t\n")))"#,
+        XmlOptions::default(),
+      ),
+      (r#"a: (@x: "1 & 2" b: () $: " t<u" c: (d: e $: " ") c: "é")"#, COMPACT),
+      // text between elements, the attribute can be empty
+      (r#"a: (@x: "" $: x b: () $: y)"#, COMPACT),
+    ] {
+      let item = parse(input.as_bytes()).unwrap();
+      let xml = to_xml(&item, &options).unwrap();
+      assert_eq!(from_xml(&xml, &options), Ok(item));
+    }
+  }
+
+  #[test]
+  fn errors() {
+    let options = XmlOptions::default();
+    let import = |input| import(input, &options).unwrap_err();
+    assert_eq!(
+      import("<a></b>"),
+      "XmlError: at byte 3: ill-formed document: expected `</a>`, but `</b>` was found"
+    );
+    assert_eq!(import("<a>"), "XmlError: unclosed element a");
+    assert_eq!(
+      import("<a/><b/>"),
+      "XmlError: at byte 8: a second root element"
+    );
+    assert_eq!(
+      import("x<a/>"),
+      "XmlError: at byte 1: text outside the root element"
+    );
+    assert_eq!(import("<!-- c -->"), "XmlError: no root element");
+
+    let export = |input, options| export(input, options).unwrap_err();
+    assert_eq!(
+      export("tag: \"1a\"", &options),
+      "XmlError: tag: 1a is not an XML name"
+    );
+    assert_eq!(export("tag: a x: 1", &options), "XmlError: x: unknown key");
+    assert_eq!(
+      export("attrs: (x: 1)", &options),
+      "XmlError: element must have a tag"
+    );
+    assert_eq!(
+      export("tag: a attrs: (x: (1))", &options),
+      "XmlError: attrs/x: attribute must be an atom"
+    );
+    assert_eq!(
+      export("tag: a children: (())", &options),
+      "XmlError: children/0: element must be a map"
+    );
+    assert_eq!(
+      export("tag: a children: x", &options),
+      "XmlError: children: children must be a list"
+    );
+    assert_eq!(
+      export("a: (b c)", &COMPACT),
+      "XmlError: a: content must be an atom or a map"
+    );
+    assert_eq!(
+      export("a: ($: (b))", &COMPACT),
+      "XmlError: a/$: text must be an atom"
+    );
+    assert_eq!(
+      export("a: 1 b: 2", &COMPACT),
+      "XmlError: a document must be a map with one root element"
+    );
+
+    // what would not be read back as it is
+    assert_eq!(
+      export("tag: a children: (x y)", &options),
+      "XmlError: children/1: text must not follow text"
+    );
+    assert_eq!(
+      export(r#"tag: a children: ("")"#, &options),
+      "XmlError: children/0: text must not be empty"
+    );
+    assert_eq!(
+      export(r##"tag: a attrs: (x: #(t)"1#(t)")"##, &options),
+      "XmlError: attrs/x: tagged atoms have no XML form"
+    );
+    assert_eq!(
+      export("tag: a attrs: (x: 1 x: 2)", &options),
+      "XmlError: attrs/x: duplicate attribute x"
+    );
+    assert_eq!(
+      export("tag: a attrs: ()", &options),
+      "XmlError: attrs: attrs must not be empty"
+    );
+    assert_eq!(
+      export("tag: a children: ()", &options),
+      "XmlError: children: children must not be empty"
+    );
+    assert_eq!(
+      export(r#"a: """#, &COMPACT),
+      "XmlError: a: text must not be empty"
+    );
+    assert_eq!(
+      export("a: ($: x b: () $: y $: z)", &COMPACT),
+      "XmlError: a/$: text must not follow text"
+    );
+    assert_eq!(
+      export("a: (@x: 1 @x: 2)", &COMPACT),
+      "XmlError: a/@x: duplicate attribute x"
+    );
+    assert_eq!(
+      export(r##"a: (b: #(t)"x#(t)")"##, &COMPACT),
+      "XmlError: a/b: tagged atoms have no XML form"
+    );
+  }
+
+  #[test]
+  fn stream() {
+    let mut xml = b"<list>".to_vec();
+    for i in 0..10_000 {
+      xml.extend_from_slice(format!("<item n=\"{i}\">{i}</item>").as_bytes());
+    }
+    xml.extend_from_slice(b"</list>");
+    let reader = std::io::BufReader::with_capacity(64, &xml[..]);
+    let item = read_xml(reader, &COMPACT).unwrap();
+    let Some(Item::Map(list)) = item.get(&"list".parse().unwrap()) else {
+      panic!("{item}")
+    };
+    assert_eq!(list.len(), 10_000);
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+
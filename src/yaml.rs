@@ -0,0 +1,291 @@
+use std::error::Error;
+use std::fmt;
+
+use yaml_rust2::yaml::Hash;
+use yaml_rust2::{Yaml, YamlEmitter, YamlLoader};
+
+use crate::atom::float_text;
+use crate::{Atom, Item, Path};
+
+const MERGE: &str = "<<";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct YamlError {
+  path: Path,
+  reason: String,
+}
+
+impl Error for YamlError {}
+
+impl YamlError {
+  /// The path in the document, the root for parse errors
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reason(&self) -> &str {
+    &self.reason
+  }
+}
+
+impl fmt::Display for YamlError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.path.is_root() {
+      write!(f, "YamlError: {}", self.reason)
+    } else {
+      write!(f, "YamlError: {}: {}", self.path, self.reason)
+    }
+  }
+}
+
+fn error(path: &Path, reason: impl Into<String>) -> YamlError {
+  YamlError { path: path.clone(), reason: reason.into() }
+}
+
+/// Convert a YAML stream to items, one per document.
+///
+/// Mappings become maps, also with complex keys, and sequences become
+/// lists. Scalars become atoms of their text, null is `null`. Aliases are
+/// replaced by their anchored node and merge keys `<<` are merged into the
+/// mapping, its own entries take precedence. Comments are dropped, items
+/// have none.
+///
+/// ```
+/// # use axp::{from_yaml, parse};
+/// let documents = from_yaml("
+/// base: &base {image: rust, os: linux}
+/// job:
+///   <<: *base
+///   os: macos
+/// ---
+/// [1, ~]
+/// ").unwrap();
+/// let expected = [
+///   parse(b"base: (image: rust os: linux) job: (os: macos image: rust)"),
+///   parse(b"1 null"),
+/// ];
+/// assert_eq!(documents, expected.map(Result::unwrap));
+/// ```
+pub fn from_yaml(input: &str) -> Result<Vec<Item>, YamlError> {
+  let documents = YamlLoader::load_from_str(input);
+  let documents =
+    documents.map_err(|err| error(&Path::root(), err.to_string()))?;
+  documents.iter().map(|yaml| from_node(&Path::root(), yaml)).collect()
+}
+
+fn from_node(path: &Path, yaml: &Yaml) -> Result<Item, YamlError> {
+  let atom = |text: &str| Ok(Item::new_atom(text.as_bytes()));
+  match yaml {
+    Yaml::Real(real) => atom(real),
+    Yaml::Integer(integer) => atom(&integer.to_string()),
+    Yaml::String(string) => atom(string),
+    Yaml::Boolean(bool) => atom(&bool.to_string()),
+    Yaml::Null => atom("null"),
+    Yaml::Array(array) => {
+      let items = array.iter().enumerate();
+      let items = items.map(|(i, yaml)| from_node(&path.join_index(i), yaml));
+      items.collect::<Result<Vec<_>, _>>().map(Item::new_list)
+    }
+    Yaml::Hash(hash) => from_hash(path, hash),
+    Yaml::Alias(_) | Yaml::BadValue => Err(error(path, "unknown alias")),
+  }
+}
+
+fn from_hash(path: &Path, hash: &Hash) -> Result<Item, YamlError> {
+  let mut entries = Vec::new();
+  let mut merged = Vec::new();
+  for (key, value) in hash {
+    let key = from_node(path, key)?;
+    let path = path.join(key.clone());
+    if key == Item::new_atom(MERGE.as_bytes()) {
+      let maps = match value {
+        Yaml::Array(maps) => maps.iter().collect(),
+        map => vec![map],
+      };
+      for map in maps {
+        match from_node(&path, map)? {
+          Item::Map(map) => merged.extend(map.0),
+          _ => return Err(error(&path, "merge value must be a mapping")),
+        }
+      }
+    } else {
+      entries.push((key, from_node(&path, value)?));
+    }
+  }
+
+  // the first of the merged mappings takes precedence
+  for (key, value) in merged {
+    if !entries.iter().any(|(k, _)| *k == key) {
+      entries.push((key, value));
+    }
+  }
+  Ok(Item::new_map(entries))
+}
+
+/// Write items as a YAML stream, one document each.
+///
+/// Maps become mappings and lists become sequences. Atoms which are written
+/// back the same become integers, floats, booleans and null, the rest
+/// become strings. Atoms tagged `datetime` become strings, YAML timestamps
+/// are strings too. Other tags, atoms which are not UTF-8 and duplicate keys
+/// are errors.
+///
+/// ```
+/// # use axp::{parse, to_yaml};
+/// let item = parse(b"name: axp ports: (80 443) note: \"true\"").unwrap();
+/// assert_eq!(
+///   to_yaml(&[item]).unwrap(),
+///   "---\nname: axp\nports:\n  - 80\n  - 443\nnote: true\n"
+/// );
+/// ```
+pub fn to_yaml(documents: &[Item]) -> Result<String, YamlError> {
+  let mut out = String::new();
+  for (i, item) in documents.iter().enumerate() {
+    let path = match documents.len() {
+      1 => Path::root(),
+      _ => Path::root().join_index(i),
+    };
+    let yaml = to_node(&path, item)?;
+    let mut emitter = YamlEmitter::new(&mut out);
+    emitter.dump(&yaml).map_err(|err| error(&path, format!("{err:?}")))?;
+    out.push('\n');
+  }
+  Ok(out)
+}
+
+fn to_node(path: &Path, item: &Item) -> Result<Yaml, YamlError> {
+  match item {
+    Item::Atom(atom) => to_scalar(path, atom),
+    Item::List(list) => {
+      let items = list.iter().enumerate();
+      let items = items.map(|(i, item)| to_node(&path.join_index(i), item));
+      items.collect::<Result<_, _>>().map(Yaml::Array)
+    }
+    Item::Map(map) => {
+      let mut hash = Hash::new();
+      for (key, value) in map.iter() {
+        let path = path.join(key.clone());
+        let node = to_node(&path, key)?;
+        if hash.insert(node, to_node(&path, value)?).is_some() {
+          return Err(error(&path, format!("duplicate key {key}")));
+        }
+      }
+      Ok(Yaml::Hash(hash))
+    }
+  }
+}
+
+fn to_scalar(path: &Path, atom: &Atom) -> Result<Yaml, YamlError> {
+  let Ok(text) = std::str::from_utf8(atom.as_bytes()) else {
+    return Err(error(path, "atom is not UTF-8"));
+  };
+  match atom.tag() {
+    Some(b"datetime") => return Ok(Yaml::String(text.to_string())),
+    Some(tag) => {
+      let tag = String::from_utf8_lossy(tag);
+      return Err(error(path, format!("tag {tag} has no YAML type")));
+    }
+    None => (),
+  }
+
+  Ok(match Yaml::from_str(text) {
+    Yaml::Integer(integer) if integer.to_string() == text => {
+      Yaml::Integer(integer)
+    }
+    Yaml::Boolean(bool) if bool.to_string() == text => Yaml::Boolean(bool),
+    Yaml::Null if text == "null" => Yaml::Null,
+    Yaml::Real(real)
+      if real.parse().is_ok_and(|x: f64| float_text(x) == text) =>
+    {
+      Yaml::Real(real)
+    }
+    _ => Yaml::String(text.to_string()),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_yaml, to_yaml};
+  use crate::{canonical, parse, Atom, Item};
+
+  fn import(input: &str) -> Result<String, String> {
+    let documents = from_yaml(input).map_err(|err| err.to_string())?;
+    let documents = documents.iter().map(canonical);
+    let documents = documents.map(|bytes| String::from_utf8(bytes).unwrap());
+    Ok(documents.collect::<Vec<_>>().join(" --- "))
+  }
+
+  fn export(input: &str) -> Result<String, String> {
+    let item = parse(input.as_bytes()).unwrap();
+    to_yaml(&[item]).map_err(|err| err.to_string())
+  }
+
+  #[test]
+  fn imports() {
+    assert_eq!(
+      import(
+        "# comment
+        a: 1
+        b: [.inf, 0x10, 'x y', ~, True]
+        ? [k, l]
+        : {c: {}}"
+      )
+      .unwrap(),
+      r#"a: 1 b: (.inf 16 "x y" null true) (k l): (c: ())"#
+    );
+    assert_eq!(
+      import(
+        "
+        a: &a {x: 1, y: 1}
+        b: &b {y: 2, z: 2}
+        c: {<<: [*b, *a], z: 3}
+        d: *a"
+      )
+      .unwrap(),
+      "a: (x: 1 y: 1) b: (y: 2 z: 2) c: (z: 3 y: 2 x: 1) d: (x: 1 y: 1)"
+    );
+    assert_eq!(import("--- 1\n--- 2\n").unwrap(), "1 --- 2");
+    assert_eq!(import("").unwrap(), "");
+  }
+
+  #[test]
+  fn exports() {
+    assert_eq!(
+      export(r#"a: (1 1.5 1e3 01 0x10 "" null True) (k l): (c: ())"#).unwrap(),
+      "---\na:\n  - 1\n  - 1.5\n  - \"1e3\"\n  - \"01\"\n  - \"0x10\"\n  - \"\"\
+       \n  - ~\n  - \"True\"\n? - k\n  - l\n: c: []\n"
+    );
+    let documents = [parse(b"a: 1").unwrap(), parse(b"b").unwrap()];
+    assert_eq!(to_yaml(&documents).unwrap(), "---\na: 1\n---\n- b\n");
+
+    let roundtrip = r#"a: (1 "x y" "") (k l): (c: (d e)) b: (1.5 true)"#;
+    let item = parse(roundtrip.as_bytes()).unwrap();
+    let yaml = to_yaml(std::slice::from_ref(&item)).unwrap();
+    assert_eq!(from_yaml(&yaml), Ok(vec![item]));
+  }
+
+  #[test]
+  fn errors() {
+    assert_eq!(
+      export(r##"a: (#(date)"x#(date)")"##).unwrap_err(),
+      "YamlError: a/0: tag date has no YAML type"
+    );
+    let item = Item::new_list([Item::new_atom(b"\xff")]);
+    let err = to_yaml(&[Item::nil(), item]).unwrap_err();
+    assert_eq!(err.to_string(), "YamlError: 1/0: atom is not UTF-8");
+    assert_eq!(
+      export("a: (k: 1 l: 2 k: 3)").unwrap_err(),
+      "YamlError: a/k: duplicate key k"
+    );
+    let tagged = Item::Atom(Atom::tagged(b"datetime", b"2001-12-14").unwrap());
+    assert_eq!(to_yaml(&[tagged]).unwrap(), "---\n2001-12-14\n");
+
+    assert_eq!(
+      import("a: {<<: 1}").unwrap_err(),
+      "YamlError: a/<<: merge value must be a mapping"
+    );
+    assert!(import("a: [").unwrap_err().starts_with("YamlError: "));
+  }
+}
+
+// Copyright see AUTHORS & LICENSE; SPDX-License-Identifier: ISC+